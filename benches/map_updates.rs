@@ -0,0 +1,72 @@
+// inserts into and removes from maps of growing size
+// run with `cargo bench --bench map_updates`
+
+use std::collections::{BTreeMap, HashMap};
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use lamp_lang::map::Map;
+
+// keys in a scattered order so inserts land all over the index
+fn keys(size: usize) -> Vec<u64> {
+    (0..size as u64)
+        .map(|i| i.wrapping_mul(0x9e37_79b9_7f4a_7c15))
+        .collect()
+}
+
+fn time(name: &str, iterations: u32, mut f: impl FnMut()) -> Duration {
+    let start = Instant::now();
+    for _ in 0..iterations {
+        f();
+    }
+    let each = start.elapsed() / iterations;
+    println!("{:<40} {:>12?}", name, each);
+    each
+}
+
+fn main() {
+    for size in [10, 100, 1000, 10000] {
+        let keys = keys(size);
+        let iterations = (100_000 / size as u32).max(5);
+        println!("{} entries", size);
+
+        time("  insert all into Map", iterations, || {
+            let mut map = Map::new();
+            for &key in &keys {
+                map.insert(key, key);
+            }
+            black_box(map);
+        });
+        time("  insert all into BTreeMap", iterations, || {
+            let mut map = BTreeMap::new();
+            for &key in &keys {
+                map.insert(key, key);
+            }
+            black_box(map);
+        });
+        time("  insert all into HashMap", iterations, || {
+            let mut map = HashMap::new();
+            for &key in &keys {
+                map.insert(key, key);
+            }
+            black_box(map);
+        });
+
+        let full: Map<u64, u64> = keys.iter().map(|&key| (key, key)).collect();
+        time("  remove all from Map", iterations, || {
+            let mut map = full.clone();
+            for key in &keys {
+                map.remove(key);
+            }
+            black_box(map);
+        });
+        let full: BTreeMap<u64, u64> = keys.iter().map(|&key| (key, key)).collect();
+        time("  remove all from BTreeMap", iterations, || {
+            let mut map = full.clone();
+            for key in &keys {
+                map.remove(key);
+            }
+            black_box(map);
+        });
+    }
+}
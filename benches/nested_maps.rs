@@ -0,0 +1,86 @@
+// hashes and compares deeply nested maps used as keys
+// run with `cargo bench --bench nested_maps`
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use lamp_lang::code::Code;
+use lamp_lang::map::Map;
+
+// a map whose keys are maps, nested to the depth, each level with the width of entries
+fn nested(depth: usize, width: usize, seed: i128) -> Code {
+    let mut map = Map::new();
+    for i in 0..width as i128 {
+        let key = if depth == 0 {
+            Code::Integer(seed * 1000 + i)
+        } else {
+            nested(depth - 1, width, seed * 10 + i)
+        };
+        map.insert(key, Code::Identifier(format!("v{}", i)));
+    }
+    Code::Map(map)
+}
+
+// how hashing worked before the hash was cached, sorting every map on the way down
+fn sorting_hash<H: Hasher>(code: &Code, state: &mut H) {
+    match code {
+        Code::Map(map) => {
+            let mut pairs: Vec<_> = map.iter().collect();
+            pairs.sort_by_key(|(k, _)| *k);
+            for (k, v) in pairs {
+                sorting_hash(k, state);
+                sorting_hash(v, state);
+            }
+        }
+        Code::List(list) => list.iter().for_each(|item| sorting_hash(item, state)),
+        _ => code.hash(state),
+    }
+}
+
+fn time(name: &str, iterations: u32, mut f: impl FnMut()) -> Duration {
+    let start = Instant::now();
+    for _ in 0..iterations {
+        f();
+    }
+    let each = start.elapsed() / iterations;
+    println!("{:<40} {:>12?}", name, each);
+    each
+}
+
+fn main() {
+    for depth in [2, 4, 6] {
+        let one = nested(depth, 4, 1);
+        let two = nested(depth, 4, 1);
+        let other = nested(depth, 4, 2);
+        println!("depth {} with 4 entries a level", depth);
+
+        let sorting = time("  hash by sorting entries", 200, || {
+            let mut hasher = DefaultHasher::new();
+            sorting_hash(black_box(&one), &mut hasher);
+            black_box(hasher.finish());
+        });
+        let cached = time("  cached hash", 200, || {
+            let mut hasher = DefaultHasher::new();
+            black_box(&one).hash(&mut hasher);
+            black_box(hasher.finish());
+        });
+        println!(
+            "  cached hash is {:.0}x faster",
+            sorting.as_secs_f64() / cached.as_secs_f64().max(1e-9)
+        );
+
+        time("  compare equal maps", 200, || {
+            black_box(black_box(&one).cmp(black_box(&two)));
+        });
+        time("  compare unequal maps", 200, || {
+            black_box(black_box(&one) == black_box(&other));
+        });
+        time("  look up a nested key", 200, || {
+            let mut map = Map::new();
+            map.insert(one.clone(), 1);
+            black_box(map.get(black_box(&two)));
+        });
+    }
+}
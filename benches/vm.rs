@@ -0,0 +1,65 @@
+// runs the same programs with the tree walker and the bytecode vm
+// run with `cargo bench --bench vm`
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use lamp_lang::code::Code;
+use lamp_lang::compile::compile;
+use lamp_lang::runtime::Runtime;
+
+const PROGRAMS: &[(&str, &str)] = &[
+    (
+        "recursive fib 20",
+        "[var fib {fn a: [n] c: {if c: [< n 2] do: n else: [+ [fib [- n 1]] [fib [- n 2]]]}}]
+         [fib 20]",
+    ),
+    (
+        "while loop to 100000",
+        "[pgm [var i 0] [var sum 0]
+          {while c: [< i 100000] do: [[set sum [+ sum i]] [set i [+ i 1]]]}
+          sum]",
+    ),
+    (
+        "closure counter 20000",
+        "[pgm
+          [var n 0]
+          [var next {fn c: [set n [+ n 1]]}]
+          {while c: [< n 20000] do: [next]}
+          n]",
+    ),
+];
+
+fn time(name: &str, iterations: u32, mut f: impl FnMut()) -> Duration {
+    let start = Instant::now();
+    for _ in 0..iterations {
+        f();
+    }
+    let each = start.elapsed() / iterations;
+    println!("{:<40} {:>12?}", name, each);
+    each
+}
+
+fn main() {
+    for (name, source) in PROGRAMS {
+        let code = Code::from_str(source).unwrap();
+        println!("{}", name);
+        let tree = time("  tree walker", 5, || {
+            let mut runtime = Runtime::new();
+            for expr in code.iter() {
+                black_box(runtime.interpret(expr).unwrap());
+            }
+        });
+        let programs: Vec<_> = code.iter().map(|expr| compile(expr).unwrap()).collect();
+        let vm = time("  bytecode vm", 5, || {
+            let mut runtime = Runtime::new();
+            for program in programs.iter() {
+                black_box(runtime.exec(program).unwrap());
+            }
+        });
+        println!(
+            "  vm is {:.1}x faster",
+            tree.as_secs_f64() / vm.as_secs_f64().max(1e-9)
+        );
+    }
+}
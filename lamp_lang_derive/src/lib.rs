@@ -0,0 +1,509 @@
+//! `#[derive(ToDatum, FromDatum)]` for lamp_lang.
+//!
+//! Structs use the same encoding as `String` in lamp_lang's datum.rs: a
+//! `LampType::Struct` of field types with a `Code::Map` from field identifiers
+//! to values. Tuple structs become a `LampType::List`. Enums use the encoding
+//! of `Option`: a `LampType::Enum` with values written as `[Variant payload]`.
+//!
+//! Fields can be renamed with `#[lamp(rename = "name")]` and left out with
+//! `#[lamp(skip)]`, skipped fields are filled in with `Default::default()`.
+//! Variants can be renamed the same way.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::{
+    parse_macro_input, parse_quote, Attribute, Data, DeriveInput, Error, Fields, Ident, LitStr,
+    Member, Type,
+};
+
+#[proc_macro_derive(ToDatum, attributes(lamp))]
+pub fn derive_to_datum(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    to_datum(input)
+        .unwrap_or_else(|err| err.to_compile_error())
+        .into()
+}
+
+#[proc_macro_derive(FromDatum, attributes(lamp))]
+pub fn derive_from_datum(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    from_datum(input)
+        .unwrap_or_else(|err| err.to_compile_error())
+        .into()
+}
+
+#[derive(Default)]
+struct Attrs {
+    rename: Option<String>,
+    skip: bool,
+}
+
+fn parse_attrs(attrs: &[Attribute]) -> syn::Result<Attrs> {
+    let mut out = Attrs::default();
+    for attr in attrs.iter().filter(|a| a.path().is_ident("lamp")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("rename") {
+                let name: LitStr = meta.value()?.parse()?;
+                out.rename = Some(name.value());
+                Ok(())
+            } else if meta.path.is_ident("skip") {
+                out.skip = true;
+                Ok(())
+            } else {
+                Err(meta.error("unknown lamp attribute, expected `rename` or `skip`"))
+            }
+        })?;
+    }
+    Ok(out)
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Named,
+    Unnamed,
+    Unit,
+}
+
+// what from_datum reads, from_code gets code and from_value gets values
+#[derive(Clone, Copy)]
+enum Source {
+    Code,
+    Value,
+}
+
+impl Source {
+    // the argument holding the whole input
+    fn input(self) -> TokenStream2 {
+        match self {
+            Source::Code => quote!(code),
+            Source::Value => quote!(value),
+        }
+    }
+
+    fn from(self) -> TokenStream2 {
+        match self {
+            Source::Code => quote!(from_code),
+            Source::Value => quote!(from_value),
+        }
+    }
+
+    fn wrong(self) -> TokenStream2 {
+        match self {
+            Source::Code => quote!(::lamp_lang::datum::wrong_shape::<Self>(code)),
+            Source::Value => quote!(::lamp_lang::datum::wrong_value::<Self>(value)),
+        }
+    }
+
+    fn map(self) -> TokenStream2 {
+        match self {
+            Source::Code => quote!(::lamp_lang::code::Code::Map),
+            Source::Value => quote!(::lamp_lang::value::Value::Map),
+        }
+    }
+
+    fn list(self) -> TokenStream2 {
+        match self {
+            Source::Code => quote!(::lamp_lang::code::Code::List),
+            Source::Value => quote!(::lamp_lang::value::Value::List),
+        }
+    }
+
+    // the key of a named field
+    fn key(self, name: &str) -> TokenStream2 {
+        match self {
+            Source::Code => quote! {
+                ::lamp_lang::code::Code::Identifier(::std::string::String::from(#name))
+            },
+            Source::Value => quote!(::lamp_lang::value::Value::symbol(#name)),
+        }
+    }
+}
+
+struct Field {
+    member: Member,
+    ty: Type,
+    // the name the field has in lamp
+    name: String,
+    // the binding used when matching on an enum variant
+    binding: Ident,
+    skip: bool,
+}
+
+struct Shape {
+    kind: Kind,
+    fields: Vec<Field>,
+}
+
+impl Shape {
+    fn new(fields: &Fields) -> syn::Result<Shape> {
+        let kind = match fields {
+            Fields::Named(_) => Kind::Named,
+            Fields::Unnamed(_) => Kind::Unnamed,
+            Fields::Unit => Kind::Unit,
+        };
+        let mut out = Vec::new();
+        for (i, field) in fields.iter().enumerate() {
+            let attrs = parse_attrs(&field.attrs)?;
+            let member = match &field.ident {
+                Some(ident) => Member::Named(ident.clone()),
+                None => Member::Unnamed(i.into()),
+            };
+            let name = match (&attrs.rename, &field.ident) {
+                (Some(rename), _) => rename.clone(),
+                (None, Some(ident)) => ident.to_string(),
+                (None, None) => i.to_string(),
+            };
+            out.push(Field {
+                member,
+                ty: field.ty.clone(),
+                name,
+                binding: format_ident!("__field{}", i),
+                skip: attrs.skip,
+            });
+        }
+        Ok(Shape { kind, fields: out })
+    }
+
+    fn kept(&self) -> impl Iterator<Item = &Field> {
+        self.fields.iter().filter(|f| !f.skip)
+    }
+
+    // a lone unskipped tuple field is stored directly as an enum payload
+    fn single(&self) -> Option<&Field> {
+        match (self.kind, self.fields.as_slice()) {
+            (Kind::Unnamed, [field]) if !field.skip => Some(field),
+            _ => None,
+        }
+    }
+
+    fn type_expr(&self) -> TokenStream2 {
+        let names = self.kept().map(|f| &f.name);
+        let types = self.kept().map(|f| &f.ty);
+        match self.kind {
+            Kind::Named | Kind::Unit => quote! {{
+                let mut fields = ::lamp_lang::map::Map::new();
+                #(
+                    fields.insert(
+                        ::std::string::String::from(#names),
+                        <#types as ::lamp_lang::datum::ToDatum>::to_lamp_type(),
+                    );
+                )*
+                ::lamp_lang::lamp_type::LampType::Struct(fields)
+            }},
+            Kind::Unnamed => quote! {
+                ::lamp_lang::lamp_type::LampType::List(::std::vec![
+                    #( <#types as ::lamp_lang::datum::ToDatum>::to_lamp_type() ),*
+                ])
+            },
+        }
+    }
+
+    // code for the fields, access gives the expression referencing a field
+    fn code_expr(&self, access: impl Fn(&Field) -> TokenStream2) -> TokenStream2 {
+        let names = self.kept().map(|f| &f.name);
+        let values = self.kept().map(&access);
+        match self.kind {
+            Kind::Named | Kind::Unit => quote! {{
+                let mut fields = ::lamp_lang::map::Map::new();
+                #(
+                    fields.insert(
+                        ::lamp_lang::code::Code::Identifier(::std::string::String::from(#names)),
+                        ::lamp_lang::datum::ToDatum::to_code(#values),
+                    );
+                )*
+                ::lamp_lang::code::Code::Map(fields)
+            }},
+            Kind::Unnamed => quote! {
+                ::lamp_lang::code::Code::List(::std::vec![
+                    #( ::lamp_lang::datum::ToDatum::to_code(#values) ),*
+                ])
+            },
+        }
+    }
+
+    // builds ctor from the source in payload, mismatches report the whole input
+    fn build_expr(
+        &self,
+        source: Source,
+        ctor: TokenStream2,
+        payload: TokenStream2,
+    ) -> TokenStream2 {
+        let from = source.from();
+        let wrong = source.wrong();
+        let count = self.kept().count();
+        let mut index = 0usize;
+        let members = self.fields.iter().map(|f| &f.member);
+        let values: Vec<TokenStream2> = self
+            .fields
+            .iter()
+            .map(|f| {
+                let ty = &f.ty;
+                if f.skip {
+                    return quote!(::std::default::Default::default());
+                }
+                let item = match self.kind {
+                    Kind::Named | Kind::Unit => {
+                        let key = source.key(&f.name);
+                        quote!(fields.get(&#key))
+                    }
+                    Kind::Unnamed => {
+                        index += 1;
+                        let i = index - 1;
+                        quote!(fields.get(#i))
+                    }
+                };
+                quote! {
+                    match #item {
+                        ::std::option::Option::Some(value) => {
+                            <#ty as ::lamp_lang::datum::FromDatum>::#from(value)?
+                        }
+                        ::std::option::Option::None => return ::std::result::Result::Err(#wrong),
+                    }
+                }
+            })
+            .collect();
+        let pattern = match self.kind {
+            Kind::Named | Kind::Unit => source.map(),
+            Kind::Unnamed => source.list(),
+        };
+        quote! {
+            match #payload {
+                #pattern(fields) if fields.len() == #count => {
+                    ::std::result::Result::Ok(#ctor { #( #members: #values ),* })
+                }
+                _ => ::std::result::Result::Err(#wrong),
+            }
+        }
+    }
+}
+
+struct Variant {
+    ident: Ident,
+    name: String,
+    shape: Shape,
+}
+
+fn variants(data: &syn::DataEnum) -> syn::Result<Vec<Variant>> {
+    let mut out = Vec::new();
+    for variant in data.variants.iter() {
+        let attrs = parse_attrs(&variant.attrs)?;
+        if attrs.skip {
+            return Err(Error::new_spanned(
+                variant,
+                "lamp(skip) can only be used on fields",
+            ));
+        }
+        out.push(Variant {
+            ident: variant.ident.clone(),
+            name: attrs.rename.unwrap_or_else(|| variant.ident.to_string()),
+            shape: Shape::new(&variant.fields)?,
+        });
+    }
+    Ok(out)
+}
+
+// adds a bound on the trait to every type parameter
+fn add_bounds(input: &mut DeriveInput, bound: syn::Path) {
+    for param in input.generics.type_params_mut() {
+        param.bounds.push(parse_quote!(#bound));
+    }
+}
+
+fn to_datum(mut input: DeriveInput) -> syn::Result<TokenStream2> {
+    add_bounds(&mut input, parse_quote!(::lamp_lang::datum::ToDatum));
+    let (lamp_type, to_code) = match &input.data {
+        Data::Struct(data) => {
+            let shape = Shape::new(&data.fields)?;
+            let to_code = shape.code_expr(|f| {
+                let member = &f.member;
+                quote!(&self.#member)
+            });
+            (shape.type_expr(), to_code)
+        }
+        Data::Enum(data) => {
+            let variants = variants(data)?;
+            let names = variants.iter().map(|v| &v.name);
+            let payloads = variants
+                .iter()
+                .map(|v| match (v.shape.kind, v.shape.single()) {
+                    (Kind::Unit, _) => quote!(::std::option::Option::None),
+                    (_, Some(field)) => {
+                        let ty = &field.ty;
+                        quote! {
+                            ::std::option::Option::Some(
+                                <#ty as ::lamp_lang::datum::ToDatum>::to_lamp_type()
+                            )
+                        }
+                    }
+                    _ => {
+                        let typ = v.shape.type_expr();
+                        quote!(::std::option::Option::Some(#typ))
+                    }
+                });
+            let lamp_type = quote! {{
+                let mut variants = ::lamp_lang::map::Map::new();
+                #( variants.insert(::std::string::String::from(#names), #payloads); )*
+                ::lamp_lang::lamp_type::LampType::Enum(variants)
+            }};
+
+            let arms = variants.iter().map(|v| {
+                let ident = &v.ident;
+                let name = &v.name;
+                let tag = quote! {
+                    ::lamp_lang::code::Code::Identifier(::std::string::String::from(#name))
+                };
+                let members = v.shape.fields.iter().map(|f| &f.member);
+                let bindings = v.shape.fields.iter().map(|f| {
+                    if f.skip {
+                        quote!(_)
+                    } else {
+                        let binding = &f.binding;
+                        quote!(#binding)
+                    }
+                });
+                let pattern = quote!(Self::#ident { #( #members: #bindings ),* });
+                let code = match (v.shape.kind, v.shape.single()) {
+                    (Kind::Unit, _) => quote!(::lamp_lang::code::Code::List(::std::vec![#tag])),
+                    (_, Some(field)) => {
+                        let binding = &field.binding;
+                        quote! {
+                            ::lamp_lang::code::Code::List(::std::vec![
+                                #tag,
+                                ::lamp_lang::datum::ToDatum::to_code(#binding),
+                            ])
+                        }
+                    }
+                    _ => {
+                        let payload = v.shape.code_expr(|f| {
+                            let binding = &f.binding;
+                            quote!(#binding)
+                        });
+                        quote!(::lamp_lang::code::Code::List(::std::vec![#tag, #payload]))
+                    }
+                };
+                quote!(#pattern => #code,)
+            });
+            let to_code = quote! {
+                match self {
+                    #( #arms )*
+                }
+            };
+            (lamp_type, to_code)
+        }
+        Data::Union(_) => {
+            return Err(Error::new_spanned(
+                &input.ident,
+                "ToDatum cannot be derived for unions",
+            ))
+        }
+    };
+
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::lamp_lang::datum::ToDatum for #ident #ty_generics #where_clause {
+            fn to_lamp_type() -> ::lamp_lang::lamp_type::LampType {
+                #lamp_type
+            }
+
+            fn to_code(&self) -> ::lamp_lang::code::Code {
+                #to_code
+            }
+        }
+    })
+}
+
+fn from_datum(mut input: DeriveInput) -> syn::Result<TokenStream2> {
+    add_bounds(&mut input, parse_quote!(::lamp_lang::datum::FromDatum));
+    let from_code = from_body(&input, Source::Code)?;
+    let from_value = from_body(&input, Source::Value)?;
+
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::lamp_lang::datum::FromDatum for #ident #ty_generics #where_clause {
+            fn from_code(
+                code: &::lamp_lang::code::Code,
+            ) -> ::std::result::Result<Self, ::lamp_lang::datum::DatumError> {
+                #from_code
+            }
+
+            fn from_value(
+                value: &::lamp_lang::value::Value,
+            ) -> ::std::result::Result<Self, ::lamp_lang::datum::DatumError> {
+                #from_value
+            }
+        }
+    })
+}
+
+// the body of from_code or from_value
+fn from_body(input: &DeriveInput, source: Source) -> syn::Result<TokenStream2> {
+    let input_arg = source.input();
+    let from = source.from();
+    let wrong = source.wrong();
+    Ok(match &input.data {
+        Data::Struct(data) => Shape::new(&data.fields)?.build_expr(source, quote!(Self), input_arg),
+        Data::Enum(data) => {
+            let variants = variants(data)?;
+            let arms = variants.into_iter().map(|v| {
+                let ident = &v.ident;
+                let name = &v.name;
+                let build = match (v.shape.kind, v.shape.single()) {
+                    (Kind::Unit, _) => quote!(::std::result::Result::Ok(Self::#ident)),
+                    (_, Some(field)) => {
+                        let ty = &field.ty;
+                        quote! {
+                            ::std::result::Result::Ok(Self::#ident(
+                                <#ty as ::lamp_lang::datum::FromDatum>::#from(payload)?,
+                            ))
+                        }
+                    }
+                    _ => v.shape.build_expr(source, quote!(Self::#ident), quote!(payload)),
+                };
+                match (source, v.shape.kind) {
+                    (Source::Code, Kind::Unit) => quote! {
+                        [::lamp_lang::code::Code::Identifier(tag)] if tag == #name => #build,
+                    },
+                    (Source::Code, _) => quote! {
+                        [::lamp_lang::code::Code::Identifier(tag), payload] if tag == #name => {
+                            #build
+                        }
+                    },
+                    (Source::Value, Kind::Unit) => quote! {
+                        ::std::option::Option::Some((#name, ::std::option::Option::None)) => #build,
+                    },
+                    (Source::Value, _) => quote! {
+                        ::std::option::Option::Some((#name, ::std::option::Option::Some(payload))) => {
+                            #build
+                        }
+                    },
+                }
+            });
+            match source {
+                Source::Code => quote! {
+                    match code {
+                        ::lamp_lang::code::Code::List(list) => match list.as_slice() {
+                            #( #arms )*
+                            _ => ::std::result::Result::Err(#wrong),
+                        },
+                        _ => ::std::result::Result::Err(#wrong),
+                    }
+                },
+                Source::Value => quote! {
+                    match ::lamp_lang::datum::tagged(value) {
+                        #( #arms )*
+                        _ => ::std::result::Result::Err(#wrong),
+                    }
+                },
+            }
+        }
+        Data::Union(_) => {
+            return Err(Error::new_spanned(
+                &input.ident,
+                "FromDatum cannot be derived for unions",
+            ))
+        }
+    })
+}
@@ -0,0 +1,419 @@
+use std::collections::HashMap;
+
+use crate::code::Code;
+use crate::datum::{Datum, FromDatum, ToDatum};
+use crate::lamp_type::LampType;
+use crate::map::Map;
+use crate::queue::Queue;
+use crate::value::Value;
+
+// every encoding starts with the magic, the version and what kind of value follows
+const MAGIC: &[u8] = b"LAMP";
+const VERSION: u8 = 1;
+
+const KIND_CODE: u8 = 0;
+const KIND_DATUM: u8 = 1;
+const KIND_TYPE: u8 = 2;
+
+const TAG_INTEGER: u8 = 0;
+const TAG_FLOAT: u8 = 1;
+const TAG_CHARACTER: u8 = 2;
+const TAG_STRING: u8 = 3;
+const TAG_IDENTIFIER: u8 = 4;
+const TAG_LIST: u8 = 5;
+const TAG_MAP: u8 = 6;
+
+// deeper trees are rejected instead of overflowing the stack
+const MAX_DEPTH: usize = 512;
+
+// after the header comes the table of identifiers, each written once,
+// then the codes which refer to identifiers by their index in the table
+struct Encoder {
+    identifiers: Vec<String>,
+    indices: HashMap<String, usize>,
+    body: Vec<u8>,
+}
+
+fn push_varint(out: &mut Vec<u8>, mut num: u128) {
+    while num >= 0x80 {
+        out.push(num as u8 | 0x80);
+        num >>= 7;
+    }
+    out.push(num as u8);
+}
+
+// zigzag so small negative integers stay small
+fn zigzag(num: i128) -> u128 {
+    ((num << 1) ^ (num >> 127)) as u128
+}
+
+fn unzigzag(num: u128) -> i128 {
+    (num >> 1) as i128 ^ -((num & 1) as i128)
+}
+
+fn push_str(out: &mut Vec<u8>, s: &str) {
+    push_varint(out, s.len() as u128);
+    out.extend_from_slice(s.as_bytes());
+}
+
+impl Encoder {
+    fn new() -> Encoder {
+        Encoder {
+            identifiers: Vec::new(),
+            indices: HashMap::new(),
+            body: Vec::new(),
+        }
+    }
+
+    fn code(&mut self, code: &Code) {
+        match code {
+            Code::Integer(num) => {
+                self.body.push(TAG_INTEGER);
+                push_varint(&mut self.body, zigzag(*num));
+            }
+            Code::Float(bits) => {
+                self.body.push(TAG_FLOAT);
+                self.body.extend_from_slice(&bits.to_le_bytes());
+            }
+            Code::Character(c) => {
+                self.body.push(TAG_CHARACTER);
+                push_varint(&mut self.body, *c as u128);
+            }
+            Code::StringLiteral(s) => {
+                self.body.push(TAG_STRING);
+                push_str(&mut self.body, s);
+            }
+            Code::Identifier(name) => {
+                let next = self.identifiers.len();
+                let index = *self.indices.entry(name.clone()).or_insert(next);
+                if index == next {
+                    self.identifiers.push(name.clone());
+                }
+                self.body.push(TAG_IDENTIFIER);
+                push_varint(&mut self.body, index as u128);
+            }
+            Code::List(list) => {
+                self.body.push(TAG_LIST);
+                push_varint(&mut self.body, list.len() as u128);
+                for item in list {
+                    self.code(item);
+                }
+            }
+            Code::Map(map) => {
+                self.body.push(TAG_MAP);
+                push_varint(&mut self.body, map.len() as u128);
+                for (key, value) in map.iter() {
+                    self.code(key);
+                    self.code(value);
+                }
+            }
+        }
+    }
+
+    fn finish(self, kind: u8) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        out.push(VERSION);
+        out.push(kind);
+        push_varint(&mut out, self.identifiers.len() as u128);
+        for name in self.identifiers.iter() {
+            push_str(&mut out, name);
+        }
+        out.extend(self.body);
+        out
+    }
+}
+
+fn encode(kind: u8, codes: &[&Code]) -> Vec<u8> {
+    let mut encoder = Encoder::new();
+    for code in codes {
+        encoder.code(code);
+    }
+    encoder.finish(kind)
+}
+
+fn binary_error(queue: &Queue<u8>, msg: &str) -> String {
+    format!("Binary Error: {} at byte {}", msg, queue.cursor)
+}
+
+impl<'a> Queue<'a, u8> {
+    fn pop_byte(&mut self) -> Result<u8, String> {
+        match self.pop() {
+            Some(byte) => Ok(*byte),
+            None => Err(binary_error(self, "Unexpected end of input")),
+        }
+    }
+
+    fn pop_bytes(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if self.len() - self.cursor < len {
+            return Err(binary_error(self, "Unexpected end of input"));
+        }
+        self.cursor += len;
+        Ok(&self.data[self.cursor - len..self.cursor])
+    }
+
+    fn pop_varint(&mut self) -> Result<u128, String> {
+        let mut num = 0u128;
+        let mut shift = 0;
+        loop {
+            let byte = self.pop_byte()?;
+            let bits = (byte & 0x7f) as u128;
+            if shift >= 128 || (shift > 0 && bits >> (128 - shift) != 0) {
+                return Err(binary_error(self, "Varint overflow"));
+            }
+            num |= bits << shift;
+            if byte & 0x80 == 0 {
+                return Ok(num);
+            }
+            shift += 7;
+        }
+    }
+
+    // a length can't be more than the bytes left since every item takes at least one
+    fn pop_len(&mut self) -> Result<usize, String> {
+        let len = self.pop_varint()?;
+        if len > (self.len() - self.cursor) as u128 {
+            return Err(binary_error(self, "Length longer than input"));
+        }
+        Ok(len as usize)
+    }
+
+    fn pop_str(&mut self) -> Result<String, String> {
+        let len = self.pop_len()?;
+        let bytes = self.pop_bytes(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| binary_error(self, "Invalid utf8"))
+    }
+
+    fn pop_header(&mut self, kind: u8) -> Result<Vec<String>, String> {
+        if self.pop_bytes(MAGIC.len()).ok() != Some(MAGIC) {
+            return Err(binary_error(self, "Missing magic bytes"));
+        }
+        let version = self.pop_byte()?;
+        if version != VERSION {
+            return Err(binary_error(
+                self,
+                &format!("Unsupported version {}", version),
+            ));
+        }
+        if self.pop_byte()? != kind {
+            return Err(binary_error(self, "Wrong kind of value"));
+        }
+        let count = self.pop_len()?;
+        let mut identifiers = Vec::with_capacity(count);
+        for _ in 0..count {
+            identifiers.push(self.pop_str()?);
+        }
+        Ok(identifiers)
+    }
+
+    fn pop_code(&mut self, identifiers: &[String], depth: usize) -> Result<Code, String> {
+        if depth > MAX_DEPTH {
+            return Err(binary_error(self, "Nesting too deep"));
+        }
+        match self.pop_byte()? {
+            TAG_INTEGER => {
+                let num = self.pop_varint()?;
+                Ok(Code::Integer(unzigzag(num)))
+            }
+            TAG_FLOAT => {
+                let bytes = self.pop_bytes(8)?;
+                Ok(Code::Float(u64::from_le_bytes(bytes.try_into().unwrap())))
+            }
+            TAG_CHARACTER => {
+                let num = self.pop_varint()?;
+                u32::try_from(num)
+                    .ok()
+                    .and_then(char::from_u32)
+                    .map(Code::Character)
+                    .ok_or_else(|| binary_error(self, "Invalid character"))
+            }
+            TAG_STRING => Ok(Code::StringLiteral(self.pop_str()?)),
+            TAG_IDENTIFIER => {
+                let index = self.pop_varint()?;
+                identifiers
+                    .get(index.min(usize::MAX as u128) as usize)
+                    .map(|name| Code::Identifier(name.clone()))
+                    .ok_or_else(|| binary_error(self, "Unknown identifier index"))
+            }
+            TAG_LIST => {
+                let len = self.pop_len()?;
+                let mut list = Vec::with_capacity(len);
+                for _ in 0..len {
+                    list.push(self.pop_code(identifiers, depth + 1)?);
+                }
+                Ok(Code::List(list))
+            }
+            TAG_MAP => {
+                let len = self.pop_len()?;
+                let mut map = Map::new();
+                for _ in 0..len {
+                    let key = self.pop_code(identifiers, depth + 1)?;
+                    let value = self.pop_code(identifiers, depth + 1)?;
+                    if map.insert(key, value).is_some() {
+                        return Err(binary_error(self, "Duplicate map key"));
+                    }
+                }
+                Ok(Code::Map(map))
+            }
+            tag => Err(binary_error(self, &format!("Unknown tag {}", tag))),
+        }
+    }
+}
+
+fn decode(bytes: &[u8], kind: u8, count: usize) -> Result<Vec<Code>, String> {
+    let mut queue = Queue::new(bytes);
+    let identifiers = queue.pop_header(kind)?;
+    let codes = (0..count)
+        .map(|_| queue.pop_code(&identifiers, 0))
+        .collect::<Result<Vec<_>, _>>()?;
+    if !queue.empty() {
+        return Err(binary_error(&queue, "Unexpected trailing bytes"));
+    }
+    Ok(codes)
+}
+
+impl Code {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        encode(KIND_CODE, &[self])
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Code, String> {
+        Ok(decode(bytes, KIND_CODE, 1)?.remove(0))
+    }
+}
+
+// a datum is written as the code of its type followed by its data
+impl Datum {
+    // fails for data holding host objects
+    pub fn to_bytes(&self) -> Result<Vec<u8>, String> {
+        Ok(encode(KIND_DATUM, &[&self.typ.to_code(), &self.code()?]))
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Datum, String> {
+        let mut codes = decode(bytes, KIND_DATUM, 2)?;
+        let data = codes.pop().unwrap();
+        let typ = LampType::from_code(&codes[0])?;
+        Ok(Datum::new(typ, Value::from_code(&data))?)
+    }
+}
+
+impl LampType {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        encode(KIND_TYPE, &[&self.to_code()])
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<LampType, String> {
+        Ok(LampType::from_code(&decode(bytes, KIND_TYPE, 1)?[0])?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::ts;
+
+    fn sample() -> Code {
+        Code::from_str(
+            "[pgm [var x 1 -300 170141183460469231731687303715884105727] \
+             {if c: [< x 2.5] do: \"é\\n\" else: 'c'} [x x x] []]",
+        )
+        .unwrap()
+        .remove(0)
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let code = sample();
+        let bytes = code.to_bytes();
+        assert_eq!(Code::from_bytes(&bytes), Ok(code));
+
+        for num in [0, 1, -1, 63, -64, 64, i128::MIN, i128::MAX] {
+            let code = Code::Integer(num);
+            assert_eq!(Code::from_bytes(&code.to_bytes()), Ok(code));
+        }
+
+        let datum = vec![(ts("a"), 'b'), (ts("c"), 'd')].to_datum();
+        assert_eq!(
+            Datum::from_bytes(&datum.to_bytes().unwrap()),
+            Ok(datum.clone())
+        );
+        assert_eq!(LampType::from_bytes(&datum.typ.to_bytes()), Ok(datum.typ));
+    }
+
+    #[test]
+    fn test_compact() {
+        // each identifier is stored once however often it appears
+        let code = Code::from_str(&"[some_long_identifier 1] ".repeat(100)).unwrap();
+        let bytes = Code::List(code).to_bytes();
+        assert_eq!(bytes.len(), MAGIC.len() + 3 + 21 + 2 + 100 * 6);
+    }
+
+    #[test]
+    fn test_errors() {
+        let bytes = sample().to_bytes();
+        assert_eq!(
+            Code::from_bytes(b"LAMQ"),
+            Err("Binary Error: Missing magic bytes at byte 4".to_string())
+        );
+        assert_eq!(
+            Code::from_bytes(b"LAMP\x02"),
+            Err("Binary Error: Unsupported version 2 at byte 5".to_string())
+        );
+        assert_eq!(
+            Code::from_bytes(b"LAMP\x01\x00\x00\x07"),
+            Err("Binary Error: Unknown tag 7 at byte 8".to_string())
+        );
+        assert_eq!(
+            Code::from_bytes(b"LAMP\x01\x00\x00\x04\x00"),
+            Err("Binary Error: Unknown identifier index at byte 9".to_string())
+        );
+        assert_eq!(
+            LampType::from_bytes(&bytes),
+            Err("Binary Error: Wrong kind of value at byte 6".to_string())
+        );
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert!(Code::from_bytes(&trailing)
+            .unwrap_err()
+            .contains("Unexpected trailing bytes"));
+        let mut deep = b"LAMP\x01\x00\x00".to_vec();
+        deep.extend([TAG_LIST, 1].repeat(10_000));
+        assert!(Code::from_bytes(&deep)
+            .unwrap_err()
+            .contains("Nesting too deep"));
+    }
+
+    // a small deterministic generator so the fuzzing is reproducible
+    fn xorshift(state: &mut u64) -> u64 {
+        *state ^= *state << 13;
+        *state ^= *state >> 7;
+        *state ^= *state << 17;
+        *state
+    }
+
+    #[test]
+    fn test_malformed_never_panics() {
+        let bytes = sample().to_bytes();
+        for len in 0..bytes.len() {
+            assert!(Code::from_bytes(&bytes[..len]).is_err());
+        }
+
+        let mut state = 0x2545f4914f6cdd1d;
+        for _ in 0..20_000 {
+            let mut mutated = bytes.clone();
+            for _ in 0..1 + xorshift(&mut state) % 4 {
+                let i = xorshift(&mut state) as usize % mutated.len();
+                mutated[i] = xorshift(&mut state) as u8;
+            }
+            let _ = Code::from_bytes(&mutated);
+            let _ = Datum::from_bytes(&mutated);
+
+            let mut random = MAGIC.to_vec();
+            random.push(VERSION);
+            for _ in 0..xorshift(&mut state) % 32 {
+                random.push(xorshift(&mut state) as u8);
+            }
+            let _ = Code::from_bytes(&random);
+            let _ = LampType::from_bytes(&random);
+        }
+    }
+}
@@ -0,0 +1,283 @@
+use crate::capability::Capabilities;
+use crate::error::{self, Error, Kind};
+use crate::json;
+use crate::value::Value;
+
+// name, argument names and optional rest argument of every built-in function
+pub const BUILTINS: &[(&str, &[&str], Option<&str>)] = &[
+    ("plus", &["lhs", "rhs"], None),
+    ("minus", &["lhs", "rhs"], None),
+    ("multiply", &["lhs", "rhs"], None),
+    ("divide", &["lhs", "rhs"], None),
+    ("remainder", &["lhs", "rhs"], None),
+    ("less_than", &["lhs", "rhs"], None),
+    ("less_equal", &["lhs", "rhs"], None),
+    ("greater_than", &["lhs", "rhs"], None),
+    ("greater_equal", &["lhs", "rhs"], None),
+    ("equal", &["lhs", "rhs"], None),
+    ("not_equal", &["lhs", "rhs"], None),
+    ("concat", &[], Some("parts")),
+    ("display", &["value"], None),
+    ("is_error", &["value", "kind"], None),
+    ("json_parse", &["text"], None),
+    ("json_stringify", &["value"], None),
+    ("u8", &["value"], None),
+    ("u16", &["value"], None),
+    ("u32", &["value"], None),
+    ("u64", &["value"], None),
+    ("i8", &["value"], None),
+    ("i16", &["value"], None),
+    ("i32", &["value"], None),
+    ("i64", &["value"], None),
+    ("f32", &["value"], None),
+    ("f64", &["value"], None),
+    // these need capabilities from the runtime
+    ("read_file", &["path"], None),
+    ("write_file", &["path", "text"], None),
+    ("env_var", &["name"], None),
+    ("clock", &[], None),
+    ("spawn", &["program"], Some("args")),
+];
+
+// the symbols bound to built-ins in a new runtime
+pub const OPERATORS: &[(&str, &str)] = &[
+    ("+", "plus"),
+    ("-", "minus"),
+    ("*", "multiply"),
+    ("/", "divide"),
+    ("%", "remainder"),
+    ("<", "less_than"),
+    ("<=", "less_equal"),
+    (">", "greater_than"),
+    (">=", "greater_equal"),
+    ("=", "equal"),
+    ("!=", "not_equal"),
+    ("++", "concat"),
+];
+
+#[derive(Clone, Copy)]
+enum Num {
+    Int(i128),
+    Float(f64),
+}
+
+impl Num {
+    fn from_value(value: &Value, func: &str) -> Result<Num, Error> {
+        match value {
+            Value::Integer(num) => Ok(Num::Int(*num)),
+            Value::Float(num) => Ok(Num::Float(f64::from_bits(*num))),
+            _ => Err(Error::new(
+                Kind::TypeError,
+                format!("{} expects numbers but got {}", func, value),
+            )),
+        }
+    }
+
+    fn as_float(self) -> f64 {
+        match self {
+            Num::Int(num) => num as f64,
+            Num::Float(num) => num,
+        }
+    }
+}
+
+fn arithmetic(
+    func: &str,
+    args: &[Value],
+    int_op: fn(i128, i128) -> Option<i128>,
+    float_op: fn(f64, f64) -> f64,
+) -> Result<Value, Error> {
+    let lhs = Num::from_value(&args[0], func)?;
+    let rhs = Num::from_value(&args[1], func)?;
+    match (lhs, rhs) {
+        (Num::Int(l), Num::Int(r)) => match int_op(l, r) {
+            Some(num) => Ok(Value::Integer(num)),
+            None if r == 0 && (func == "divide" || func == "remainder") => {
+                Err(Error::new(Kind::DivisionByZero, "Division by zero"))
+            }
+            None => Err(Error::new(
+                Kind::Overflow,
+                format!("Integer overflow in {}", func),
+            )),
+        },
+        _ => Ok(Value::from_float(float_op(lhs.as_float(), rhs.as_float()))),
+    }
+}
+
+fn compare(func: &str, args: &[Value]) -> Result<std::cmp::Ordering, Error> {
+    let lhs = Num::from_value(&args[0], func)?;
+    let rhs = Num::from_value(&args[1], func)?;
+    match (lhs, rhs) {
+        (Num::Int(l), Num::Int(r)) => Ok(l.cmp(&r)),
+        _ => lhs.as_float().partial_cmp(&rhs.as_float()).ok_or_else(|| {
+            Error::new(
+                Kind::ArithmeticError,
+                format!("{} cannot compare NaN", func),
+            )
+        }),
+    }
+}
+
+// numbers compare by value, everything else structurally
+fn equal(lhs: &Value, rhs: &Value) -> bool {
+    match (Num::from_value(lhs, ""), Num::from_value(rhs, "")) {
+        (Ok(Num::Int(l)), Ok(Num::Int(r))) => l == r,
+        (Ok(l), Ok(r)) => l.as_float() == r.as_float(),
+        _ => lhs == rhs,
+    }
+}
+
+fn cast_integer(typ: &str, value: &Value) -> Result<Value, Error> {
+    let (min, max): (i128, i128) = match typ {
+        "u8" => (0, u8::MAX.into()),
+        "u16" => (0, u16::MAX.into()),
+        "u32" => (0, u32::MAX.into()),
+        "u64" => (0, u64::MAX.into()),
+        "i8" => (i8::MIN.into(), i8::MAX.into()),
+        "i16" => (i16::MIN.into(), i16::MAX.into()),
+        "i32" => (i32::MIN.into(), i32::MAX.into()),
+        _ => (i64::MIN.into(), i64::MAX.into()),
+    };
+    let num = match Num::from_value(value, typ)? {
+        Num::Int(num) => num,
+        Num::Float(num) if num.is_finite() => num.trunc() as i128,
+        Num::Float(_) => {
+            return Err(Error::new(
+                Kind::TypeError,
+                format!("Cannot convert {} to {}", value, typ),
+            ))
+        }
+    };
+    if num < min || num > max {
+        return Err(Error::new(
+            Kind::Overflow,
+            format!("{} does not fit in {}", value, typ),
+        ));
+    }
+    Ok(Value::Integer(num))
+}
+
+fn string_arg<'a>(func: &str, value: &'a Value) -> Result<&'a str, Error> {
+    match value {
+        Value::String(s) => Ok(s),
+        _ => Err(Error::new(
+            Kind::TypeError,
+            format!("{} expects strings but got {}", func, value),
+        )),
+    }
+}
+
+fn denied(err: String) -> Error {
+    Error::new(Kind::CapabilityError, err)
+}
+
+// the built-ins that reach outside the runtime, each checks the capability first
+fn io(name: &str, args: &[Value], caps: &Capabilities) -> Result<Value, Error> {
+    match name {
+        "read_file" => {
+            let path = caps
+                .check_read(string_arg(name, &args[0])?)
+                .map_err(denied)?;
+            let text = std::fs::read_to_string(&path)
+                .map_err(|err| format!("read_file: {}: {}", path.display(), err))?;
+            Ok(Value::string(&text))
+        }
+        "write_file" => {
+            let path = caps
+                .check_write(string_arg(name, &args[0])?)
+                .map_err(denied)?;
+            std::fs::write(&path, string_arg(name, &args[1])?)
+                .map_err(|err| format!("write_file: {}: {}", path.display(), err))?;
+            Ok(Value::List(Default::default()))
+        }
+        "env_var" => {
+            caps.check_env().map_err(denied)?;
+            match std::env::var(string_arg(name, &args[0])?) {
+                Ok(value) => Ok(Value::string(&value)),
+                Err(_) => Ok(Value::List(Default::default())),
+            }
+        }
+        // seconds since the unix epoch
+        "clock" => {
+            caps.check_clock().map_err(denied)?;
+            let now = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map_err(|err| err.to_string())?;
+            Ok(Value::from_float(now.as_secs_f64()))
+        }
+        // runs the program to the end and gives back what it printed
+        "spawn" => {
+            let program = string_arg(name, &args[0])?;
+            caps.check_spawn(program).map_err(denied)?;
+            let rest = args[1..]
+                .iter()
+                .map(|arg| string_arg(name, arg))
+                .collect::<Result<Vec<_>, _>>()?;
+            let output = std::process::Command::new(program)
+                .args(rest)
+                .output()
+                .map_err(|err| format!("spawn: {}: {}", program, err))?;
+            if !output.status.success() {
+                return Err(format!("spawn: {} exited with {}", program, output.status).into());
+            }
+            Ok(Value::string(&String::from_utf8_lossy(&output.stdout)))
+        }
+        _ => Err(Error::new(
+            Kind::NameError,
+            format!("Unknown built-in function \"{}\"", name),
+        )),
+    }
+}
+
+pub fn call(name: &str, args: Vec<Value>, caps: &Capabilities) -> Result<Value, Error> {
+    match name {
+        "plus" => arithmetic(name, &args, i128::checked_add, |l, r| l + r),
+        "minus" => arithmetic(name, &args, i128::checked_sub, |l, r| l - r),
+        "multiply" => arithmetic(name, &args, i128::checked_mul, |l, r| l * r),
+        "divide" => arithmetic(name, &args, i128::checked_div, |l, r| l / r),
+        "remainder" => arithmetic(name, &args, i128::checked_rem, |l, r| l % r),
+        "less_than" => Ok(Value::boolean(compare(name, &args)?.is_lt())),
+        "less_equal" => Ok(Value::boolean(compare(name, &args)?.is_le())),
+        "greater_than" => Ok(Value::boolean(compare(name, &args)?.is_gt())),
+        "greater_equal" => Ok(Value::boolean(compare(name, &args)?.is_ge())),
+        "equal" => Ok(Value::boolean(equal(&args[0], &args[1]))),
+        "not_equal" => Ok(Value::boolean(!equal(&args[0], &args[1]))),
+        "concat" => {
+            let mut s = String::new();
+            for arg in args.iter() {
+                match arg {
+                    Value::String(part) => s.push_str(part),
+                    Value::Character(c) => s.push(*c),
+                    _ => {
+                        return Err(Error::new(
+                            Kind::TypeError,
+                            format!("concat expects strings but got {}", arg),
+                        ))
+                    }
+                }
+            }
+            Ok(Value::string(&s))
+        }
+        "display" => Ok(Value::string(&args[0].to_string())),
+        "is_error" => Ok(Value::boolean(match (error::parts(&args[0]), &args[1]) {
+            (Some((kind, _)), Value::Symbol(of)) => error::is_a(kind, of),
+            _ => false,
+        })),
+        "json_parse" => match &args[0] {
+            Value::String(text) => Ok(Value::from_code(&json::parse(text)?)),
+            arg => Err(Error::new(
+                Kind::TypeError,
+                format!("json_parse expects a string but got {}", arg),
+            )),
+        },
+        "json_stringify" => Ok(Value::string(&json::stringify(&args[0].to_code()?)?)),
+        "u8" | "u16" | "u32" | "u64" | "i8" | "i16" | "i32" | "i64" => cast_integer(name, &args[0]),
+        "f32" => Ok(Value::from_float(
+            Num::from_value(&args[0], name)?.as_float() as f32 as f64,
+        )),
+        "f64" => Ok(Value::from_float(
+            Num::from_value(&args[0], name)?.as_float(),
+        )),
+        _ => io(name, &args, caps),
+    }
+}
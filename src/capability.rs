@@ -0,0 +1,214 @@
+use std::path::{Component, Path, PathBuf};
+
+// what a runtime lets scripts touch outside of it, built-ins that do i/o check it first
+// the default grants nothing so scripts are pure
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Capabilities {
+    // files and directories scripts can read, a directory includes everything under it
+    pub read: Vec<PathBuf>,
+    pub write: Vec<PathBuf>,
+    pub env: bool,
+    pub clock: bool,
+    // running other programs
+    pub spawn: bool,
+}
+
+fn denied(what: &str) -> String {
+    format!("Capability Error: {} is not allowed", what)
+}
+
+// the absolute path with . and .. worked out, following links where the path exists
+// so neither can be used to step out of a granted directory
+fn resolve(path: &Path) -> Result<PathBuf, String> {
+    let absolute = std::env::current_dir()
+        .map_err(|err| err.to_string())?
+        .join(path);
+    let mut resolved = PathBuf::new();
+    for component in absolute.components() {
+        match component {
+            Component::ParentDir => {
+                resolved.pop();
+            }
+            Component::CurDir => (),
+            other => {
+                resolved.push(other);
+                if let Ok(real) = resolved.canonicalize() {
+                    resolved = real;
+                }
+            }
+        }
+    }
+    Ok(resolved)
+}
+
+impl Capabilities {
+    pub fn pure() -> Capabilities {
+        Capabilities::default()
+    }
+
+    // everything, what scripts could do before there was a sandbox
+    pub fn all() -> Capabilities {
+        Capabilities {
+            read: vec![PathBuf::from("/")],
+            write: vec![PathBuf::from("/")],
+            env: true,
+            clock: true,
+            spawn: true,
+        }
+    }
+
+    // adds the capabilities written like the repl flag, read=PATH write=PATH env clock spawn
+    // separated by commas
+    pub fn grant(&mut self, spec: &str) -> Result<(), String> {
+        for part in spec.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            match part.split_once('=') {
+                Some(("read", path)) => self.read.push(PathBuf::from(path)),
+                Some(("write", path)) => self.write.push(PathBuf::from(path)),
+                None if part == "env" => self.env = true,
+                None if part == "clock" => self.clock = true,
+                None if part == "spawn" => self.spawn = true,
+                None if part == "all" => *self = Capabilities::all(),
+                _ => return Err(format!("Unknown capability {}", part)),
+            }
+        }
+        Ok(())
+    }
+
+    fn allowed(roots: &[PathBuf], path: &str, what: &str) -> Result<PathBuf, String> {
+        let resolved = resolve(Path::new(path))?;
+        for root in roots {
+            if resolved.starts_with(resolve(root)?) {
+                return Ok(resolved);
+            }
+        }
+        Err(denied(&format!("{} {}", what, path)))
+    }
+
+    // the path to open when reading the file is allowed
+    pub fn check_read(&self, path: &str) -> Result<PathBuf, String> {
+        Capabilities::allowed(&self.read, path, "reading")
+    }
+
+    pub fn check_write(&self, path: &str) -> Result<PathBuf, String> {
+        Capabilities::allowed(&self.write, path, "writing")
+    }
+
+    pub fn check_env(&self) -> Result<(), String> {
+        match self.env {
+            true => Ok(()),
+            false => Err(denied("reading the environment")),
+        }
+    }
+
+    pub fn check_clock(&self) -> Result<(), String> {
+        match self.clock {
+            true => Ok(()),
+            false => Err(denied("reading the clock")),
+        }
+    }
+
+    pub fn check_spawn(&self, program: &str) -> Result<(), String> {
+        match self.spawn {
+            true => Ok(()),
+            false => Err(denied(&format!("running {}", program))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builtin;
+    use crate::value::Value;
+
+    fn call(caps: &Capabilities, name: &str, args: &[&str]) -> Result<Value, String> {
+        builtin::call(name, args.iter().map(|a| Value::string(a)).collect(), caps)
+            .map_err(|e| e.message)
+    }
+
+    #[test]
+    fn test_pure() {
+        let caps = Capabilities::pure();
+        assert_eq!(
+            call(&caps, "read_file", &["Cargo.toml"]),
+            Err("Capability Error: reading Cargo.toml is not allowed".to_string())
+        );
+        assert_eq!(
+            call(&caps, "write_file", &["out.txt", "x"]),
+            Err("Capability Error: writing out.txt is not allowed".to_string())
+        );
+        assert_eq!(
+            call(&caps, "env_var", &["HOME"]),
+            Err("Capability Error: reading the environment is not allowed".to_string())
+        );
+        assert_eq!(
+            call(&caps, "clock", &[]),
+            Err("Capability Error: reading the clock is not allowed".to_string())
+        );
+        assert_eq!(
+            call(&caps, "spawn", &["echo", "hi"]),
+            Err("Capability Error: running echo is not allowed".to_string())
+        );
+    }
+
+    #[test]
+    fn test_paths() {
+        let dir = std::env::temp_dir().join(format!("lamp_caps_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("inside")).unwrap();
+        let inside = dir.join("inside");
+        let file = inside.join("note.txt");
+        let file = file.to_str().unwrap();
+
+        let mut caps = Capabilities::pure();
+        caps.grant(&format!(
+            "read={},write={}",
+            dir.display(),
+            inside.display()
+        ))
+        .unwrap();
+        assert_eq!(
+            call(&caps, "write_file", &[file, "hello"]),
+            Ok(Value::List(Default::default()))
+        );
+        assert_eq!(
+            call(&caps, "read_file", &[file]),
+            Ok(Value::string("hello"))
+        );
+
+        // going up and out of a granted directory is caught
+        let escape = format!("{}/../../outside.txt", inside.display());
+        assert!(call(&caps, "write_file", &[&escape, "x"]).is_err());
+        assert!(caps
+            .check_write(dir.join("other.txt").to_str().unwrap())
+            .is_err());
+        assert!(caps
+            .check_read(dir.join("other.txt").to_str().unwrap())
+            .is_ok());
+        assert!(caps.check_read("/etc/passwd").is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_grant() {
+        let mut caps = Capabilities::pure();
+        caps.grant("env, clock").unwrap();
+        assert!(caps.check_env().is_ok());
+        assert!(call(&caps, "clock", &[]).is_ok());
+        assert!(caps.check_spawn("ls").is_err());
+        assert_eq!(
+            caps.grant("network"),
+            Err("Unknown capability network".to_string())
+        );
+
+        caps.grant("spawn").unwrap();
+        assert_eq!(
+            call(&caps, "spawn", &["echo", "hi"]),
+            Ok(Value::string("hi\n"))
+        );
+
+        let mut all = Capabilities::pure();
+        all.grant("all").unwrap();
+        assert_eq!(all, Capabilities::all());
+    }
+}
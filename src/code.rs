@@ -1,8 +1,13 @@
-use crate::datum::ToDatum;
+use std::fmt;
+
+use crate::datum::{DatumError, FromDatum, ToDatum};
+use crate::error::Error;
 use crate::map::Map;
 use crate::parse;
 use crate::token::tokenize_from_str;
 use crate::lamp_type::LampType;
+use crate::runtime::Runtime;
+use crate::value::Value;
 use Code::*;
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -17,31 +22,98 @@ pub enum Code {
 }
 
 impl Code {
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(code: &str) -> Result<Vec<Code>, String> {
         parse::parse(&tokenize_from_str(code)?)
     }
 
-    pub fn eval(&self) -> Result<Code, String> {
+    // evaluates the code in a fresh runtime
+    pub fn eval(&self) -> Result<Value, Error> {
+        Runtime::new().eval(self)
+    }
+
+    pub fn from_float(num: f64) -> Code {
+        Code::Float(num.to_bits())
+    }
+
+    // the code as it would be written in a lamp file
+    pub fn to_source(&self) -> String {
+        struct Source<'a>(&'a Code);
+        impl fmt::Display for Source<'_> {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                self.0.write_source(f)
+            }
+        }
+        Source(self).to_string()
+    }
+
+    // writes the code so that it tokenizes back to the same value
+    fn write_source(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            &Integer(num) => Ok(Integer(num)),
-            &Float(num) => Ok(Float(num)),
-            &Character(c) => Ok(Character(c)),
-            StringLiteral(s) => Ok(StringLiteral(s.clone())),
-            Identifier(i) => Ok(Identifier(i.clone())),
-            List(fun) => {
-                let mut args = fun.iter();
-                match args.next().map(|c| c.eval()) {
-                    Some(Err(err)) => Err(err),
-                    None => Ok(List(Vec::new())),
-                    _ => Err("unimplmented".to_string()),
+            Integer(num) => write!(f, "{}", num),
+            Float(num) => {
+                let num = f64::from_bits(*num);
+                let sign = if num.is_sign_negative() { '-' } else { '+' };
+                match num {
+                    _ if num.is_nan() => write!(f, "{}nan", sign),
+                    _ if num.is_infinite() => write!(f, "{}inf", sign),
+                    _ => write!(f, "{:?}", num),
+                }
+            }
+            Character(c) => write!(f, "'{}'", c.escape_debug()),
+            StringLiteral(s) => {
+                write!(f, "\"")?;
+                for c in s.chars() {
+                    match c {
+                        '{' | '}' => write!(f, "\\{}", c)?,
+                        _ => write!(f, "{}", c.escape_debug())?,
+                    }
+                }
+                write!(f, "\"")
+            }
+            Identifier(i) => write!(f, "{}", i),
+            List(list) => {
+                write!(f, "[")?;
+                for (i, code) in list.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    code.write_source(f)?;
                 }
+                write!(f, "]")
+            }
+            Code::Map(map) => {
+                let head = Identifier("head_position_field".to_string());
+                write!(f, "{{")?;
+                let mut first = true;
+                if let Some(code) = map.get(&head) {
+                    code.write_source(f)?;
+                    first = false;
+                }
+                for (key, value) in map.iter().filter(|(k, _)| **k != head) {
+                    if !first {
+                        write!(f, " ")?;
+                    }
+                    first = false;
+                    key.write_source(f)?;
+                    write!(f, ": ")?;
+                    value.write_source(f)?;
+                }
+                write!(f, "}}")
             }
-            _ => Err("unimplmented".to_string()),
         }
     }
+}
 
-    pub fn from_float(num: f64) -> Code {
-        Code::Float(num.to_bits())
+// the display protocol used by string interpolation
+// top level strings and characters are shown without quotes
+impl fmt::Display for Code {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StringLiteral(s) => write!(f, "{}", s),
+            Character(c) => write!(f, "{}", c),
+            _ => self.write_source(f),
+        }
     }
 }
 
@@ -53,4 +125,57 @@ impl ToDatum for Code {
     fn to_code(&self) -> Code {
         self.clone()
     }
-}
\ No newline at end of file
+}
+
+impl FromDatum for Code {
+    fn from_code(code: &Code) -> Result<Self, DatumError> {
+        Ok(code.clone())
+    }
+
+    // any datum can be looked at as plain code
+    fn from_datum(datum: &crate::datum::Datum) -> Result<Self, DatumError> {
+        datum.code()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        assert_eq!(StringLiteral("hi".to_string()).to_string(), "hi");
+        assert_eq!(Character('c').to_string(), "c");
+        assert_eq!(Code::from_float(1.0).to_string(), "1.0");
+        assert_eq!(
+            List(vec![
+                Integer(-4),
+                StringLiteral("a\"{b}\n".to_string()),
+                Character('\''),
+            ])
+            .to_string(),
+            "[-4 \"a\\\"\\{b\\}\\n\" '\\'']"
+        );
+    }
+
+    #[test]
+    fn test_display_round_trip() {
+        let code = "[f {if c: [g 1.5 -2] do: \"x{y}\"} b\"ok\" 'q']";
+        let parsed = Code::from_str(code).unwrap();
+        let shown = parsed[0].to_string();
+        assert_eq!(Code::from_str(&shown).unwrap(), parsed);
+
+        // floats without digits are written so they read back the same
+        for num in [f64::INFINITY, f64::NEG_INFINITY, f64::NAN, -f64::NAN] {
+            let code = Code::from_float(num);
+            assert_eq!(Code::from_str(&code.to_source()).unwrap(), [code]);
+        }
+        assert_eq!(Code::from_float(f64::NEG_INFINITY).to_source(), "-inf");
+    }
+
+    #[test]
+    fn test_display_keeps_field_order() {
+        let code = "{while c: [< x 3] do: [f x] then: x else: y}";
+        assert_eq!(Code::from_str(code).unwrap()[0].to_string(), code);
+    }
+}
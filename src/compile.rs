@@ -0,0 +1,763 @@
+use std::collections::HashSet;
+use std::rc::Rc;
+
+use crate::code::Code;
+use crate::lamp_type::LampType;
+use crate::map::Map;
+use crate::parse::{Located, Location, NOWHERE};
+use crate::runtime::{parse_type, try_parts};
+use crate::utils::ts;
+use crate::value::Value;
+
+// the instructions of the vm, operands index the tables of the proto they are in
+// every instruction leaves one value more or less on the stack as its comment says
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Op {
+    // pushes constants[i]
+    Constant(u32),
+    // pushes the empty list
+    Nothing,
+    Pop,
+    // locals not used by inner functions live in slots, the stores keep the value on the stack
+    LoadSlot(u32),
+    StoreSlot(u32),
+    // locals inner functions can see live in cells shared with the closures
+    LoadCell(u32),
+    StoreCell(u32),
+    // replaces cells from the first for the count with new ones, a pgm scope starting again
+    FreshCells(u32, u32),
+    // the cells of the closure being run
+    LoadCapture(u32),
+    StoreCapture(u32),
+    // names[i] looked up in the globals when run
+    LoadGlobal(u32),
+    DefineGlobal(u32),
+    SetGlobal(u32),
+    // pops the count of items, or keys and values, and pushes the collection
+    MakeList(u32),
+    MakeMap(u32),
+    Jump(u32),
+    // pop the condition and jump when it is false
+    IfFalse(u32),
+    WhileFalse(u32),
+    // jumps back to the start of a while, where the run can be cancelled
+    Loop(u32),
+    // pops the count of arguments and calls names[i] the way the runtime calls a name
+    Call(u32, u32),
+    // pops a function and then the count of arguments, names[i] is what it was called as
+    CallValue(u32, u32),
+    // calls whose result the function returns, a lamp closure replaces the frame making them
+    TailCall(u32, u32),
+    TailCallValue(u32, u32),
+    // pops the defaults and pushes a closure of protos[i]
+    Closure(u32, u32),
+    // pops the finally and the handler when there are those and then the body
+    // and pushes what try_call gives back
+    Try(bool, bool),
+}
+
+impl Op {
+    // limits count the expressions evaluated like the tree walker does, jumps and pops are free
+    pub(crate) fn is_step(self) -> bool {
+        !matches!(
+            self,
+            Op::Pop | Op::Jump(_) | Op::IfFalse(_) | Op::WhileFalse(_) | Op::Loop(_)
+        )
+    }
+
+    // the ops that call a function, the frame they make is given the location of the op
+    pub(crate) fn is_call(self) -> bool {
+        matches!(
+            self,
+            Op::Call(..)
+                | Op::CallValue(..)
+                | Op::TailCall(..)
+                | Op::TailCallValue(..)
+                | Op::Try(..)
+        )
+    }
+}
+
+// where a local lives in the frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Local {
+    Slot(u32),
+    Cell(u32),
+}
+
+// where a closure gets a cell from when it is made
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Capture {
+    // a cell of the frame making the closure
+    Cell(u32),
+    // one of the captures of the closure making the closure
+    Outer(u32),
+}
+
+// an argument of a compiled function, the default is pushed when the closure is made
+#[derive(Debug)]
+pub(crate) struct Param {
+    pub(crate) name: String,
+    pub(crate) typ: LampType,
+    pub(crate) has_default: bool,
+    pub(crate) local: Local,
+}
+
+// a compiled function, or the top level of a program
+#[derive(Debug)]
+pub(crate) struct Proto {
+    pub(crate) ops: Vec<Op>,
+    pub(crate) constants: Vec<Value>,
+    pub(crate) names: Vec<String>,
+    pub(crate) protos: Vec<Rc<Proto>>,
+    pub(crate) slots: usize,
+    pub(crate) cells: usize,
+    pub(crate) params: Vec<Param>,
+    pub(crate) captures: Vec<Capture>,
+    pub(crate) returns: Option<LampType>,
+    // the fn form, what closures of it are shown as
+    pub(crate) source: Code,
+    // where the call each op makes was written, when the code came with its locations
+    pub(crate) locations: Vec<Option<Location>>,
+}
+
+// code compiled for Runtime::exec
+#[derive(Debug, Clone)]
+pub struct Program {
+    pub(crate) proto: Rc<Proto>,
+}
+
+impl Program {
+    // how many instructions the program and the functions in it have
+    pub fn len(&self) -> usize {
+        fn count(proto: &Proto) -> usize {
+            proto.ops.len() + proto.protos.iter().map(|p| count(p)).sum::<usize>()
+        }
+        count(&self.proto)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+// the names defined by var in a pgm or function body
+#[derive(Default)]
+struct Block {
+    names: Vec<(String, Local)>,
+}
+
+// a function being compiled
+struct Function {
+    proto: Proto,
+    blocks: Vec<Block>,
+    // names used inside functions nested in this one, they get cells
+    shared: HashSet<String>,
+    captured: Vec<String>,
+    // vars in the outermost block of the top level are globals
+    top_level: bool,
+}
+
+// every identifier inside fn forms in the code
+fn nested_names(code: &Code, inside: bool, names: &mut HashSet<String>) {
+    match code {
+        Code::Identifier(name) if inside => {
+            names.insert(name.clone());
+        }
+        Code::List(list) => list.iter().for_each(|c| nested_names(c, inside, names)),
+        Code::Map(map) => {
+            // the parts of a try run as closures
+            let inside = inside || is_form(map, "fn") || is_form(map, "try");
+            map.values().for_each(|c| nested_names(c, inside, names));
+        }
+        _ => (),
+    }
+}
+
+// the names given functions by var in a block, not counting inner pgm, fn and try forms
+// they are declared when the block starts so the functions can call themselves and each other
+fn function_names(code: &Code, names: &mut Vec<String>) {
+    match code {
+        Code::List(list) => match list.as_slice() {
+            [Code::Identifier(var), Code::Identifier(name), Code::Map(value)]
+                if var == "var" && is_form(value, "fn") =>
+            {
+                names.push(name.clone())
+            }
+            [Code::Identifier(pgm), ..] if pgm == "pgm" => (),
+            _ => list.iter().for_each(|c| function_names(c, names)),
+        },
+        Code::Map(map) if !is_form(map, "fn") && !is_form(map, "try") => {
+            map.values().for_each(|c| function_names(c, names))
+        }
+        _ => (),
+    }
+}
+
+fn field<'a>(map: &'a Map<Code, Code>, name: &str) -> Option<&'a Code> {
+    map.get(&Code::Identifier(ts(name)))
+}
+
+fn is_form(map: &Map<Code, Code>, form: &str) -> bool {
+    matches!(field(map, "head_position_field"), Some(Code::Identifier(f)) if f == form)
+}
+
+fn index(len: usize) -> Result<u32, String> {
+    u32::try_from(len).map_err(|_| "Program too large to compile".to_string())
+}
+
+struct Compiler {
+    // the function being compiled is last, the ones it is nested in come before
+    functions: Vec<Function>,
+}
+
+// whether running from the instruction at pc only jumps to the end
+fn returns(ops: &[Op], mut pc: usize) -> bool {
+    loop {
+        match ops.get(pc) {
+            None => return true,
+            Some(Op::Jump(to)) => pc = *to as usize,
+            Some(_) => return false,
+        }
+    }
+}
+
+// turns the calls a function returns the result of into tail calls
+fn tail_calls(ops: &mut [Op]) {
+    for pc in 0..ops.len() {
+        if returns(ops, pc + 1) {
+            ops[pc] = match ops[pc] {
+                Op::Call(name, argc) => Op::TailCall(name, argc),
+                Op::CallValue(name, argc) => Op::TailCallValue(name, argc),
+                op => op,
+            };
+        }
+    }
+}
+
+// compiles top level code, vars in it define globals just like Runtime::eval
+pub fn compile(code: &Code) -> Result<Program, String> {
+    compile_located(code, &NOWHERE)
+}
+
+// compiles code with the locations a SourceMap found, so backtraces say where calls were written
+pub fn compile_located(code: &Code, at: &Located) -> Result<Program, String> {
+    let mut shared = HashSet::new();
+    nested_names(code, false, &mut shared);
+    let mut compiler = Compiler {
+        functions: vec![Function::new(code.clone(), shared, true)],
+    };
+    compiler.expr(code, at)?;
+    let function = compiler.functions.pop().unwrap();
+    Ok(Program {
+        proto: Rc::new(function.proto),
+    })
+}
+
+impl Function {
+    fn new(source: Code, shared: HashSet<String>, top_level: bool) -> Function {
+        Function {
+            proto: Proto {
+                ops: Vec::new(),
+                constants: Vec::new(),
+                names: Vec::new(),
+                protos: Vec::new(),
+                slots: 0,
+                cells: 0,
+                params: Vec::new(),
+                captures: Vec::new(),
+                returns: None,
+                source,
+                locations: Vec::new(),
+            },
+            blocks: vec![Block::default()],
+            shared,
+            captured: Vec::new(),
+            top_level,
+        }
+    }
+
+    // a new local in the innermost block, or the one the block already has
+    fn declare(&mut self, name: &str) -> Result<Local, String> {
+        let block = self.blocks.last_mut().unwrap();
+        if let Some((_, local)) = block.names.iter().find(|(n, _)| n == name) {
+            return Ok(*local);
+        }
+        let local = if self.shared.contains(name) {
+            self.proto.cells += 1;
+            Local::Cell(index(self.proto.cells - 1)?)
+        } else {
+            self.proto.slots += 1;
+            Local::Slot(index(self.proto.slots - 1)?)
+        };
+        block.names.push((ts(name), local));
+        Ok(local)
+    }
+
+    fn hoist(&mut self, code: &[Code]) -> Result<(), String> {
+        let mut names = Vec::new();
+        code.iter().for_each(|c| function_names(c, &mut names));
+        for name in names {
+            self.declare(&name)?;
+        }
+        Ok(())
+    }
+
+    fn local(&self, name: &str) -> Option<Local> {
+        self.blocks
+            .iter()
+            .rev()
+            .find_map(|block| block.names.iter().rev().find(|(n, _)| n == name))
+            .map(|(_, local)| *local)
+    }
+}
+
+// how compiled code gets to a name
+enum Access {
+    Local(Local),
+    Capture(u32),
+    Global,
+}
+
+impl Compiler {
+    fn function(&mut self) -> &mut Function {
+        self.functions.last_mut().unwrap()
+    }
+
+    fn emit(&mut self, op: Op) -> usize {
+        let proto = &mut self.function().proto;
+        proto.ops.push(op);
+        proto.locations.push(None);
+        proto.ops.len() - 1
+    }
+
+    fn here(&mut self) -> Result<u32, String> {
+        index(self.function().proto.ops.len())
+    }
+
+    // points the jump at the next instruction
+    fn patch(&mut self, at: usize) -> Result<(), String> {
+        let target = self.here()?;
+        match &mut self.function().proto.ops[at] {
+            Op::Jump(to) | Op::IfFalse(to) | Op::WhileFalse(to) => *to = target,
+            _ => unreachable!("only jumps are patched"),
+        }
+        Ok(())
+    }
+
+    fn constant(&mut self, value: Value) -> Result<(), String> {
+        let constants = &mut self.function().proto.constants;
+        let i = match constants.iter().position(|c| *c == value) {
+            Some(i) => i,
+            None => {
+                constants.push(value);
+                constants.len() - 1
+            }
+        };
+        self.emit(Op::Constant(index(i)?));
+        Ok(())
+    }
+
+    fn name(&mut self, name: &str) -> Result<u32, String> {
+        let names = &mut self.function().proto.names;
+        match names.iter().position(|n| n == name) {
+            Some(i) => index(i),
+            None => {
+                names.push(ts(name));
+                index(names.len() - 1)
+            }
+        }
+    }
+
+    // finds the name in the function at depth or the ones around it
+    // names from around it become captures of every function in between
+    fn resolve_at(&mut self, depth: usize, name: &str) -> Access {
+        let function = &self.functions[depth];
+        if let Some(local) = function.local(name) {
+            return Access::Local(local);
+        }
+        if let Some(i) = function.captured.iter().position(|n| n == name) {
+            return Access::Capture(i as u32);
+        }
+        if depth == 0 {
+            return Access::Global;
+        }
+        let capture = match self.resolve_at(depth - 1, name) {
+            Access::Local(Local::Cell(i)) => Capture::Cell(i),
+            Access::Capture(i) => Capture::Outer(i),
+            // every name used in a nested function is given a cell
+            Access::Local(Local::Slot(_)) | Access::Global => return Access::Global,
+        };
+        let function = &mut self.functions[depth];
+        function.captured.push(ts(name));
+        function.proto.captures.push(capture);
+        Access::Capture(function.proto.captures.len() as u32 - 1)
+    }
+
+    fn resolve(&mut self, name: &str) -> Access {
+        self.resolve_at(self.functions.len() - 1, name)
+    }
+
+    fn load(&mut self, name: &str) -> Result<(), String> {
+        let op = match self.resolve(name) {
+            Access::Local(Local::Slot(i)) => Op::LoadSlot(i),
+            Access::Local(Local::Cell(i)) => Op::LoadCell(i),
+            Access::Capture(i) => Op::LoadCapture(i),
+            Access::Global => Op::LoadGlobal(self.name(name)?),
+        };
+        self.emit(op);
+        Ok(())
+    }
+
+    // at is where the code was written, NOWHERE for code without a source
+    fn expr(&mut self, code: &Code, at: &Located) -> Result<(), String> {
+        match code {
+            Code::Identifier(name) => self.load(name),
+            Code::List(list) => match list.first() {
+                None => {
+                    self.emit(Op::Nothing);
+                    Ok(())
+                }
+                Some(Code::Identifier(name)) => match name.as_str() {
+                    "var" | "set" => self.binding(name, &list[1..], at),
+                    "pgm" => self.pgm(&list[1..], at),
+                    _ => self.call(name, &list[1..], at),
+                },
+                // lists that don't start with a function name are data
+                Some(_) => {
+                    for (i, item) in list.iter().enumerate() {
+                        self.expr(item, at.item(i))?;
+                    }
+                    self.emit(Op::MakeList(index(list.len())?));
+                    Ok(())
+                }
+            },
+            Code::Map(map) if is_form(map, "if") => self.if_form(map, at),
+            Code::Map(map) if is_form(map, "while") => self.while_form(map, at),
+            Code::Map(map) if is_form(map, "fn") => self.fn_form(code, map, at),
+            Code::Map(map) if is_form(map, "try") => self.try_form(map, at),
+            // other maps are data with their values evaluated
+            Code::Map(map) => {
+                for (i, (k, v)) in map.iter().enumerate() {
+                    self.constant(Value::from_code(k))?;
+                    self.expr(v, at.item(i))?;
+                }
+                self.emit(Op::MakeMap(index(map.len())?));
+                Ok(())
+            }
+            _ => self.constant(Value::from_code(code)),
+        }
+    }
+
+    // a body that is a list of statements runs each in turn giving back the last
+    fn body(&mut self, code: &Code, at: &Located) -> Result<(), String> {
+        match code {
+            Code::List(items) if !matches!(items.first(), None | Some(Code::Identifier(_))) => {
+                self.sequence(items, at, 0)
+            }
+            _ => self.expr(code, at),
+        }
+    }
+
+    // at is the list the items are in, starting from its item first
+    fn sequence(&mut self, items: &[Code], at: &Located, first: usize) -> Result<(), String> {
+        if items.is_empty() {
+            self.emit(Op::Nothing);
+        }
+        for (i, item) in items.iter().enumerate() {
+            if i > 0 {
+                self.emit(Op::Pop);
+            }
+            self.expr(item, at.item(first + i))?;
+        }
+        Ok(())
+    }
+
+    fn binding(&mut self, form: &str, args: &[Code], at: &Located) -> Result<(), String> {
+        let (name, value) = match args {
+            [Code::Identifier(name), value] => (name, value),
+            _ => return Err(format!("{} expects a name and a value", form)),
+        };
+        self.expr(value, at.item(2))?;
+        let function = self.function();
+        let access = match form {
+            "var" if function.top_level && function.blocks.len() == 1 => Access::Global,
+            "var" => Access::Local(function.declare(name)?),
+            _ => self.resolve(name),
+        };
+        let op = match access {
+            Access::Local(Local::Slot(i)) => Op::StoreSlot(i),
+            Access::Local(Local::Cell(i)) => Op::StoreCell(i),
+            Access::Capture(i) => Op::StoreCapture(i),
+            Access::Global if form == "var" => Op::DefineGlobal(self.name(name)?),
+            Access::Global => Op::SetGlobal(self.name(name)?),
+        };
+        self.emit(op);
+        Ok(())
+    }
+
+    // a new scope each time it is run, so closures made in it get their own cells
+    fn pgm(&mut self, items: &[Code], at: &Located) -> Result<(), String> {
+        let fresh = self.emit(Op::FreshCells(0, 0));
+        let first = self.function().proto.cells;
+        self.function().blocks.push(Block::default());
+        self.function().hoist(items)?;
+        self.sequence(items, at, 1)?;
+        let function = self.function();
+        function.blocks.pop();
+        let count = function.proto.cells - first;
+        function.proto.ops[fresh] = Op::FreshCells(index(first)?, index(count)?);
+        Ok(())
+    }
+
+    fn call(&mut self, name: &str, args: &[Code], at: &Located) -> Result<(), String> {
+        for (i, arg) in args.iter().enumerate() {
+            self.expr(arg, at.item(i + 1))?;
+        }
+        let argc = index(args.len())?;
+        let op = match self.resolve(name) {
+            Access::Global => {
+                let name = self.name(name)?;
+                self.emit(Op::Call(name, argc))
+            }
+            _ => {
+                self.load(name)?;
+                let name = self.name(name)?;
+                self.emit(Op::CallValue(name, argc))
+            }
+        };
+        self.function().proto.locations[op] = at.at;
+        Ok(())
+    }
+
+    fn if_form(&mut self, map: &Map<Code, Code>, at: &Located) -> Result<(), String> {
+        let cond = field(map, "c").ok_or("if needs a condition c:")?;
+        self.expr(cond, at.field(map, "c"))?;
+        let to_else = self.emit(Op::IfFalse(0));
+        match field(map, "do") {
+            Some(body) => self.body(body, at.field(map, "do"))?,
+            None => {
+                self.emit(Op::Nothing);
+            }
+        }
+        let to_end = self.emit(Op::Jump(0));
+        self.patch(to_else)?;
+        match field(map, "else") {
+            Some(body) => self.body(body, at.field(map, "else"))?,
+            None => {
+                self.emit(Op::Nothing);
+            }
+        }
+        self.patch(to_end)
+    }
+
+    fn while_form(&mut self, map: &Map<Code, Code>, at: &Located) -> Result<(), String> {
+        let cond = field(map, "c").ok_or("while needs a condition c:")?;
+        let start = self.here()?;
+        self.expr(cond, at.field(map, "c"))?;
+        let to_end = self.emit(Op::WhileFalse(0));
+        if let Some(body) = field(map, "do") {
+            self.body(body, at.field(map, "do"))?;
+            self.emit(Op::Pop);
+        }
+        self.emit(Op::Loop(start));
+        self.patch(to_end)?;
+        self.emit(Op::Nothing);
+        Ok(())
+    }
+
+    // the parts are compiled from the code in the try rather than the fn forms made of copies,
+    // so calls in them keep where they were written
+    fn try_form(&mut self, map: &Map<Code, Code>, at: &Located) -> Result<(), String> {
+        let (body, handler, finally) = try_parts(map)?;
+        let none = Code::List(Vec::new());
+        let part = |name| (field(map, name).unwrap_or(&none), at.field(map, name));
+        self.closure(&body, (&none, &NOWHERE), None, part("do"))?;
+        if let Some(handler) = &handler {
+            self.closure(handler, part("catch"), None, part("handle"))?;
+        }
+        if let Some(finally) = &finally {
+            self.closure(finally, (&none, &NOWHERE), None, part("finally"))?;
+        }
+        self.emit(Op::Try(handler.is_some(), finally.is_some()));
+        Ok(())
+    }
+
+    // {fn a: [[name type default]] r: type c: body}
+    fn fn_form(&mut self, code: &Code, map: &Map<Code, Code>, at: &Located) -> Result<(), String> {
+        let none = Code::List(Vec::new());
+        let body = field(map, "c").ok_or("fn needs a body c:")?;
+        let specs = field(map, "a").unwrap_or(&none);
+        self.closure(
+            code,
+            (specs, at.field(map, "a")),
+            field(map, "r"),
+            (body, at.field(map, "c")),
+        )
+    }
+
+    // the specs and the body come with where they were written
+    // defaults are compiled into the function around it, they are worked out when the closure is made
+    fn closure(
+        &mut self,
+        code: &Code,
+        (specs, specs_at): (&Code, &Located),
+        returns: Option<&Code>,
+        (body, body_at): (&Code, &Located),
+    ) -> Result<(), String> {
+        let specs = match specs {
+            Code::List(specs) => specs.as_slice(),
+            other => return Err(format!("fn arguments must be a list but got {}", other)),
+        };
+        let mut args = Vec::new();
+        for (i, spec) in specs.iter().enumerate() {
+            let (name, typ, default) = match spec {
+                Code::Identifier(name) => (name, None, None),
+                Code::List(items) => match items.as_slice() {
+                    [Code::Identifier(name)] => (name, None, None),
+                    [Code::Identifier(name), typ] => (name, Some(typ), None),
+                    [Code::Identifier(name), typ, default] => (name, Some(typ), Some(default)),
+                    _ => return Err(format!("Invalid fn argument {}", spec)),
+                },
+                _ => return Err(format!("Invalid fn argument {}", spec)),
+            };
+            let typ = typ.map(parse_type).transpose()?.unwrap_or(LampType::Code);
+            if let Some(default) = default {
+                self.expr(default, specs_at.item(i).item(2))?;
+            }
+            args.push((name, typ, default.is_some()));
+        }
+        let returns = returns.map(parse_type).transpose()?;
+
+        let mut shared = HashSet::new();
+        nested_names(body, false, &mut shared);
+        let mut function = Function::new(code.clone(), shared, false);
+        function.proto.returns = returns;
+        for (name, typ, has_default) in args {
+            let local = function.declare(name)?;
+            function.proto.params.push(Param {
+                name: name.clone(),
+                typ,
+                has_default,
+                local,
+            });
+        }
+        function.hoist(std::slice::from_ref(body))?;
+        self.functions.push(function);
+        self.body(body, body_at)?;
+        let mut function = self.functions.pop().unwrap();
+        tail_calls(&mut function.proto.ops);
+
+        let defaults = function
+            .proto
+            .params
+            .iter()
+            .filter(|p| p.has_default)
+            .count();
+        let protos = &mut self.function().proto.protos;
+        protos.push(Rc::new(function.proto));
+        let proto = index(protos.len() - 1)?;
+        self.emit(Op::Closure(proto, index(defaults)?));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ops(code: &str) -> Vec<Op> {
+        compile(&Code::from_str(code).unwrap()[0])
+            .unwrap()
+            .proto
+            .ops
+            .clone()
+    }
+
+    #[test]
+    fn test_locals() {
+        // n is only used by this function so it is a slot, globals are looked up by name
+        let program = compile(&Code::from_str("{fn a: [n] c: [+ n x]}").unwrap()[0]).unwrap();
+        assert_eq!(program.proto.ops, vec![Op::Closure(0, 0)]);
+        let inner = &program.proto.protos[0];
+        assert_eq!(inner.params[0].local, Local::Slot(0));
+        assert_eq!(
+            inner.ops,
+            vec![Op::LoadSlot(0), Op::LoadGlobal(0), Op::TailCall(1, 2)]
+        );
+        assert_eq!(inner.names, vec!["x".to_string(), "+".to_string()]);
+
+        // n is shared with the inner function through a cell
+        let program =
+            compile(&Code::from_str("{fn a: [n] c: {fn c: [set n [+ n 1]]}}").unwrap()[0]).unwrap();
+        let outer = &program.proto.protos[0];
+        assert_eq!(outer.params[0].local, Local::Cell(0));
+        assert_eq!(outer.protos[0].captures, vec![Capture::Cell(0)]);
+        assert_eq!(
+            outer.protos[0].ops,
+            vec![
+                Op::LoadCapture(0),
+                Op::Constant(0),
+                Op::Call(0, 2),
+                Op::StoreCapture(0)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tail_calls() {
+        let program = compile(
+            &Code::from_str("{fn a: [n] c: {if c: [< n 1] do: [f n] else: [[var x [g n]] [h x]]}}")
+                .unwrap()[0],
+        )
+        .unwrap();
+        let inner = &program.proto.protos[0];
+        let calls: Vec<_> = inner
+            .ops
+            .iter()
+            .filter_map(|op| match *op {
+                Op::Call(name, _) => Some((inner.names[name as usize].as_str(), false)),
+                Op::TailCall(name, _) => Some((inner.names[name as usize].as_str(), true)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            calls,
+            vec![("<", false), ("f", true), ("g", false), ("h", true)]
+        );
+
+        // the top level has no frame to replace
+        assert_eq!(ops("[f 1]"), vec![Op::Constant(0), Op::Call(0, 1)]);
+    }
+
+    #[test]
+    fn test_control_flow() {
+        assert_eq!(
+            ops("{if c: true do: 1 else: 2}"),
+            vec![
+                Op::LoadGlobal(0),
+                Op::IfFalse(4),
+                Op::Constant(0),
+                Op::Jump(5),
+                Op::Constant(1),
+            ]
+        );
+        assert_eq!(
+            ops("{while c: false do: [pgm [var i 1]]}"),
+            vec![
+                Op::LoadGlobal(0),
+                Op::WhileFalse(7),
+                Op::FreshCells(0, 0),
+                Op::Constant(0),
+                Op::StoreSlot(0),
+                Op::Pop,
+                Op::Loop(0),
+                Op::Nothing,
+            ]
+        );
+        assert_eq!(
+            compile(&Code::from_str("{if do: 1}").unwrap()[0]).unwrap_err(),
+            "if needs a condition c:"
+        );
+    }
+}
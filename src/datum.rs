@@ -1,25 +1,155 @@
-use std::vec;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt;
 use std::hash::Hash;
+use std::rc::Rc;
+use std::vec;
 
 use crate::code::Code;
 use crate::lamp_type::LampType;
 use crate::map::*;
 use crate::utils::ts;
+use crate::value::Value;
+
+// derive both on a type to map it to a lamp Struct or Enum
+pub use lamp_lang_derive::{FromDatum, ToDatum};
 
-#[derive(PartialEq, Eq)]
+// a value together with its type
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Datum {
     pub typ: LampType,
-    pub data: Code,
+    pub data: Value,
+}
+
+impl Datum {
+    // builds a datum after checking the data is valid for the type
+    pub fn new(typ: LampType, data: Value) -> Result<Datum, DatumError> {
+        data.check(&typ, "datum")?;
+        Ok(Datum { typ, data })
+    }
+
+    pub fn validate(&self) -> Result<(), DatumError> {
+        self.data.check(&self.typ, "datum")
+    }
+
+    // the data written as code, which fails for data holding host objects
+    pub fn code(&self) -> Result<Code, DatumError> {
+        self.data.to_code().map_err(|reason| DatumError::Invalid {
+            path: ts("datum"),
+            reason,
+        })
+    }
 }
 
 pub trait ToDatum {
     fn to_lamp_type() -> LampType;
     fn to_code(&self) -> Code;
+    fn to_value(&self) -> Value {
+        Value::from_code(&self.to_code())
+    }
     fn to_datum(&self) -> Datum {
         Datum {
             typ: Self::to_lamp_type(),
-            data: self.to_code(),
+            data: self.to_value(),
+        }
+    }
+}
+
+// why a datum couldn't be turned back into a rust value
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DatumError {
+    // the datum was declared as a different type
+    WrongType {
+        expected: Box<LampType>,
+        found: Box<LampType>,
+    },
+    // the code doesn't have the shape the type calls for
+    WrongShape {
+        expected: Box<LampType>,
+        found: Box<Code>,
+    },
+    // the integer doesn't fit in the rust type
+    OutOfRange {
+        expected: Box<LampType>,
+        found: i128,
+    },
+    InvalidUtf8(Vec<u8>),
+    // the data doesn't agree with the declared type at the path
+    Invalid {
+        path: String,
+        reason: String,
+    },
+}
+
+impl fmt::Display for DatumError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DatumError::WrongType { expected, found } => {
+                write!(
+                    f,
+                    "Expected a datum of type {:?} but found {:?}",
+                    expected, found
+                )
+            }
+            DatumError::WrongShape { expected, found } => {
+                write!(
+                    f,
+                    "Expected code for {:?} but found {}",
+                    expected,
+                    found.to_source()
+                )
+            }
+            DatumError::OutOfRange { expected, found } => {
+                write!(f, "{} is out of range for {:?}", found, expected)
+            }
+            DatumError::InvalidUtf8(bytes) => write!(f, "{:?} is not valid utf8", bytes),
+            DatumError::Invalid { path, reason } => write!(f, "{}: {}", path, reason),
+        }
+    }
+}
+
+impl From<DatumError> for String {
+    fn from(err: DatumError) -> String {
+        err.to_string()
+    }
+}
+
+pub trait FromDatum: ToDatum + Sized {
+    fn from_code(code: &Code) -> Result<Self, DatumError>;
+    fn from_datum(datum: &Datum) -> Result<Self, DatumError> {
+        if datum.typ != Self::to_lamp_type() {
+            return Err(DatumError::WrongType {
+                expected: Box::new(Self::to_lamp_type()),
+                found: Box::new(datum.typ.clone()),
+            });
         }
+        Self::from_value(&datum.data)
+    }
+    // types whose data is code go by way of the code, the rest read the value as it is
+    fn from_value(value: &Value) -> Result<Self, DatumError> {
+        let code = value.to_code().map_err(|reason| DatumError::Invalid {
+            path: ts("value"),
+            reason,
+        })?;
+        Self::from_code(&code)
+    }
+}
+
+// the error for code that doesn't fit the type T
+pub fn wrong_shape<T: ToDatum>(code: &Code) -> DatumError {
+    DatumError::WrongShape {
+        expected: Box::new(T::to_lamp_type()),
+        found: Box::new(code.clone()),
+    }
+}
+
+// the error for a value that doesn't fit the type T
+pub fn wrong_value<T: ToDatum>(value: &Value) -> DatumError {
+    match value.to_code() {
+        Ok(code) => wrong_shape::<T>(&code),
+        Err(reason) => DatumError::Invalid {
+            path: ts("value"),
+            reason,
+        },
     }
 }
 
@@ -73,17 +203,179 @@ impl ToDatum for char {
     }
 }
 
-impl<T: ToDatum> ToDatum for Vec<T> {
+impl ToDatum for bool {
+    fn to_lamp_type() -> LampType {
+        LampType::Bool
+    }
+
+    fn to_code(&self) -> Code {
+        Code::Identifier(ts(if *self { "true" } else { "false" }))
+    }
+}
+
+impl<T: ToDatum> ToDatum for [T] {
     fn to_lamp_type() -> LampType {
         LampType::Vector(Box::new(T::to_lamp_type()))
     }
-    
+
     fn to_code(&self) -> Code {
         Code::List(self.iter().map(|t| t.to_code()).collect())
     }
+
+    // item by item so host handles inside keep their objects
+    fn to_value(&self) -> Value {
+        Value::List(self.iter().map(|t| t.to_value()).collect())
+    }
 }
 
-impl ToDatum for String {
+impl<T: ToDatum, const N: usize> ToDatum for [T; N] {
+    fn to_lamp_type() -> LampType {
+        <[T]>::to_lamp_type()
+    }
+
+    fn to_code(&self) -> Code {
+        self.as_slice().to_code()
+    }
+
+    fn to_value(&self) -> Value {
+        self.as_slice().to_value()
+    }
+}
+
+impl<T: ToDatum> ToDatum for Vec<T> {
+    fn to_lamp_type() -> LampType {
+        <[T]>::to_lamp_type()
+    }
+
+    fn to_code(&self) -> Code {
+        self.as_slice().to_code()
+    }
+
+    fn to_value(&self) -> Value {
+        self.as_slice().to_value()
+    }
+}
+
+// references and smart pointers look the same as what they point to
+impl<T: ToDatum + ?Sized> ToDatum for &T {
+    fn to_lamp_type() -> LampType {
+        T::to_lamp_type()
+    }
+
+    fn to_code(&self) -> Code {
+        (**self).to_code()
+    }
+
+    fn to_value(&self) -> Value {
+        (**self).to_value()
+    }
+}
+
+impl<T: ToDatum + ?Sized> ToDatum for Box<T> {
+    fn to_lamp_type() -> LampType {
+        T::to_lamp_type()
+    }
+
+    fn to_code(&self) -> Code {
+        (**self).to_code()
+    }
+
+    fn to_value(&self) -> Value {
+        (**self).to_value()
+    }
+}
+
+impl<T: ToDatum + ?Sized> ToDatum for Rc<T> {
+    fn to_lamp_type() -> LampType {
+        T::to_lamp_type()
+    }
+
+    fn to_code(&self) -> Code {
+        (**self).to_code()
+    }
+
+    fn to_value(&self) -> Value {
+        (**self).to_value()
+    }
+}
+
+macro_rules! tuple_datum {
+    ( $( $name:ident $idx:tt ),+ ) => {
+        impl<$( $name: ToDatum ),+> ToDatum for ( $( $name, )+ ) {
+            fn to_lamp_type() -> LampType {
+                LampType::List(vec![ $( $name::to_lamp_type() ),+ ])
+            }
+
+            fn to_code(&self) -> Code {
+                Code::List(vec![ $( self.$idx.to_code() ),+ ])
+            }
+
+            fn to_value(&self) -> Value {
+                Value::List([ $( self.$idx.to_value() ),+ ].into_iter().collect())
+            }
+        }
+
+        impl<$( $name: FromDatum ),+> FromDatum for ( $( $name, )+ ) {
+            fn from_code(code: &Code) -> Result<Self, DatumError> {
+                let len = [ $( stringify!($idx) ),+ ].len();
+                match code {
+                    Code::List(list) if list.len() == len => {
+                        Ok(( $( $name::from_code(&list[$idx])?, )+ ))
+                    }
+                    _ => Err(wrong_shape::<Self>(code)),
+                }
+            }
+
+            fn from_value(value: &Value) -> Result<Self, DatumError> {
+                let len = [ $( stringify!($idx) ),+ ].len();
+                match value {
+                    Value::List(list) if list.len() == len => {
+                        Ok(( $( $name::from_value(&list[$idx])?, )+ ))
+                    }
+                    _ => Err(wrong_value::<Self>(value)),
+                }
+            }
+        }
+    };
+}
+
+// the empty tuple is the empty list, the argument list of a function without arguments
+impl ToDatum for () {
+    fn to_lamp_type() -> LampType {
+        LampType::List(vec![])
+    }
+
+    fn to_code(&self) -> Code {
+        Code::List(vec![])
+    }
+}
+
+impl FromDatum for () {
+    fn from_code(code: &Code) -> Result<Self, DatumError> {
+        match code {
+            Code::List(list) if list.is_empty() => Ok(()),
+            _ => Err(wrong_shape::<Self>(code)),
+        }
+    }
+
+    fn from_value(value: &Value) -> Result<Self, DatumError> {
+        match value {
+            Value::List(list) if list.is_empty() => Ok(()),
+            _ => Err(wrong_value::<Self>(value)),
+        }
+    }
+}
+
+tuple_datum!(A 0);
+tuple_datum!(A 0, B 1);
+tuple_datum!(A 0, B 1, C 2);
+tuple_datum!(A 0, B 1, C 2, D 3);
+tuple_datum!(A 0, B 1, C 2, D 3, E 4);
+tuple_datum!(A 0, B 1, C 2, D 3, E 4, F 5);
+tuple_datum!(A 0, B 1, C 2, D 3, E 4, F 5, G 6);
+tuple_datum!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7);
+
+impl ToDatum for str {
     fn to_lamp_type() -> LampType {
         LampType::Struct(map![
             {ts("data"), LampType::Vector(Box::new(LampType::U8))}
@@ -92,11 +384,21 @@ impl ToDatum for String {
 
     fn to_code(&self) -> Code {
         Code::Map(map![
-            {Code::Identifier(ts("data")), Code::List(self.as_bytes().iter().map(|c| c.to_code()).collect())}
+            {Code::Identifier(ts("data")), self.as_bytes().to_code()}
         ])
     }
 }
 
+impl ToDatum for String {
+    fn to_lamp_type() -> LampType {
+        str::to_lamp_type()
+    }
+
+    fn to_code(&self) -> Code {
+        self.as_str().to_code()
+    }
+}
+
 impl<T: ToDatum> ToDatum for Option<T> {
     fn to_lamp_type() -> LampType {
         LampType::Enum(map![
@@ -127,7 +429,406 @@ impl<K: Eq + Hash + Ord + ToDatum, V: PartialEq + Hash + Ord + ToDatum> ToDatum
     }
 }
 
+fn integer_from<T: ToDatum + TryFrom<i128>>(num: i128) -> Result<T, DatumError> {
+    T::try_from(num).map_err(|_| DatumError::OutOfRange {
+        expected: Box::new(T::to_lamp_type()),
+        found: num,
+    })
+}
+
+fn integer_from_code<T: ToDatum + TryFrom<i128>>(code: &Code) -> Result<T, DatumError> {
+    match code {
+        &Code::Integer(num) => integer_from(num),
+        _ => Err(wrong_shape::<T>(code)),
+    }
+}
+
+fn integer_from_value<T: ToDatum + TryFrom<i128>>(value: &Value) -> Result<T, DatumError> {
+    match value {
+        &Value::Integer(num) => integer_from(num),
+        _ => Err(wrong_value::<T>(value)),
+    }
+}
+
+impl FromDatum for u8 {
+    fn from_code(code: &Code) -> Result<Self, DatumError> {
+        integer_from_code(code)
+    }
+
+    fn from_value(value: &Value) -> Result<Self, DatumError> {
+        integer_from_value(value)
+    }
+}
+
+impl FromDatum for u64 {
+    fn from_code(code: &Code) -> Result<Self, DatumError> {
+        integer_from_code(code)
+    }
+
+    fn from_value(value: &Value) -> Result<Self, DatumError> {
+        integer_from_value(value)
+    }
+}
+
+impl FromDatum for i64 {
+    fn from_code(code: &Code) -> Result<Self, DatumError> {
+        integer_from_code(code)
+    }
+
+    fn from_value(value: &Value) -> Result<Self, DatumError> {
+        integer_from_value(value)
+    }
+}
+
+impl FromDatum for f64 {
+    fn from_code(code: &Code) -> Result<Self, DatumError> {
+        match code {
+            &Code::Float(bits) => Ok(f64::from_bits(bits)),
+            _ => Err(wrong_shape::<f64>(code)),
+        }
+    }
+
+    fn from_value(value: &Value) -> Result<Self, DatumError> {
+        match value {
+            &Value::Float(bits) => Ok(f64::from_bits(bits)),
+            _ => Err(wrong_value::<f64>(value)),
+        }
+    }
+}
+
+impl FromDatum for char {
+    fn from_code(code: &Code) -> Result<Self, DatumError> {
+        match code {
+            &Code::Character(c) => Ok(c),
+            _ => Err(wrong_shape::<char>(code)),
+        }
+    }
+
+    fn from_value(value: &Value) -> Result<Self, DatumError> {
+        match value {
+            &Value::Character(c) => Ok(c),
+            _ => Err(wrong_value::<char>(value)),
+        }
+    }
+}
+
+impl<T: FromDatum> FromDatum for Vec<T> {
+    fn from_code(code: &Code) -> Result<Self, DatumError> {
+        match code {
+            Code::List(list) => list.iter().map(T::from_code).collect(),
+            _ => Err(wrong_shape::<Vec<T>>(code)),
+        }
+    }
+
+    fn from_value(value: &Value) -> Result<Self, DatumError> {
+        match value {
+            Value::List(list) => list.iter().map(T::from_value).collect(),
+            _ => Err(wrong_value::<Vec<T>>(value)),
+        }
+    }
+}
+
+impl FromDatum for String {
+    fn from_code(code: &Code) -> Result<Self, DatumError> {
+        let data = match code {
+            Code::Map(map) if map.len() == 1 => map.get(&Code::Identifier(ts("data"))),
+            _ => None,
+        };
+        let bytes = Vec::<u8>::from_code(data.ok_or_else(|| wrong_shape::<String>(code))?)?;
+        String::from_utf8(bytes).map_err(|err| DatumError::InvalidUtf8(err.into_bytes()))
+    }
+
+    fn from_value(value: &Value) -> Result<Self, DatumError> {
+        let data = match value {
+            Value::Map(map) if map.len() == 1 => map.get(&Value::symbol("data")),
+            _ => None,
+        };
+        let bytes = Vec::<u8>::from_value(data.ok_or_else(|| wrong_value::<String>(value))?)?;
+        String::from_utf8(bytes).map_err(|err| DatumError::InvalidUtf8(err.into_bytes()))
+    }
+}
+
+impl<T: FromDatum> FromDatum for Option<T> {
+    fn from_code(code: &Code) -> Result<Self, DatumError> {
+        match code {
+            Code::List(list) => match list.as_slice() {
+                [Code::Identifier(tag), value] if tag == "Some" => Ok(Some(T::from_code(value)?)),
+                [Code::Identifier(tag)] if tag == "None" => Ok(None),
+                _ => Err(wrong_shape::<Option<T>>(code)),
+            },
+            _ => Err(wrong_shape::<Option<T>>(code)),
+        }
+    }
+
+    fn from_value(value: &Value) -> Result<Self, DatumError> {
+        match tagged(value) {
+            Some(("Some", Some(payload))) => Ok(Some(T::from_value(payload)?)),
+            Some(("None", None)) => Ok(None),
+            _ => Err(wrong_value::<Option<T>>(value)),
+        }
+    }
+}
+
+impl<K, V> FromDatum for Map<K, V>
+where
+    K: Eq + Hash + Ord + FromDatum,
+    V: PartialEq + Hash + Ord + FromDatum,
+{
+    fn from_code(code: &Code) -> Result<Self, DatumError> {
+        match code {
+            Code::Map(map) => {
+                let mut out = Map::new();
+                for (key, val) in map.iter() {
+                    out.insert(K::from_code(key)?, V::from_code(val)?);
+                }
+                Ok(out)
+            }
+            _ => Err(wrong_shape::<Map<K, V>>(code)),
+        }
+    }
+
+    fn from_value(value: &Value) -> Result<Self, DatumError> {
+        pairs_from_value(value)
+    }
+}
+
+impl<T: ToDatum, E: ToDatum> ToDatum for Result<T, E> {
+    fn to_lamp_type() -> LampType {
+        LampType::Enum(map![
+            {ts("Ok"), Some(T::to_lamp_type())},
+            {ts("Err"), Some(E::to_lamp_type())},
+        ])
+    }
+
+    fn to_code(&self) -> Code {
+        match self {
+            Ok(t) => Code::List(vec![Code::Identifier(ts("Ok")), t.to_code()]),
+            Err(e) => Code::List(vec![Code::Identifier(ts("Err")), e.to_code()]),
+        }
+    }
+}
+
+fn pairs_to_code<'a, K: ToDatum + 'a, V: ToDatum + 'a>(
+    pairs: impl Iterator<Item = (&'a K, &'a V)>,
+) -> Code {
+    let mut code = Map::new();
+    for (key, val) in pairs {
+        code.insert(key.to_code(), val.to_code());
+    }
+    Code::Map(code)
+}
+
+impl<K: ToDatum, V: ToDatum, S> ToDatum for HashMap<K, V, S> {
+    fn to_lamp_type() -> LampType {
+        LampType::Dict(Box::new(K::to_lamp_type()), Box::new(V::to_lamp_type()))
+    }
+
+    fn to_code(&self) -> Code {
+        pairs_to_code(self.iter())
+    }
+}
+
+impl<K: ToDatum, V: ToDatum> ToDatum for BTreeMap<K, V> {
+    fn to_lamp_type() -> LampType {
+        LampType::Dict(Box::new(K::to_lamp_type()), Box::new(V::to_lamp_type()))
+    }
+
+    fn to_code(&self) -> Code {
+        pairs_to_code(self.iter())
+    }
+}
+
+// sets are vectors of their elements in sorted order
+fn set_to_code<'a, T: ToDatum + 'a>(items: impl Iterator<Item = &'a T>) -> Code {
+    let mut items: Vec<Code> = items.map(|t| t.to_code()).collect();
+    items.sort();
+    Code::List(items)
+}
+
+impl<T: ToDatum, S> ToDatum for HashSet<T, S> {
+    fn to_lamp_type() -> LampType {
+        LampType::Vector(Box::new(T::to_lamp_type()))
+    }
+
+    fn to_code(&self) -> Code {
+        set_to_code(self.iter())
+    }
+}
+
+impl<T: ToDatum> ToDatum for BTreeSet<T> {
+    fn to_lamp_type() -> LampType {
+        LampType::Vector(Box::new(T::to_lamp_type()))
+    }
+
+    fn to_code(&self) -> Code {
+        set_to_code(self.iter())
+    }
+}
+
+impl FromDatum for bool {
+    fn from_code(code: &Code) -> Result<Self, DatumError> {
+        match code {
+            Code::Identifier(b) if b == "true" => Ok(true),
+            Code::Identifier(b) if b == "false" => Ok(false),
+            _ => Err(wrong_shape::<bool>(code)),
+        }
+    }
+
+    fn from_value(value: &Value) -> Result<Self, DatumError> {
+        match value {
+            Value::Symbol(b) if &**b == "true" => Ok(true),
+            Value::Symbol(b) if &**b == "false" => Ok(false),
+            _ => Err(wrong_value::<bool>(value)),
+        }
+    }
+}
+
+impl<T: FromDatum, const N: usize> FromDatum for [T; N] {
+    fn from_code(code: &Code) -> Result<Self, DatumError> {
+        match code {
+            Code::List(list) if list.len() == N => {
+                let items = list
+                    .iter()
+                    .map(T::from_code)
+                    .collect::<Result<Vec<T>, _>>()?;
+                items.try_into().map_err(|_| wrong_shape::<Self>(code))
+            }
+            _ => Err(wrong_shape::<Self>(code)),
+        }
+    }
+
+    fn from_value(value: &Value) -> Result<Self, DatumError> {
+        match value {
+            Value::List(list) if list.len() == N => {
+                let items = list
+                    .iter()
+                    .map(T::from_value)
+                    .collect::<Result<Vec<T>, _>>()?;
+                items.try_into().map_err(|_| wrong_value::<Self>(value))
+            }
+            _ => Err(wrong_value::<Self>(value)),
+        }
+    }
+}
+
+impl<T: FromDatum> FromDatum for Box<T> {
+    fn from_code(code: &Code) -> Result<Self, DatumError> {
+        T::from_code(code).map(Box::new)
+    }
+
+    fn from_value(value: &Value) -> Result<Self, DatumError> {
+        T::from_value(value).map(Box::new)
+    }
+}
+
+impl<T: FromDatum> FromDatum for Rc<T> {
+    fn from_code(code: &Code) -> Result<Self, DatumError> {
+        T::from_code(code).map(Rc::new)
+    }
+
+    fn from_value(value: &Value) -> Result<Self, DatumError> {
+        T::from_value(value).map(Rc::new)
+    }
+}
+
+impl<T: FromDatum, E: FromDatum> FromDatum for Result<T, E> {
+    fn from_code(code: &Code) -> Result<Self, DatumError> {
+        match code {
+            Code::List(list) => match list.as_slice() {
+                [Code::Identifier(tag), value] if tag == "Ok" => Ok(Ok(T::from_code(value)?)),
+                [Code::Identifier(tag), value] if tag == "Err" => Ok(Err(E::from_code(value)?)),
+                _ => Err(wrong_shape::<Self>(code)),
+            },
+            _ => Err(wrong_shape::<Self>(code)),
+        }
+    }
+
+    fn from_value(value: &Value) -> Result<Self, DatumError> {
+        match tagged(value) {
+            Some(("Ok", Some(payload))) => Ok(Ok(T::from_value(payload)?)),
+            Some(("Err", Some(payload))) => Ok(Err(E::from_value(payload)?)),
+            _ => Err(wrong_value::<Self>(value)),
+        }
+    }
+}
+
+// the tag and payload of an enum value, [Tag] or [Tag payload]
+pub fn tagged(value: &Value) -> Option<(&str, Option<&Value>)> {
+    match value {
+        Value::List(list) if list.len() <= 2 => match list.get(0) {
+            Some(Value::Symbol(tag)) => Some((tag, list.get(1))),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn pairs_from_code<K: FromDatum, V: FromDatum, M: ToDatum + FromIterator<(K, V)>>(
+    code: &Code,
+) -> Result<M, DatumError> {
+    match code {
+        Code::Map(map) => map
+            .iter()
+            .map(|(key, val)| Ok((K::from_code(key)?, V::from_code(val)?)))
+            .collect(),
+        _ => Err(wrong_shape::<M>(code)),
+    }
+}
+
+fn pairs_from_value<K: FromDatum, V: FromDatum, M: ToDatum + FromIterator<(K, V)>>(
+    value: &Value,
+) -> Result<M, DatumError> {
+    match value {
+        Value::Map(map) => map
+            .iter()
+            .map(|(key, val)| Ok((K::from_value(key)?, V::from_value(val)?)))
+            .collect(),
+        _ => Err(wrong_value::<M>(value)),
+    }
+}
+
+impl<K: FromDatum + Eq + Hash, V: FromDatum> FromDatum for HashMap<K, V> {
+    fn from_code(code: &Code) -> Result<Self, DatumError> {
+        pairs_from_code(code)
+    }
+
+    fn from_value(value: &Value) -> Result<Self, DatumError> {
+        pairs_from_value(value)
+    }
+}
+
+impl<K: FromDatum + Ord, V: FromDatum> FromDatum for BTreeMap<K, V> {
+    fn from_code(code: &Code) -> Result<Self, DatumError> {
+        pairs_from_code(code)
+    }
+
+    fn from_value(value: &Value) -> Result<Self, DatumError> {
+        pairs_from_value(value)
+    }
+}
+
+impl<T: FromDatum + Eq + Hash> FromDatum for HashSet<T> {
+    fn from_code(code: &Code) -> Result<Self, DatumError> {
+        Ok(Vec::<T>::from_code(code)?.into_iter().collect())
+    }
+
+    fn from_value(value: &Value) -> Result<Self, DatumError> {
+        Ok(Vec::<T>::from_value(value)?.into_iter().collect())
+    }
+}
+
+impl<T: FromDatum + Ord> FromDatum for BTreeSet<T> {
+    fn from_code(code: &Code) -> Result<Self, DatumError> {
+        Ok(Vec::<T>::from_code(code)?.into_iter().collect())
+    }
+
+    fn from_value(value: &Value) -> Result<Self, DatumError> {
+        Ok(Vec::<T>::from_value(value)?.into_iter().collect())
+    }
+}
+
 #[cfg(test)]
+#[allow(clippy::approx_constant)]
 mod tests {
     use super::*;
 
@@ -136,4 +837,285 @@ mod tests {
         assert_eq!(f64::to_lamp_type(), LampType::F64);
         assert_eq!(3.14.to_code(), Code::Float(3.14_f64.to_bits()))
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_from_datum_round_trip() {
+        assert_eq!(u8::from_datum(&200u8.to_datum()), Ok(200));
+        assert_eq!(i64::from_datum(&(-7i64).to_datum()), Ok(-7));
+        assert_eq!(f64::from_datum(&2.5.to_datum()), Ok(2.5));
+        assert_eq!(char::from_datum(&'x'.to_datum()), Ok('x'));
+        assert_eq!(String::from_datum(&ts("héllo").to_datum()), Ok(ts("héllo")));
+        let v = vec![Some(1u64), None, Some(3)];
+        assert_eq!(Vec::<Option<u64>>::from_datum(&v.to_datum()), Ok(v));
+        let m: Map<String, Vec<char>> = map![{ts("a"), vec!['b']}, {ts("c"), vec![]}];
+        assert_eq!(Map::from_datum(&m.to_datum()), Ok(m));
+    }
+
+    #[test]
+    fn test_from_datum_errors() {
+        assert_eq!(
+            u8::from_datum(&300u64.to_datum()),
+            Err(DatumError::WrongType {
+                expected: Box::new(LampType::U8),
+                found: Box::new(LampType::U64)
+            })
+        );
+        assert_eq!(
+            u8::from_code(&Code::Integer(300)),
+            Err(DatumError::OutOfRange {
+                expected: Box::new(LampType::U8),
+                found: 300
+            })
+        );
+        assert_eq!(
+            Option::<char>::from_code(&Code::List(vec![Code::Identifier(ts("Maybe"))])),
+            Err(DatumError::WrongShape {
+                expected: Box::new(Option::<char>::to_lamp_type()),
+                found: Box::new(Code::List(vec![Code::Identifier(ts("Maybe"))])),
+            })
+        );
+        assert_eq!(
+            String::from_code(&wrap_data(vec![0xFFu8].to_code())),
+            Err(DatumError::InvalidUtf8(vec![0xFF]))
+        );
+        assert_eq!(
+            u64::from_code(&Code::Character('a'))
+                .unwrap_err()
+                .to_string(),
+            "Expected code for U64 but found 'a'"
+        );
+    }
+
+    fn wrap_data(code: Code) -> Code {
+        Code::Map(map![{Code::Identifier(ts("data")), code}])
+    }
+
+    #[test]
+    fn test_more_to_datum() {
+        assert_eq!(true.to_code(), Code::Identifier(ts("true")));
+        assert_eq!(
+            (1u8, 'a').to_datum(),
+            Datum {
+                typ: LampType::List(vec![LampType::U8, LampType::Char]),
+                data: Value::from_code(&Code::List(vec![Code::Integer(1), Code::Character('a')])),
+            }
+        );
+        assert_eq!("hi".to_datum(), ts("hi").to_datum());
+        assert_eq!(Box::new(5u64).to_datum(), 5u64.to_datum());
+        assert_eq!(Rc::new('c').to_datum(), 'c'.to_datum());
+        assert_eq!([1u8, 2].to_datum(), vec![1u8, 2].to_datum());
+        assert_eq!([1u8, 2][..].to_datum(), vec![1u8, 2].to_datum());
+        let ok: Result<u8, String> = Ok(3);
+        assert_eq!(
+            ok.to_code(),
+            Code::List(vec![Code::Identifier(ts("Ok")), Code::Integer(3)])
+        );
+        let hash: HashMap<char, u8> = [('a', 1), ('b', 2)].into_iter().collect();
+        let btree: BTreeMap<char, u8> = hash.clone().into_iter().collect();
+        assert_eq!(hash.to_datum(), btree.to_datum());
+        let set: HashSet<u64> = [3, 1, 2].into_iter().collect();
+        assert_eq!(set.to_code(), vec![1u64, 2, 3].to_code());
+        assert_eq!(
+            set.to_datum(),
+            set.iter().collect::<BTreeSet<_>>().to_datum()
+        );
+    }
+
+    #[test]
+    fn test_more_from_datum() {
+        fn round_trip<T: FromDatum + PartialEq + fmt::Debug>(value: T) {
+            let datum = value.to_datum();
+            assert_eq!(datum.validate(), Ok(()));
+            assert_eq!(T::from_datum(&datum), Ok(value));
+        }
+        round_trip(false);
+        round_trip((1u8, 'a', ts("b"), (2.5, true)));
+        round_trip(Box::new(vec![Rc::new(1i64)]));
+        round_trip::<Result<u8, String>>(Err(ts("bad")));
+        round_trip([[1u8; 2]; 3]);
+        round_trip([('a', 1u8)].into_iter().collect::<HashMap<_, _>>());
+        round_trip([(1u64, ts("x"))].into_iter().collect::<BTreeMap<_, _>>());
+        round_trip([1u8, 4].into_iter().collect::<HashSet<_>>());
+        round_trip(['z', 'y'].into_iter().collect::<BTreeSet<_>>());
+        let short = vec![1u8].to_code();
+        assert_eq!(
+            <[u8; 3]>::from_code(&short),
+            Err(wrong_shape::<[u8; 3]>(&short))
+        );
+        assert_eq!(
+            <[u8; 3]>::from_value(&Value::from_code(&short)),
+            Err(wrong_shape::<[u8; 3]>(&short))
+        );
+    }
+
+    #[derive(Debug, PartialEq, ToDatum, FromDatum)]
+    struct Point {
+        x: i64,
+        #[lamp(rename = "why")]
+        y: i64,
+        #[lamp(skip)]
+        cache: Option<u8>,
+    }
+
+    #[derive(Debug, PartialEq, ToDatum, FromDatum)]
+    struct Pair(u8, char);
+
+    #[derive(Debug, PartialEq, ToDatum, FromDatum)]
+    enum Shape {
+        Empty,
+        Circle(f64),
+        Line(Point, Point),
+        #[lamp(rename = "Box")]
+        Rect {
+            corner: Point,
+            size: Vec<u64>,
+        },
+    }
+
+    #[derive(Debug, PartialEq, ToDatum, FromDatum)]
+    struct Tagged<T> {
+        tag: String,
+        value: T,
+    }
+
+    fn point(x: i64, y: i64) -> Point {
+        Point { x, y, cache: None }
+    }
+
+    #[test]
+    fn test_derive_struct() {
+        assert_eq!(
+            Point::to_lamp_type(),
+            LampType::Struct(map![
+                {ts("x"), LampType::I64},
+                {ts("why"), LampType::I64},
+            ])
+        );
+        let p = Point {
+            x: 1,
+            y: -2,
+            cache: Some(9),
+        };
+        assert_eq!(
+            p.to_code(),
+            Code::Map(map![
+                {Code::Identifier(ts("x")), Code::Integer(1)},
+                {Code::Identifier(ts("why")), Code::Integer(-2)},
+            ])
+        );
+        assert_eq!(Point::from_datum(&p.to_datum()), Ok(point(1, -2)));
+
+        assert_eq!(
+            Pair::to_lamp_type(),
+            LampType::List(vec![LampType::U8, LampType::Char])
+        );
+        assert_eq!(Pair::from_datum(&Pair(4, 'z').to_datum()), Ok(Pair(4, 'z')));
+
+        let tagged = Tagged {
+            tag: ts("t"),
+            value: vec![1u8],
+        };
+        assert_eq!(Tagged::from_datum(&tagged.to_datum()), Ok(tagged));
+    }
+
+    #[test]
+    fn test_derive_enum() {
+        assert_eq!(
+            Shape::to_lamp_type(),
+            LampType::Enum(map![
+                {ts("Empty"), None},
+                {ts("Circle"), Some(LampType::F64)},
+                {ts("Line"), Some(LampType::List(vec![Point::to_lamp_type(), Point::to_lamp_type()]))},
+                {ts("Box"), Some(LampType::Struct(map![
+                    {ts("corner"), Point::to_lamp_type()},
+                    {ts("size"), Vec::<u64>::to_lamp_type()},
+                ]))},
+            ])
+        );
+        assert_eq!(
+            Shape::Circle(1.5).to_code(),
+            Code::List(vec![Code::Identifier(ts("Circle")), 1.5.to_code()])
+        );
+        assert_eq!(
+            Shape::Empty.to_code(),
+            Code::List(vec![Code::Identifier(ts("Empty"))])
+        );
+        let shapes = vec![
+            Shape::Empty,
+            Shape::Circle(2.0),
+            Shape::Line(point(0, 0), point(3, 4)),
+            Shape::Rect {
+                corner: point(1, 1),
+                size: vec![2, 3],
+            },
+        ];
+        for shape in shapes {
+            assert_eq!(Shape::from_datum(&shape.to_datum()), Ok(shape));
+        }
+    }
+
+    #[test]
+    fn test_derive_errors() {
+        let code = Code::List(vec![Code::Identifier(ts("Rect"))]);
+        assert_eq!(
+            Shape::from_code(&code),
+            Err(DatumError::WrongShape {
+                expected: Box::new(Shape::to_lamp_type()),
+                found: Box::new(code),
+            })
+        );
+        let code = Code::Map(map![{Code::Identifier(ts("x")), Code::Integer(1)}]);
+        assert_eq!(
+            Point::from_code(&code),
+            Err(DatumError::WrongShape {
+                expected: Box::new(Point::to_lamp_type()),
+                found: Box::new(code),
+            })
+        );
+    }
+
+    struct Door;
+
+    impl crate::value::HostObject for Door {
+        const TYPE_NAME: &'static str = "Door";
+    }
+
+    #[derive(Debug, ToDatum, FromDatum)]
+    struct Room {
+        door: Option<crate::value::Handle<Door>>,
+        size: u64,
+    }
+
+    #[test]
+    fn test_from_value() {
+        // values are read as they are, so host objects inside them come through
+        let door = crate::value::Handle::new(Door);
+        let some = Value::List(
+            [Value::symbol("Some"), door.to_value()]
+                .into_iter()
+                .collect(),
+        );
+        let room = Value::Map(
+            [
+                (Value::symbol("door"), some),
+                (Value::symbol("size"), Value::Integer(3)),
+            ]
+            .into_iter()
+            .collect(),
+        );
+        let back = Room::from_value(&room).unwrap();
+        assert_eq!(back.size, 3);
+        assert_eq!(door.ref_count(), 3);
+        assert_eq!(
+            u8::from_value(&Value::Integer(256)),
+            Err(DatumError::OutOfRange {
+                expected: Box::new(LampType::U8),
+                found: 256
+            })
+        );
+        assert_eq!(
+            Option::<u8>::from_value(&Value::symbol("None")),
+            Err(wrong_shape::<Option<u8>>(&Code::Identifier(ts("None"))))
+        );
+    }
+}
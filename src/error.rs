@@ -0,0 +1,258 @@
+use std::fmt;
+
+use crate::runtime::Frame;
+use crate::value::Value;
+
+// the kinds of error built-in failures are caught as
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Error,
+    TypeError,
+    ArityError,
+    NameError,
+    ArithmeticError,
+    DivisionByZero,
+    Overflow,
+    CapabilityError,
+}
+
+const KINDS: &[Kind] = &[
+    Kind::Error,
+    Kind::TypeError,
+    Kind::ArityError,
+    Kind::NameError,
+    Kind::ArithmeticError,
+    Kind::DivisionByZero,
+    Kind::Overflow,
+    Kind::CapabilityError,
+];
+
+impl Kind {
+    // the symbol catch gets it as
+    pub fn name(self) -> &'static str {
+        match self {
+            Kind::Error => "error",
+            Kind::TypeError => "type_error",
+            Kind::ArityError => "arity_error",
+            Kind::NameError => "name_error",
+            Kind::ArithmeticError => "arithmetic_error",
+            Kind::DivisionByZero => "division_by_zero",
+            Kind::Overflow => "overflow",
+            Kind::CapabilityError => "capability_error",
+        }
+    }
+
+    // the kind this is a case of
+    pub fn parent(self) -> Option<Kind> {
+        match self {
+            Kind::Error => None,
+            Kind::DivisionByZero | Kind::Overflow => Some(Kind::ArithmeticError),
+            _ => Some(Kind::Error),
+        }
+    }
+}
+
+// what try does with an error
+#[derive(Debug, Clone, PartialEq)]
+pub enum Cause {
+    // a built-in or a call failed, catch gets {error: kind message: "text"}
+    Failed(Kind),
+    // a script raised the value, catch gets it back
+    Raised(Value),
+    // limits and interrupts stop the script, it can't catch them
+    Limit,
+    Interrupted,
+}
+
+// why a run failed
+#[derive(Debug, Clone, PartialEq)]
+pub struct Error {
+    pub cause: Cause,
+    pub message: String,
+    // the lamp functions it came out of, innermost first
+    // taken by the innermost one it leaves so the outer ones don't replace it
+    pub(crate) backtrace: Option<Vec<Frame>>,
+}
+
+// the frames shown with an error, deep recursion would bury the message
+const SHOWN_FRAMES: usize = 10;
+
+impl Error {
+    pub fn new(kind: Kind, message: impl Into<String>) -> Error {
+        Error::with_cause(Cause::Failed(kind), message)
+    }
+
+    fn with_cause(cause: Cause, message: impl Into<String>) -> Error {
+        Error {
+            cause,
+            message: message.into(),
+            backtrace: None,
+        }
+    }
+
+    pub(crate) fn limit(message: impl Into<String>) -> Error {
+        Error::with_cause(Cause::Limit, message)
+    }
+
+    pub(crate) fn interrupted() -> Error {
+        Error::with_cause(Cause::Interrupted, "Interrupted")
+    }
+
+    // raising an error value that was caught fails with its message again
+    pub(crate) fn raised(value: Value) -> Error {
+        let message = match parts(&value) {
+            Some((_, message)) => message.to_string(),
+            None => format!("Uncaught error {}", value),
+        };
+        Error::with_cause(Cause::Raised(value), message)
+    }
+
+    pub fn is_fatal(&self) -> bool {
+        matches!(self.cause, Cause::Limit | Cause::Interrupted)
+    }
+
+    // the calls it came out of, innermost first
+    pub fn backtrace(&self) -> &[Frame] {
+        self.backtrace.as_deref().unwrap_or_default()
+    }
+
+    // what catch gets
+    pub(crate) fn to_value(&self) -> Value {
+        match &self.cause {
+            Cause::Raised(value) => value.clone(),
+            Cause::Failed(kind) => error_value(kind.name(), &self.message),
+            Cause::Limit | Cause::Interrupted => error_value("error", &self.message),
+        }
+    }
+}
+
+// errors without a kind of their own, from compiling or from the host
+impl From<String> for Error {
+    fn from(message: String) -> Error {
+        Error::new(Kind::Error, message)
+    }
+}
+
+impl From<&str> for Error {
+    fn from(message: &str) -> Error {
+        Error::new(Kind::Error, message)
+    }
+}
+
+// the message followed by the calls it came out of, innermost first
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+        let frames = self.backtrace();
+        for frame in frames.iter().take(SHOWN_FRAMES) {
+            write!(f, "\n  in {}", frame)?;
+        }
+        if frames.len() > SHOWN_FRAMES {
+            write!(f, "\n  ... {} more", frames.len() - SHOWN_FRAMES)?;
+        }
+        Ok(())
+    }
+}
+
+// {error: kind message: "text"}, what catch gets for a failure that wasn't raised
+pub fn error_value(kind: &str, message: &str) -> Value {
+    Value::Map(
+        [
+            (Value::symbol("error"), Value::symbol(kind)),
+            (Value::symbol("message"), Value::string(message)),
+        ]
+        .into_iter()
+        .collect(),
+    )
+}
+
+// the kind and message of an error value
+pub fn parts(value: &Value) -> Option<(&str, &str)> {
+    let Value::Map(map) = value else {
+        return None;
+    };
+    match (
+        map.get(&Value::symbol("error")),
+        map.get(&Value::symbol("message")),
+    ) {
+        (Some(Value::Symbol(kind)), Some(Value::String(message))) => Some((kind, message)),
+        _ => None,
+    }
+}
+
+// whether the kind is the other kind or one of its cases
+// kinds raised by scripts are only cases of themselves
+pub fn is_a(kind: &str, of: &str) -> bool {
+    if kind == of {
+        return true;
+    }
+    let mut kind = KINDS.iter().copied().find(|k| k.name() == kind);
+    while let Some(k) = kind {
+        if k.name() == of {
+            return true;
+        }
+        kind = k.parent();
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::Location;
+
+    #[test]
+    fn test_errors() {
+        let error = Error::new(Kind::DivisionByZero, "Division by zero");
+        assert_eq!(
+            error.to_value(),
+            error_value("division_by_zero", "Division by zero")
+        );
+        assert!(!error.is_fatal());
+        assert!(Error::limit("Step limit of 10 reached").is_fatal());
+        assert!(Error::interrupted().is_fatal());
+
+        // a raised value is what it fails as whatever its message says
+        let value = error_value("x", "Interrupted");
+        let raised = Error::raised(value.clone());
+        assert_eq!(raised.message, "Interrupted");
+        assert!(!raised.is_fatal());
+        assert_eq!(raised.to_value(), value);
+        assert_eq!(
+            Error::raised(Value::symbol("bad")).message,
+            "Uncaught error bad"
+        );
+
+        let mut error = Error::from("failed");
+        assert_eq!(error.cause, Cause::Failed(Kind::Error));
+        error.backtrace = Some(vec![
+            Frame {
+                name: "inner".to_string(),
+                at: Some(Location { line: 2, column: 5 }),
+            },
+            Frame {
+                name: "outer".to_string(),
+                at: None,
+            },
+        ]);
+        assert_eq!(error.to_string(), "failed\n  in inner at 2:5\n  in outer");
+    }
+
+    #[test]
+    fn test_hierarchy() {
+        assert!(is_a("division_by_zero", "division_by_zero"));
+        assert!(is_a("division_by_zero", "arithmetic_error"));
+        assert!(is_a("division_by_zero", "error"));
+        assert!(!is_a("division_by_zero", "type_error"));
+        assert!(!is_a("error", "type_error"));
+        // kinds raised by scripts are only cases of themselves
+        assert!(is_a("parse_failure", "parse_failure"));
+        assert!(!is_a("parse_failure", "error"));
+
+        assert_eq!(
+            parts(&error_value("overflow", "Integer overflow in plus")),
+            Some(("overflow", "Integer overflow in plus"))
+        );
+        assert_eq!(parts(&Value::Integer(1)), None);
+    }
+}
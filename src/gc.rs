@@ -0,0 +1,420 @@
+use std::cell::RefCell;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::rc::{Rc, Weak};
+
+use crate::datum::ToDatum;
+use crate::runtime::{nothing, Closure, Env, Scope};
+use crate::value::Value;
+use crate::vm::Cell;
+
+// values are reference counted, which frees everything but cycles
+// the only things a cycle can go through are the cells and scopes lamp can change in place,
+// a closure kept in the cell or scope it captures, so those are what the heap keeps track of
+//
+// the collector works out the roots from the reference counts, a box with more references
+// than the heap accounts for is held from outside it, by a frame running, the globals or
+// a value the host kept, everything reachable from those is marked and the rest is swept
+// by emptying it, which breaks the cycles so the reference counts free them
+
+// how much the collector has done, what the gc_stats built-in gives back
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ToDatum)]
+pub struct GcStats {
+    pub collections: u64,
+    // cells and scopes made so far
+    pub allocated: u64,
+    // cells and scopes the collector emptied
+    pub freed: u64,
+    // cells and scopes left after the last collection
+    pub live: u64,
+}
+
+// walks the reference counted boxes inside a value
+pub(crate) trait Trace {
+    fn trace(&self, tracer: &mut Tracer);
+}
+
+// what the tracer found out about a box
+struct Found {
+    strong: usize,
+    // references from other boxes the tracer walked
+    internal: usize,
+    // the heap holds one more reference to its objects while collecting
+    object: bool,
+    // borrowed while collecting, so what it holds couldn't be walked
+    pinned: bool,
+    points_to: Vec<usize>,
+}
+
+impl Found {
+    fn is_root(&self) -> bool {
+        self.pinned || self.strong > self.internal + self.object as usize
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct Tracer {
+    found: HashMap<usize, Found>,
+    // the box being walked
+    current: Option<usize>,
+    // boxes found but not walked yet, walking them as they are found
+    // would recurse once for every level a list is nested
+    pending: Vec<(usize, Rc<dyn Trace>)>,
+}
+
+fn address<T: ?Sized>(rc: &Rc<T>) -> usize {
+    Rc::as_ptr(rc) as *const () as usize
+}
+
+impl Tracer {
+    // a reference from the box being walked, the box it points to is walked the first time
+    // its count is taken before the tracer holds on to it
+    pub(crate) fn reference<T: Trace + 'static>(&mut self, rc: &Rc<T>) {
+        let to = address(rc);
+        if let Some(from) = self.current {
+            self.found.get_mut(&from).unwrap().points_to.push(to);
+        }
+        match self.found.entry(to) {
+            Entry::Occupied(mut found) => found.get_mut().internal += 1,
+            Entry::Vacant(found) => {
+                found.insert(Found {
+                    strong: Rc::strong_count(rc),
+                    internal: 1,
+                    object: false,
+                    pinned: false,
+                    points_to: Vec::new(),
+                });
+                self.pending.push((to, rc.clone()));
+            }
+        }
+    }
+
+    // an object of the heap, nothing points to it yet
+    fn object<T: Trace + 'static>(&mut self, rc: &Rc<T>) {
+        let at = address(rc);
+        match self.found.entry(at) {
+            Entry::Occupied(mut found) => found.get_mut().object = true,
+            Entry::Vacant(found) => {
+                found.insert(Found {
+                    strong: Rc::strong_count(rc),
+                    internal: 0,
+                    object: true,
+                    pinned: false,
+                    points_to: Vec::new(),
+                });
+                self.pending.push((at, rc.clone()));
+                self.walk();
+            }
+        }
+    }
+
+    // walks the pending boxes and the ones they lead to
+    fn walk(&mut self) {
+        while let Some((at, rc)) = self.pending.pop() {
+            self.current = Some(at);
+            rc.trace(self);
+        }
+        self.current = None;
+    }
+
+    // the box being walked is in use and has to be kept
+    fn pin(&mut self) {
+        if let Some(at) = self.current {
+            self.found.get_mut(&at).unwrap().pinned = true;
+        }
+    }
+
+    // the addresses of the boxes reachable from the roots
+    fn marked(&self) -> HashSet<usize> {
+        let mut marked = HashSet::new();
+        let mut stack: Vec<usize> = self
+            .found
+            .iter()
+            .filter(|(_, found)| found.is_root())
+            .map(|(at, _)| *at)
+            .collect();
+        while let Some(at) = stack.pop() {
+            if marked.insert(at) {
+                stack.extend(&self.found[&at].points_to);
+            }
+        }
+        marked
+    }
+}
+
+impl Trace for Value {
+    fn trace(&self, tracer: &mut Tracer) {
+        match self {
+            Value::List(list) => list.trace(tracer),
+            Value::Map(map) => map.trace(tracer),
+            Value::Closure(closure) => tracer.reference(closure),
+            _ => (),
+        }
+    }
+}
+
+impl Trace for Closure {
+    fn trace(&self, tracer: &mut Tracer) {
+        let function = &self.function;
+        for arg in function.args.iter().chain(&function.rest) {
+            arg.default.trace(tracer);
+        }
+        tracer.reference(&self.env);
+        for cell in &self.captures {
+            tracer.reference(cell);
+        }
+    }
+}
+
+impl Trace for Scope {
+    fn trace(&self, tracer: &mut Tracer) {
+        for (_, value) in &self.variables {
+            value.trace(tracer);
+        }
+        if let Some(parent) = &self.parent {
+            tracer.reference(parent);
+        }
+    }
+}
+
+impl<T: Trace> Trace for RefCell<T> {
+    fn trace(&self, tracer: &mut Tracer) {
+        match self.try_borrow() {
+            Ok(inner) => inner.trace(tracer),
+            Err(_) => tracer.pin(),
+        }
+    }
+}
+
+impl<T: Trace> Trace for Option<T> {
+    fn trace(&self, tracer: &mut Tracer) {
+        if let Some(inner) = self {
+            inner.trace(tracer);
+        }
+    }
+}
+
+impl<T: Trace> Trace for Vec<T> {
+    fn trace(&self, tracer: &mut Tracer) {
+        self.iter().for_each(|item| item.trace(tracer));
+    }
+}
+
+impl<K: Trace, V: Trace> Trace for (K, V) {
+    fn trace(&self, tracer: &mut Tracer) {
+        self.0.trace(tracer);
+        self.1.trace(tracer);
+    }
+}
+
+enum Object {
+    Cell(Weak<RefCell<Value>>),
+    Scope(Weak<RefCell<Scope>>),
+}
+
+// the first collection comes after this many cells and scopes, later ones when the heap doubles
+const FIRST_COLLECTION: usize = 1024;
+
+pub(crate) struct Heap {
+    objects: Vec<Object>,
+    stats: GcStats,
+    next_collection: usize,
+    // collects before every allocation so tests find objects freed too early
+    pub(crate) stress: bool,
+}
+
+impl Default for Heap {
+    fn default() -> Heap {
+        Heap {
+            objects: Vec::new(),
+            stats: GcStats::default(),
+            next_collection: FIRST_COLLECTION,
+            stress: false,
+        }
+    }
+}
+
+impl Heap {
+    pub(crate) fn cell(&mut self, value: Value) -> Cell {
+        self.allocating();
+        let cell = Rc::new(RefCell::new(value));
+        self.objects.push(Object::Cell(Rc::downgrade(&cell)));
+        cell
+    }
+
+    pub(crate) fn scope(&mut self, parent: &Env) -> Env {
+        self.allocating();
+        let scope = Rc::new(RefCell::new(Scope {
+            variables: Vec::new(),
+            parent: Some(parent.clone()),
+        }));
+        self.objects.push(Object::Scope(Rc::downgrade(&scope)));
+        scope
+    }
+
+    fn allocating(&mut self) {
+        self.stats.allocated += 1;
+        if self.stress || self.objects.len() >= self.next_collection {
+            self.collect();
+        }
+    }
+
+    pub(crate) fn stats(&self) -> GcStats {
+        self.stats
+    }
+
+    // empties the cells and scopes only cycles keep alive, giving back how many there were
+    pub(crate) fn collect(&mut self) -> usize {
+        // objects freed by their reference counts are forgotten
+        let cells: Vec<_> = self
+            .objects
+            .iter()
+            .filter_map(|object| match object {
+                Object::Cell(cell) => cell.upgrade(),
+                Object::Scope(_) => None,
+            })
+            .collect();
+        let scopes: Vec<_> = self
+            .objects
+            .iter()
+            .filter_map(|object| match object {
+                Object::Scope(scope) => scope.upgrade(),
+                Object::Cell(_) => None,
+            })
+            .collect();
+
+        let mut tracer = Tracer::default();
+        cells.iter().for_each(|cell| tracer.object(cell));
+        scopes.iter().for_each(|scope| tracer.object(scope));
+        let marked = tracer.marked();
+
+        // what is emptied is dropped after the borrows end, dropping it can free other objects
+        let mut garbage: Vec<Value> = Vec::new();
+        let mut parents: Vec<Env> = Vec::new();
+        self.objects.clear();
+        for cell in cells {
+            match marked.contains(&address(&cell)) {
+                true => self.objects.push(Object::Cell(Rc::downgrade(&cell))),
+                false => garbage.push(std::mem::replace(&mut *cell.borrow_mut(), nothing())),
+            }
+        }
+        let mut freed = garbage.len();
+        for scope in scopes {
+            if marked.contains(&address(&scope)) {
+                self.objects.push(Object::Scope(Rc::downgrade(&scope)));
+                continue;
+            }
+            let mut scope = scope.borrow_mut();
+            garbage.extend(scope.variables.drain(..).map(|(_, value)| value));
+            parents.extend(scope.parent.take());
+            freed += 1;
+        }
+
+        self.stats.collections += 1;
+        self.stats.freed += freed as u64;
+        self.stats.live = self.objects.len() as u64;
+        self.next_collection = FIRST_COLLECTION.max(self.objects.len() * 2);
+        freed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::code::Code;
+    use crate::runtime::Runtime;
+
+    fn run(runtime: &mut Runtime, code: &str, tree: bool) -> Result<Value, String> {
+        let mut last = nothing();
+        for code in Code::from_str(code)? {
+            last = match tree {
+                true => runtime.interpret(&code),
+                false => runtime.eval(&code),
+            }
+            .map_err(|e| e.to_string())?;
+        }
+        Ok(last)
+    }
+
+    #[test]
+    fn test_cycles() {
+        // a function that can call itself is a closure kept in what it captures
+        let code = "[var make {fn c: [pgm [var f {fn a: [n] c: [f n]}] 0]}]
+                    [var i 0]
+                    {while c: [< i 100] do: [[make] [set i [+ i 1]]]}";
+        // the vm keeps f in a cell, the tree walker in the scope of the pgm
+        // which holds on to the scope of the call to make as well
+        for (tree, cycles) in [(false, 100), (true, 200)] {
+            let mut runtime = Runtime::new();
+            run(&mut runtime, code, tree).unwrap();
+            assert_eq!(runtime.collect_garbage(), cycles);
+            let stats = runtime.gc_stats();
+            assert_eq!(stats.collections, 1);
+            assert_eq!(stats.freed, cycles as u64);
+            assert_eq!(stats.live, 0);
+            assert_eq!(runtime.collect_garbage(), 0);
+        }
+
+        // collections come by themselves as the heap grows
+        let mut runtime = Runtime::new();
+        run(&mut runtime, &code.replace("100", "5000"), false).unwrap();
+        let stats = runtime.gc_stats();
+        // make's frame starts with a cell for f and the pgm makes it a fresh one
+        assert_eq!(stats.allocated, 10000);
+        assert!(stats.collections >= 4);
+        assert!(stats.live as usize <= FIRST_COLLECTION);
+    }
+
+    #[test]
+    fn test_deep_nesting() {
+        // a list nested far deeper than the native stack could recurse is still traced
+        let mut value = nothing();
+        for _ in 0..100000 {
+            value = Value::List([value].into_iter().collect());
+        }
+        let mut heap = Heap::default();
+        let cell = heap.cell(value);
+        assert_eq!(heap.collect(), 0);
+        assert_eq!(heap.stats().live, 1);
+        // dropping it would recurse once a level
+        std::mem::forget(cell);
+    }
+
+    #[test]
+    fn test_roots() {
+        for tree in [false, true] {
+            let mut runtime = Runtime::new();
+            runtime.set_gc_stress(true);
+            // a closure the host holds on to keeps what it captured
+            let counter = run(
+                &mut runtime,
+                "[pgm [var n 0] [var inc {fn c: [set n [+ n 1]]}] [inc] inc]",
+                tree,
+            )
+            .unwrap();
+            runtime.collect_garbage();
+            assert_eq!(
+                runtime.call_value("counter", &counter, vec![]),
+                Ok(Value::Integer(2))
+            );
+
+            // and so does a global
+            run(
+                &mut runtime,
+                "[var total [pgm [var sum 0] {fn a: [k] c: [set sum [+ sum k]]}]]",
+                tree,
+            )
+            .unwrap();
+            runtime.collect_garbage();
+            assert_eq!(
+                run(&mut runtime, "[total 2] [total 3]", tree),
+                Ok(Value::Integer(5))
+            );
+            assert!(runtime.gc_stats().collections > 2);
+        }
+
+        // the stats are a built-in
+        let stats = run(&mut Runtime::new(), "[gc_stats]", false).unwrap();
+        assert_eq!(stats, GcStats::default().to_value());
+    }
+}
@@ -0,0 +1,617 @@
+use crate::code::Code;
+use crate::datum::{Datum, FromDatum, ToDatum};
+use crate::lamp_type::LampType;
+use crate::map::Map;
+use crate::queue::Queue;
+use crate::utils::ts;
+use crate::value::Value;
+
+// deeper documents are rejected instead of overflowing the stack
+const MAX_DEPTH: usize = 512;
+
+// JSON is read into plain code:
+// objects become maps with string keys, arrays become lists,
+// numbers without a fraction or exponent become integers,
+// true and false become the identifiers lamp uses for bools and null becomes null
+pub fn parse(text: &str) -> Result<Code, String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut queue = Queue::new(&chars);
+    let value = queue.pop_json(0)?;
+    queue.pop_json_whitespace();
+    if !queue.empty() {
+        return Err(json_error(&queue, "Unexpected trailing characters"));
+    }
+    Ok(value)
+}
+
+fn json_error(queue: &Queue<char>, msg: &str) -> String {
+    format!("JSON Error: {} at character {}", msg, queue.cursor)
+}
+
+impl<'a> Queue<'a, char> {
+    fn pop_json_whitespace(&mut self) {
+        self.pop_while(|c| matches!(*c, ' ' | '\t' | '\n' | '\r'));
+    }
+
+    fn expect_json(&mut self, word: &str) -> Result<(), String> {
+        for expected in word.chars() {
+            if self.pop() != Some(&expected) {
+                return Err(json_error(self, &format!("Expected \"{}\"", word)));
+            }
+        }
+        Ok(())
+    }
+
+    fn pop_json(&mut self, depth: usize) -> Result<Code, String> {
+        if depth > MAX_DEPTH {
+            return Err(json_error(self, "Nesting too deep"));
+        }
+        self.pop_json_whitespace();
+        match self.peak() {
+            Some('{') => self.pop_json_object(depth),
+            Some('[') => self.pop_json_array(depth),
+            Some('"') => Ok(Code::StringLiteral(self.pop_json_string()?)),
+            Some('t') => self
+                .expect_json("true")
+                .map(|_| Code::Identifier(ts("true"))),
+            Some('f') => self
+                .expect_json("false")
+                .map(|_| Code::Identifier(ts("false"))),
+            Some('n') => self
+                .expect_json("null")
+                .map(|_| Code::Identifier(ts("null"))),
+            Some(c) if *c == '-' || c.is_ascii_digit() => self.pop_json_number(),
+            Some(_) => Err(json_error(self, "Unexpected character")),
+            None => Err(json_error(self, "Unexpected end of input")),
+        }
+    }
+
+    fn pop_json_object(&mut self, depth: usize) -> Result<Code, String> {
+        self.pop();
+        let mut map = Map::new();
+        self.pop_json_whitespace();
+        if self.peak() == Some(&'}') {
+            self.pop();
+            return Ok(Code::Map(map));
+        }
+        loop {
+            self.pop_json_whitespace();
+            if self.peak() != Some(&'"') {
+                return Err(json_error(self, "Expected a string key"));
+            }
+            let key = self.pop_json_string()?;
+            self.pop_json_whitespace();
+            if self.pop() != Some(&':') {
+                return Err(json_error(self, "Expected ':'"));
+            }
+            let value = self.pop_json(depth + 1)?;
+            map.insert(Code::StringLiteral(key), value);
+            self.pop_json_whitespace();
+            match self.pop() {
+                Some(',') => continue,
+                Some('}') => return Ok(Code::Map(map)),
+                _ => return Err(json_error(self, "Expected ',' or '}'")),
+            }
+        }
+    }
+
+    fn pop_json_array(&mut self, depth: usize) -> Result<Code, String> {
+        self.pop();
+        let mut list = Vec::new();
+        self.pop_json_whitespace();
+        if self.peak() == Some(&']') {
+            self.pop();
+            return Ok(Code::List(list));
+        }
+        loop {
+            list.push(self.pop_json(depth + 1)?);
+            self.pop_json_whitespace();
+            match self.pop() {
+                Some(',') => continue,
+                Some(']') => return Ok(Code::List(list)),
+                _ => return Err(json_error(self, "Expected ',' or ']'")),
+            }
+        }
+    }
+
+    fn pop_json_hex(&mut self) -> Result<u32, String> {
+        let start = self.cursor;
+        let digits = self.s_pop_while(|c| c.is_ascii_hexdigit());
+        if digits.len() < 4 {
+            self.cursor = start;
+            return Err(json_error(self, "Expected four hex digits"));
+        }
+        // only the first four digits belong to the escape
+        self.cursor = start + 4;
+        Ok(u32::from_str_radix(&digits[..4], 16).unwrap())
+    }
+
+    fn pop_json_string(&mut self) -> Result<String, String> {
+        self.pop();
+        let mut s = String::new();
+        loop {
+            match self.pop().copied() {
+                Some('"') => return Ok(s),
+                Some('\\') => match self.pop() {
+                    Some('"') => s.push('"'),
+                    Some('\\') => s.push('\\'),
+                    Some('/') => s.push('/'),
+                    Some('b') => s.push('\u{8}'),
+                    Some('f') => s.push('\u{c}'),
+                    Some('n') => s.push('\n'),
+                    Some('r') => s.push('\r'),
+                    Some('t') => s.push('\t'),
+                    Some('u') => {
+                        let mut code = self.pop_json_hex()?;
+                        // characters outside the basic plane come as surrogate pairs
+                        if (0xD800..0xDC00).contains(&code)
+                            && self.peak() == Some(&'\\')
+                            && self.peak_nth(1) == Some(&'u')
+                        {
+                            self.pop();
+                            self.pop();
+                            let low = self.pop_json_hex()?;
+                            if !(0xDC00..0xE000).contains(&low) {
+                                return Err(json_error(self, "Invalid surrogate pair"));
+                            }
+                            code = 0x10000 + ((code - 0xD800) << 10) + (low - 0xDC00);
+                        }
+                        match char::from_u32(code) {
+                            Some(c) => s.push(c),
+                            None => return Err(json_error(self, "Invalid unicode escape")),
+                        }
+                    }
+                    _ => return Err(json_error(self, "Invalid escape")),
+                },
+                Some(c) if (c as u32) < 0x20 => {
+                    return Err(json_error(self, "Control character in string"))
+                }
+                Some(c) => s.push(c),
+                None => return Err(json_error(self, "Unterminated string")),
+            }
+        }
+    }
+
+    fn pop_json_number(&mut self) -> Result<Code, String> {
+        let start = self.cursor;
+        if self.peak() == Some(&'-') {
+            self.pop();
+        }
+        let int = self.s_pop_while(|c| c.is_ascii_digit());
+        if int.is_empty() || (int.len() > 1 && int.starts_with('0')) {
+            return Err(json_error(self, "Invalid number"));
+        }
+        let mut float = false;
+        if self.peak() == Some(&'.') {
+            self.pop();
+            float = true;
+            if self.s_pop_while(|c| c.is_ascii_digit()).is_empty() {
+                return Err(json_error(self, "Expected digits after '.'"));
+            }
+        }
+        if matches!(self.peak(), Some('e') | Some('E')) {
+            self.pop();
+            float = true;
+            if matches!(self.peak(), Some('+') | Some('-')) {
+                self.pop();
+            }
+            if self.s_pop_while(|c| c.is_ascii_digit()).is_empty() {
+                return Err(json_error(self, "Expected exponent digits"));
+            }
+        }
+
+        let text = self.range_string(start);
+        if !float {
+            if let Ok(num) = text.parse() {
+                return Ok(Code::Integer(num));
+            }
+        }
+        match text.parse::<f64>() {
+            Ok(num) if num.is_finite() => Ok(Code::from_float(num)),
+            _ => Err(json_error(self, "Number out of range")),
+        }
+    }
+}
+
+// writes code that has a JSON equivalent, object keys keep the map's order
+pub fn stringify(code: &Code) -> Result<String, String> {
+    let mut out = String::new();
+    write_json(code, &mut out)?;
+    Ok(out)
+}
+
+fn write_json_string(s: &str, out: &mut String) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+fn json_key(key: &Code) -> Result<String, String> {
+    match key {
+        Code::StringLiteral(s) | Code::Identifier(s) => Ok(s.clone()),
+        Code::Character(c) => Ok(c.to_string()),
+        Code::Integer(num) => Ok(num.to_string()),
+        _ => Err(format!(
+            "{} cannot be used as a JSON object key",
+            key.to_source()
+        )),
+    }
+}
+
+fn write_json(code: &Code, out: &mut String) -> Result<(), String> {
+    match code {
+        Code::Integer(num) => out.push_str(&num.to_string()),
+        Code::Float(bits) => {
+            let num = f64::from_bits(*bits);
+            if !num.is_finite() {
+                return Err(format!("{} cannot be written as JSON", num));
+            }
+            // debug formatting keeps the ".0" so floats read back as floats
+            out.push_str(&format!("{:?}", num));
+        }
+        Code::Character(c) => write_json_string(&c.to_string(), out),
+        Code::StringLiteral(s) => write_json_string(s, out),
+        Code::Identifier(i) if i == "true" || i == "false" || i == "null" => out.push_str(i),
+        Code::Identifier(i) => {
+            return Err(format!("Identifier {} cannot be written as JSON", i));
+        }
+        Code::List(list) => {
+            out.push('[');
+            for (i, item) in list.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_json(item, out)?;
+            }
+            out.push(']');
+        }
+        Code::Map(map) => {
+            out.push('{');
+            for (i, (key, value)) in map.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_json_string(&json_key(key)?, out);
+                out.push(':');
+                write_json(value, out)?;
+            }
+            out.push('}');
+        }
+    }
+    Ok(())
+}
+
+// the Some/None enum that Option maps to, which JSON writes as a value or null
+fn option_payload(typ: &LampType) -> Option<&LampType> {
+    match typ {
+        LampType::Enum(variants) if variants.len() == 2 => {
+            match (variants.get("Some"), variants.get("None")) {
+                (Some(Some(payload)), Some(None)) => Some(payload),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+fn json_mismatch(typ: &LampType, json: &Code) -> String {
+    format!(
+        "JSON value {} does not match type {}",
+        json.to_source(),
+        typ.to_code()
+    )
+}
+
+// turns a parsed JSON value into the code for a datum of the type
+fn code_from_json(json: &Code, typ: &LampType) -> Result<Code, String> {
+    if *typ == String::to_lamp_type() {
+        return match json {
+            Code::StringLiteral(s) => Ok(s.to_code()),
+            _ => Err(json_mismatch(typ, json)),
+        };
+    }
+    if let Some(payload) = option_payload(typ) {
+        return match json {
+            Code::Identifier(null) if null == "null" => Ok(None::<u8>.to_code()),
+            _ => Ok(Code::List(vec![
+                Code::Identifier(ts("Some")),
+                code_from_json(json, payload)?,
+            ])),
+        };
+    }
+
+    match (typ, json) {
+        (LampType::F64, &Code::Integer(num)) => Ok(Code::from_float(num as f64)),
+        (LampType::Char, Code::StringLiteral(s)) if s.chars().count() == 1 => {
+            Ok(Code::Character(s.chars().next().unwrap()))
+        }
+        (LampType::List(types), Code::List(items)) if types.len() == items.len() => Ok(Code::List(
+            types
+                .iter()
+                .zip(items.iter())
+                .map(|(typ, item)| code_from_json(item, typ))
+                .collect::<Result<_, _>>()?,
+        )),
+        (LampType::Vector(typ), Code::List(items)) => Ok(Code::List(
+            items
+                .iter()
+                .map(|item| code_from_json(item, typ))
+                .collect::<Result<_, _>>()?,
+        )),
+        (LampType::Struct(fields), Code::Map(map)) => {
+            let mut code = Map::new();
+            for (key, value) in map.iter() {
+                let name = json_key(key)?;
+                let field = fields
+                    .get(&name)
+                    .ok_or(format!("Unknown field {} in JSON object", name))?;
+                code.insert(Code::Identifier(name), code_from_json(value, field)?);
+            }
+            Ok(Code::Map(code))
+        }
+        (LampType::Enum(variants), Code::StringLiteral(tag)) if variants.contains_key(tag) => {
+            Ok(Code::List(vec![Code::Identifier(tag.clone())]))
+        }
+        (LampType::Enum(variants), Code::Map(map)) if map.len() == 1 => {
+            let (key, value) = map.iter().next().unwrap();
+            let tag = json_key(key)?;
+            match variants.get(&tag) {
+                Some(Some(payload)) => Ok(Code::List(vec![
+                    Code::Identifier(tag),
+                    code_from_json(value, payload)?,
+                ])),
+                _ => Err(json_mismatch(typ, json)),
+            }
+        }
+        (LampType::Dict(key_typ, val_typ), Code::Map(map)) => {
+            let mut code = Map::new();
+            for (key, value) in map.iter() {
+                code.insert(
+                    key_from_json(key, key_typ)?,
+                    code_from_json(value, val_typ)?,
+                );
+            }
+            Ok(Code::Map(code))
+        }
+        _ => Ok(json.clone()),
+    }
+}
+
+// object keys are always strings in JSON so other key types are parsed out of them
+fn key_from_json(key: &Code, typ: &LampType) -> Result<Code, String> {
+    let text = json_key(key)?;
+    match typ {
+        LampType::U8 | LampType::U64 | LampType::I64 => text
+            .parse()
+            .map(Code::Integer)
+            .map_err(|_| json_mismatch(typ, key)),
+        LampType::F64 => text
+            .parse()
+            .map(Code::from_float)
+            .map_err(|_| json_mismatch(typ, key)),
+        LampType::Bool if text == "true" || text == "false" => Ok(Code::Identifier(text)),
+        _ => code_from_json(&Code::StringLiteral(text), typ),
+    }
+}
+
+// turns the code of a datum of the type into code that stringify can write
+fn code_to_json(code: &Code, typ: &LampType) -> Result<Code, String> {
+    if *typ == String::to_lamp_type() {
+        return Ok(Code::StringLiteral(String::from_code(code)?));
+    }
+    if let Some(payload) = option_payload(typ) {
+        return match code {
+            Code::List(list) if list.len() == 2 => code_to_json(&list[1], payload),
+            _ => Ok(Code::Identifier(ts("null"))),
+        };
+    }
+
+    match (typ, code) {
+        (LampType::List(types), Code::List(items)) => Ok(Code::List(
+            types
+                .iter()
+                .zip(items.iter())
+                .map(|(typ, item)| code_to_json(item, typ))
+                .collect::<Result<_, _>>()?,
+        )),
+        (LampType::Vector(typ), Code::List(items)) => Ok(Code::List(
+            items
+                .iter()
+                .map(|item| code_to_json(item, typ))
+                .collect::<Result<_, _>>()?,
+        )),
+        // fields are written in the order the type declares them
+        (LampType::Struct(fields), Code::Map(map)) => {
+            for key in map.keys() {
+                let name = json_key(key)?;
+                if !fields.contains_key(&name) {
+                    return Err(format!("Unknown field {}", name));
+                }
+            }
+            let mut json = Map::new();
+            for (name, field) in fields.iter() {
+                if let Some(value) = map.get(&Code::Identifier(name.clone())) {
+                    json.insert(
+                        Code::StringLiteral(name.clone()),
+                        code_to_json(value, field)?,
+                    );
+                }
+            }
+            Ok(Code::Map(json))
+        }
+        (LampType::Enum(variants), Code::List(list)) => match list.as_slice() {
+            [Code::Identifier(tag)] => Ok(Code::StringLiteral(tag.clone())),
+            [Code::Identifier(tag), payload] => {
+                let payload_typ = variants
+                    .get(tag)
+                    .cloned()
+                    .flatten()
+                    .ok_or(format!("Unknown variant {}", tag))?;
+                Ok(Code::Map(crate::map![
+                    {Code::StringLiteral(tag.clone()), code_to_json(payload, &payload_typ)?}
+                ]))
+            }
+            _ => Err(format!("{} is not an enum value", code.to_source())),
+        },
+        (LampType::Dict(key_typ, val_typ), Code::Map(map)) => {
+            let mut json = Map::new();
+            for (key, value) in map.iter() {
+                let key = match code_to_json(key, key_typ)? {
+                    Code::StringLiteral(s) => s,
+                    other => json_key(&other)?,
+                };
+                json.insert(Code::StringLiteral(key), code_to_json(value, val_typ)?);
+            }
+            Ok(Code::Map(json))
+        }
+        _ => Ok(code.clone()),
+    }
+}
+
+// reads JSON as a datum of the type, using the same encodings ToDatum does
+pub fn datum_from_json(text: &str, typ: LampType) -> Result<Datum, String> {
+    let code = code_from_json(&parse(text)?, &typ)?;
+    Ok(Datum::new(typ, Value::from_code(&code))?)
+}
+
+pub fn datum_to_json(datum: &Datum) -> Result<String, String> {
+    datum.validate()?;
+    stringify(&code_to_json(&datum.code()?, &datum.typ)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datum::{FromDatum, ToDatum};
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            parse(r#" {"a": [1, -2.5e1, "x\né😀"], "b": {"c": null}, "d": true} "#),
+            Ok(Code::Map(map![
+                {Code::StringLiteral(ts("a")), Code::List(vec![
+                    Code::Integer(1),
+                    Code::from_float(-25.0),
+                    Code::StringLiteral(ts("x\né😀")),
+                ])},
+                {Code::StringLiteral(ts("b")), Code::Map(map![
+                    {Code::StringLiteral(ts("c")), Code::Identifier(ts("null"))},
+                ])},
+                {Code::StringLiteral(ts("d")), Code::Identifier(ts("true"))},
+            ]))
+        );
+        assert_eq!(parse("[]"), Ok(Code::List(vec![])));
+        assert_eq!(
+            parse("123456789012345678901234567890123456789012"),
+            Ok(Code::from_float(1.2345678901234568e41))
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            parse("[1,]"),
+            Err("JSON Error: Unexpected character at character 3".to_string())
+        );
+        assert_eq!(
+            parse("{\"a\" 1}"),
+            Err("JSON Error: Expected ':' at character 6".to_string())
+        );
+        assert_eq!(
+            parse("01"),
+            Err("JSON Error: Invalid number at character 2".to_string())
+        );
+        assert_eq!(
+            parse("\"abc"),
+            Err("JSON Error: Unterminated string at character 4".to_string())
+        );
+        assert_eq!(
+            parse("1 2"),
+            Err("JSON Error: Unexpected trailing characters at character 2".to_string())
+        );
+        assert_eq!(
+            parse("nul"),
+            Err("JSON Error: Expected \"null\" at character 3".to_string())
+        );
+        assert!(parse(&"[".repeat(10_000))
+            .unwrap_err()
+            .contains("Nesting too deep"));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let texts = [
+            r#"{"a":[1,-25.0,"x\n\"é"],"b":{"c":null},"d":true}"#,
+            r#"[[],{},0,1e300,"\u0001"]"#,
+        ];
+        for text in texts {
+            let code = parse(text).unwrap();
+            assert_eq!(stringify(&code).unwrap(), text);
+            assert_eq!(parse(&stringify(&code).unwrap()), Ok(code));
+        }
+        assert_eq!(
+            stringify(&Code::Identifier(ts("x"))),
+            Err("Identifier x cannot be written as JSON".to_string())
+        );
+    }
+
+    #[derive(Debug, PartialEq, crate::datum::ToDatum, crate::datum::FromDatum)]
+    enum Level {
+        Low,
+        High(u8),
+    }
+
+    #[derive(Debug, PartialEq, crate::datum::ToDatum, crate::datum::FromDatum)]
+    struct Config {
+        name: String,
+        ports: Vec<u64>,
+        ratio: f64,
+        mode: Option<char>,
+        levels: Vec<Level>,
+        limits: std::collections::BTreeMap<u64, bool>,
+    }
+
+    #[test]
+    fn test_datum_round_trip() {
+        let text = r#"{"name":"web","ports":[80,443],"ratio":1,"mode":null,"levels":["Low",{"High":3}],"limits":{"80":true}}"#;
+        let datum = datum_from_json(text, Config::to_lamp_type()).unwrap();
+        let config = Config::from_datum(&datum).unwrap();
+        assert_eq!(
+            config,
+            Config {
+                name: ts("web"),
+                ports: vec![80, 443],
+                ratio: 1.0,
+                mode: None,
+                levels: vec![Level::Low, Level::High(3)],
+                limits: [(80, true)].into_iter().collect(),
+            }
+        );
+        assert_eq!(
+            datum_to_json(&config.to_datum()).unwrap(),
+            text.replace("\"ratio\":1", "\"ratio\":1.0")
+        );
+    }
+
+    #[test]
+    fn test_datum_errors() {
+        assert_eq!(
+            datum_from_json("[1, 300]", Vec::<u8>::to_lamp_type()).unwrap_err(),
+            "datum[1]: 300 does not fit in [u8]"
+        );
+        assert_eq!(
+            datum_from_json("{\"nme\": \"x\"}", Config::to_lamp_type()).unwrap_err(),
+            "Unknown field nme in JSON object"
+        );
+    }
+}
@@ -1,10 +1,11 @@
-use core::panic;
 use std::vec;
 
 use crate::code::Code;
-use crate::datum::ToDatum;
+use crate::datum::{wrong_shape, wrong_value, DatumError, FromDatum, ToDatum};
 use crate::map::*;
+use crate::persistent::PMap;
 use crate::utils::ts;
+use crate::value::Value;
 
 use LampType::*;
 
@@ -15,6 +16,7 @@ pub enum LampType {
     I64,
     F64,
     Char,
+    Bool,
 
     List(Vec<LampType>),
     Vector(Box<LampType>),
@@ -31,19 +33,199 @@ pub enum LampType {
     Code,
 
     Type,
+
+    // a handle to a rust object registered under the name, only values can hold one
+    Host(String),
+}
+
+// an Invalid error with the reason at the path
+fn invalid(path: &str, reason: String) -> DatumError {
+    DatumError::Invalid {
+        path: path.to_string(),
+        reason,
+    }
 }
 
 impl LampType {
+    // checks the code is a value of this type
+    pub fn validate(&self, code: &Code) -> Result<(), DatumError> {
+        self.validate_value(&Value::from_code(code), "datum")
+    }
+
+    // checks the value is of this type with the name at the start of the path in errors
+    pub fn validate_value(&self, value: &Value, name: &str) -> Result<(), DatumError> {
+        self.validate_at(value, &mut ts(name))
+    }
+
+    // path is where the value sits inside the datum being checked
+    fn validate_at(&self, value: &Value, path: &mut String) -> Result<(), DatumError> {
+        let integer_range: Option<(i128, i128)> = match self {
+            U8 => Some((0, u8::MAX.into())),
+            U64 => Some((0, u64::MAX.into())),
+            I64 => Some((i64::MIN.into(), i64::MAX.into())),
+            _ => None,
+        };
+
+        match (self, value) {
+            (U8 | U64 | I64, &Value::Integer(num)) => {
+                let (min, max) = integer_range.unwrap();
+                if num < min || num > max {
+                    return Err(invalid(
+                        path,
+                        format!("{} does not fit in {}", num, self.to_code()),
+                    ));
+                }
+            }
+            (F64, Value::Float(_)) | (Char, Value::Character(_)) => (),
+            (Bool, Value::Symbol(b)) if &**b == "true" || &**b == "false" => (),
+            (List(types), Value::List(items)) => {
+                if types.len() != items.len() {
+                    return Err(invalid(
+                        path,
+                        format!(
+                            "expected a list of {} items but found {}",
+                            types.len(),
+                            items.len()
+                        ),
+                    ));
+                }
+                for (i, (typ, item)) in types.iter().zip(items.iter()).enumerate() {
+                    typ.validate_in(item, path, format!("[{}]", i))?;
+                }
+            }
+            (Vector(typ), Value::List(items)) => {
+                for (i, item) in items.iter().enumerate() {
+                    typ.validate_in(item, path, format!("[{}]", i))?;
+                }
+            }
+            (DynList, Value::List(_)) | (DynMap, Value::Map(_)) | (LampType::Code, _) => (),
+            (Maping(fields), Value::Map(map)) => {
+                let mut keys: Vec<_> = fields.keys().collect();
+                keys.sort();
+                for key in keys {
+                    let (key_typ, val_typ) = &fields[key];
+                    let key = Value::from_code(key);
+                    let value = map
+                        .get(&key)
+                        .ok_or_else(|| invalid(path, format!("missing key {}", key)))?;
+                    key_typ.validate_key(&key, path)?;
+                    val_typ.validate_in(value, path, format!("[{}]", key))?;
+                }
+                let known: Vec<_> = fields.keys().map(Value::from_code).collect();
+                unknown_keys(map, path, |k| known.contains(k))?;
+            }
+            (Dict(key_typ, val_typ), Value::Map(map)) => {
+                for (key, value) in map.iter() {
+                    key_typ.validate_key(key, path)?;
+                    val_typ.validate_in(value, path, format!("[{}]", key))?;
+                }
+            }
+            (Struct(fields), Value::Map(map)) => {
+                let mut names: Vec<_> = fields.keys().collect();
+                names.sort();
+                for name in names {
+                    let value = map
+                        .get(&Value::symbol(name))
+                        .ok_or_else(|| invalid(path, format!("missing field {}", name)))?;
+                    fields[name].validate_in(value, path, format!(".{}", name))?;
+                }
+                unknown_keys(
+                    map,
+                    path,
+                    |k| matches!(k, Value::Symbol(name) if fields.contains_key(&**name)),
+                )?;
+            }
+            (Enum(variants), Value::List(list)) => {
+                let tag = match (list.len(), list.get(0)) {
+                    (1 | 2, Some(Value::Symbol(tag))) => tag,
+                    _ => {
+                        return Err(invalid(
+                            path,
+                            format!("expected [Variant payload] but found {}", source(value)),
+                        ))
+                    }
+                };
+                match (variants.get(&**tag), list.get(1)) {
+                    (None, _) => {
+                        return Err(invalid(path, format!("unknown variant {}", tag)));
+                    }
+                    (Some(None), None) => (),
+                    (Some(Some(typ)), Some(payload)) => {
+                        typ.validate_in(payload, path, format!("::{}", tag))?;
+                    }
+                    (Some(None), Some(_)) => {
+                        return Err(invalid(
+                            path,
+                            format!("variant {} does not take a payload", tag),
+                        ));
+                    }
+                    (Some(Some(_)), None) => {
+                        return Err(invalid(path, format!("variant {} needs a payload", tag)));
+                    }
+                }
+            }
+            (Type, Value::Type(_)) => (),
+            (Type, _) => {
+                let code = value.to_code().ok();
+                if code.is_none_or(|code| LampType::from_code(&code).is_err()) {
+                    return Err(invalid(path, format!("{} is not a type", source(value))));
+                }
+            }
+            (Host(name), Value::Host(host)) if host.type_name() == name => (),
+            _ => {
+                return Err(invalid(
+                    path,
+                    format!("expected {} but found {}", self.to_code(), source(value)),
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    // validates a value found under the segment of the current path
+    fn validate_in(
+        &self,
+        value: &Value,
+        path: &mut String,
+        segment: String,
+    ) -> Result<(), DatumError> {
+        let len = path.len();
+        path.push_str(&segment);
+        let result = self.validate_at(value, path);
+        path.truncate(len);
+        result
+    }
+
+    fn validate_key(&self, key: &Value, path: &mut String) -> Result<(), DatumError> {
+        self.validate_in(key, path, format!("[{}]", key))
+            .map_err(|err| match err {
+                DatumError::Invalid { path, reason } => DatumError::Invalid {
+                    path,
+                    reason: format!("key {}", reason),
+                },
+                err => err,
+            })
+    }
 }
 
-// needed for the ToDatum implemtation for LampType
-impl ToDatum for (LampType, LampType) {
-    fn to_lamp_type() -> LampType {
-        LampType::Vector(Box::new(LampType::Type))
+// the value written as source for errors, only errors pay for turning it into code
+fn source(value: &Value) -> String {
+    match value.to_code() {
+        Ok(code) => code.to_source(),
+        Err(_) => value.to_string(),
     }
+}
 
-    fn to_code(&self) -> Code {
-        Code::List(vec![self.0.to_code(), self.1.to_code()])
+// the first of the keys that aren't known, by their source
+fn unknown_keys(
+    map: &PMap<Value, Value>,
+    path: &str,
+    known: impl Fn(&Value) -> bool,
+) -> Result<(), DatumError> {
+    let unknown = map.keys().filter(|k| !known(k)).map(source).min();
+    match unknown {
+        Some(key) => Err(invalid(path, format!("unknown field {}", key))),
+        None => Ok(()),
     }
 }
 
@@ -59,16 +241,191 @@ impl ToDatum for LampType {
             I64 => Code::List(vec![Code::Identifier(ts("i64"))]),
             F64 => Code::List(vec![Code::Identifier(ts("f64"))]),
             Char => Code::List(vec![Code::Identifier(ts("char"))]),
+            Bool => Code::List(vec![Code::Identifier(ts("bool"))]),
             List(v) => Code::List(vec![Code::Identifier(ts("List")), v.to_code()]),
             Vector(t) => Code::List(vec![Code::Identifier(ts("Vec")), t.to_code()]),
             DynList => Code::List(vec![Code::Identifier(ts("DynList"))]),
             Maping(m) => Code::List(vec![Code::Identifier(ts("Map")), m.to_code()]),
-            Dict(k, v) => Code::List(vec![Code::Identifier(ts("Dict")), Code::List(vec![k.to_code(), v.to_code()])]),
+            Dict(k, v) => Code::List(vec![
+                Code::Identifier(ts("Dict")),
+                Code::List(vec![k.to_code(), v.to_code()]),
+            ]),
             DynMap => Code::List(vec![Code::Identifier(ts("DynMap"))]),
             Struct(m) => Code::List(vec![Code::Identifier(ts("Struct")), m.to_code()]),
-            Enum(m) => Code::List(vec![Code::Identifier(ts("Struct")), m.to_code()]),
+            Enum(m) => Code::List(vec![Code::Identifier(ts("Enum")), m.to_code()]),
             Code => Code::List(vec![Code::Identifier(ts("Code"))]),
             Type => Code::List(vec![Code::Identifier(ts("Type"))]),
+            Host(name) => Code::List(vec![
+                Code::Identifier(ts("Host")),
+                Code::Identifier(name.clone()),
+            ]),
+        }
+    }
+}
+
+impl FromDatum for LampType {
+    fn from_code(code: &Code) -> Result<Self, DatumError> {
+        let (name, arg) = match code {
+            Code::List(list) => match list.as_slice() {
+                [Code::Identifier(name)] => (name.as_str(), None),
+                [Code::Identifier(name), arg] => (name.as_str(), Some(arg)),
+                _ => return Err(wrong_shape::<LampType>(code)),
+            },
+            _ => return Err(wrong_shape::<LampType>(code)),
+        };
+
+        Ok(match (name, arg) {
+            ("u8", None) => U8,
+            ("u64", None) => U64,
+            ("i64", None) => I64,
+            ("f64", None) => F64,
+            ("char", None) => Char,
+            ("bool", None) => Bool,
+            ("List", Some(arg)) => List(Vec::from_code(arg)?),
+            ("Vec", Some(arg)) => Vector(Box::new(LampType::from_code(arg)?)),
+            ("DynList", None) => DynList,
+            ("Map", Some(arg)) => Maping(Map::from_code(arg)?),
+            // the key and value types are a list of two types, [List [[Type] [Type]]]
+            // where they were [Vec [Type]] before tuples had their own lamp type
+            ("Dict", Some(arg)) => {
+                let (k, v) = <(LampType, LampType)>::from_code(arg)?;
+                Dict(Box::new(k), Box::new(v))
+            }
+            ("DynMap", None) => DynMap,
+            ("Struct", Some(arg)) => Struct(Map::from_code(arg)?),
+            ("Enum", Some(arg)) => Enum(Map::from_code(arg)?),
+            ("Code", None) => LampType::Code,
+            ("Type", None) => Type,
+            ("Host", Some(Code::Identifier(name))) => Host(name.clone()),
+            _ => return Err(wrong_shape::<LampType>(code)),
+        })
+    }
+
+    // types written as code are small enough to go by way of the code
+    fn from_value(value: &Value) -> Result<Self, DatumError> {
+        match value {
+            Value::Type(typ) => Ok((**typ).clone()),
+            _ => LampType::from_code(&value.to_code().map_err(|_| wrong_value::<Self>(value))?),
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datum::Datum;
+    use crate::value::Value;
+
+    #[test]
+    fn test_type_round_trip() {
+        let types = vec![
+            U8,
+            List(vec![Char, DynMap]),
+            Dict(Box::new(U64), Box::new(Vector(Box::new(F64)))),
+            Maping(map![{Code::Integer(1), (I64, Type)}]),
+            String::to_lamp_type(),
+            Option::<LampType>::to_lamp_type(),
+            LampType::Code,
+            Host(ts("File")),
+        ];
+        for typ in types {
+            assert_eq!(LampType::from_datum(&typ.to_datum()), Ok(typ));
+        }
+    }
+
+    #[test]
+    fn test_validate_datums() {
+        assert_eq!(
+            Datum::new(U8, Value::Integer(255)).map(|d| d.data),
+            Ok(Value::Integer(255))
+        );
+        assert!(ts("text").to_datum().validate().is_ok());
+        assert!(Some(vec![1u8]).to_datum().validate().is_ok());
+        assert!(String::to_lamp_type().to_datum().validate().is_ok());
+        assert!(Datum::new(Type, Value::Integer(1)).is_err());
+    }
+
+    #[test]
+    fn test_validate_errors() {
+        let err = |typ: LampType, code: &str| {
+            typ.validate(&Code::from_str(code).unwrap()[0])
+                .unwrap_err()
+                .to_string()
+        };
+        assert_eq!(err(U8, "\"s\""), "datum: expected [u8] but found \"s\"");
+        assert_eq!(
+            err(I64, "-9223372036854775809"),
+            "datum: -9223372036854775809 does not fit in [i64]"
+        );
+        assert_eq!(
+            err(Vector(Box::new(U8)), "[1 2 256]"),
+            "datum[2]: 256 does not fit in [u8]"
+        );
+        assert_eq!(
+            err(List(vec![U8, Char]), "[1]"),
+            "datum: expected a list of 2 items but found 1"
+        );
+        let point = Struct(map![{ts("x"), U64}, {ts("y"), U64}]);
+        assert_eq!(err(point.clone(), "{x: 1}"), "datum: missing field y");
+        assert_eq!(
+            err(point.clone(), "{x: 1 y: 2 z: 3}"),
+            "datum: unknown field z"
+        );
+        let shape = Enum(map![
+            {ts("Dot"), None},
+            {ts("Line"), Some(Vector(Box::new(point)))},
+        ]);
+        assert_eq!(
+            err(shape.clone(), "[Line [{x: 1 y: 2} {x: 1 y: 'c'}]]"),
+            "datum::Line[1].y: expected [u64] but found 'c'"
+        );
+        assert_eq!(
+            err(shape.clone(), "[Circle]"),
+            "datum: unknown variant Circle"
+        );
+        assert_eq!(
+            err(shape.clone(), "[Dot 1]"),
+            "datum: variant Dot does not take a payload"
+        );
+        assert_eq!(err(shape, "[Line]"), "datum: variant Line needs a payload");
+        assert_eq!(
+            err(Dict(Box::new(Char), Box::new(U8)), "{'a': 1 2: 3}"),
+            "datum[2]: key expected [char] but found 2"
+        );
+        assert_eq!(
+            err(Maping(map![{Code::Integer(1), (U8, F64)}]), "{1: 2.0 3: 4}"),
+            "datum: unknown field 3"
+        );
+        assert_eq!(
+            err(Maping(map![{Code::Integer(1), (U8, F64)}]), "{1: 2}"),
+            "datum[1]: expected [f64] but found 2"
+        );
+    }
+
+    #[test]
+    fn test_bad_type() {
+        let code = Code::List(vec![Code::Identifier(ts("u9"))]);
+        assert_eq!(
+            LampType::from_code(&code),
+            Err(DatumError::WrongShape {
+                expected: Box::new(Type),
+                found: Box::new(code)
+            })
+        );
+
+        // a dict takes exactly a key type and a value type
+        assert_eq!(
+            <(LampType, LampType)>::to_lamp_type(),
+            List(vec![Type, Type])
+        );
+        let types = Code::from_str("[[u8] [u8] [u8]]").unwrap().remove(0);
+        let code = Code::List(vec![Code::Identifier(ts("Dict")), types.clone()]);
+        assert_eq!(
+            LampType::from_code(&code),
+            Err(DatumError::WrongShape {
+                expected: Box::new(List(vec![Type, Type])),
+                found: Box::new(types)
+            })
+        );
+    }
+}
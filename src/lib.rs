@@ -1,7 +1,10 @@
+// lets the derive macros refer to ::lamp_lang from inside this crate
+extern crate self as lamp_lang;
 
 #[macro_use]
 pub mod map;
 
+pub mod builtin;
 pub mod code;
 pub mod lamp_type;
 pub mod parse;
@@ -10,4 +13,13 @@ pub mod runtime;
 pub mod token;
 pub mod utils;
 pub mod datum;
+pub mod json;
+pub mod binary;
+pub mod persistent;
+pub mod value;
+pub mod capability;
+pub mod compile;
+pub mod vm;
+pub mod gc;
+pub mod error;
 //pub mod grouper;
@@ -1,23 +1,81 @@
 use std::io::{self, Write};
 
+use lamp_lang::capability::Capabilities;
 use lamp_lang::code::Code;
-use lamp_lang::parse;
+use lamp_lang::error::Cause;
+use lamp_lang::parse::{parse_located, SourceMap};
+use lamp_lang::runtime::{Limits, Runtime};
 use lamp_lang::token;
 
+// scripts get no capabilities unless the repl is started with
+// --allow read=PATH,write=PATH,env,clock,spawn or --allow all
+fn capabilities() -> Result<Capabilities, String> {
+    let mut capabilities = Capabilities::pure();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--allow" => capabilities.grant(&args.next().ok_or("--allow needs capabilities")?)?,
+            _ => return Err(format!("Unknown argument {}", arg)),
+        }
+    }
+    Ok(capabilities)
+}
+
 fn main() {
     let mut input = String::new();
     let stdin = io::stdin();
     let mut stdout = io::stdout();
+    let capabilities = match capabilities() {
+        Ok(capabilities) => capabilities,
+        Err(err) => {
+            eprintln!("{}", err);
+            eprintln!("usage: lamp_lang [--allow read=PATH,write=PATH,env,clock,spawn]");
+            std::process::exit(2);
+        }
+    };
+    let mut runtime = Runtime::with_capabilities(capabilities);
+    // deep recursion is an error rather than a stack overflow
+    runtime.set_limits(Limits {
+        depth: Some(250),
+        ..Limits::default()
+    });
+    // ctrl-c stops the running evaluation instead of the repl
+    let cancel = runtime.cancel_token();
+    ctrlc::set_handler(move || cancel.cancel()).expect("could not handle ctrl-c");
+    let cancel = runtime.cancel_token();
     loop {
         print!("> ");
         stdout.flush().unwrap();
-        stdin.read_line(&mut input).unwrap();
-        let code = parse::parse(&token::tokenize_from_str(&input.trim()).unwrap()).unwrap();
-        if code.get(0) == Some(&Code::Identifier("exit".to_string())) {
+        // ctrl-d ends the session
+        if stdin.read_line(&mut input).unwrap() == 0 {
+            println!();
+            return;
+        }
+        // the whole line so columns count the whitespace before the code
+        let (code, spans) = match token::tokenize_from_str(&input).and_then(|t| parse_located(&t)) {
+            Ok(parsed) => parsed,
+            Err(err) => {
+                println!("{}", err);
+                input.clear();
+                continue;
+            }
+        };
+        if code.first() == Some(&Code::Identifier("exit".to_string())) {
             return;
         }
-        for expr in code {
-            println!("{:?}", expr.eval().unwrap());
+        // a ctrl-c at the prompt shouldn't stop the next evaluation
+        cancel.reset();
+        let source = SourceMap::new(&input);
+        for (expr, spans) in code.iter().zip(&spans) {
+            match runtime.eval_located(expr, &source.locate(expr, spans)) {
+                Ok(value) => println!("{:?}", value),
+                // the rest of the line is interrupted too
+                Err(err) if err.cause == Cause::Interrupted => {
+                    println!("{}", err);
+                    break;
+                }
+                Err(err) => println!("{}", err),
+            }
         }
         input.clear();
     }
@@ -1,15 +1,123 @@
-use std::collections::HashMap;
+use std::borrow::Borrow;
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
 use std::hash::Hash;
 use std::hash::Hasher;
-use std::ops::Deref;
-use std::ops::DerefMut;
+use std::ops::Index;
 
-#[derive(Debug, Clone, Eq)]
-pub struct Map<K: Eq + Hash + Ord, V: PartialEq + Hash + Ord>(pub HashMap<K, V>);
+// iterates in the order keys were first inserted
+// index holds the positions of the entries sorted by key for lookups
+// hash is the sum of the hashes of the entries, kept up to date on every change
+// so hashing a map is constant time however deeply maps are nested in its keys
+// lookups are O(log n) but insert shifts the index and remove shifts both vecs
+// and renumbers the later positions, so both are O(n), fine for the small maps
+// scripts build, see benches/map_updates.rs for how it grows against BTreeMap
+#[derive(Clone, Eq)]
+pub struct Map<K: Eq + Hash + Ord, V: PartialEq + Hash + Ord> {
+    entries: Vec<(K, V)>,
+    index: Vec<usize>,
+    hash: u64,
+}
+
+// the hasher has fixed keys so equal entries hash the same in every map
+fn entry_hash<K: Hash, V: Hash>(key: &K, value: &V) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    value.hash(&mut hasher);
+    hasher.finish()
+}
 
 impl<K: Eq + Hash + Ord, V: PartialEq + Hash + Ord> Map<K, V> {
+    #[allow(clippy::new_without_default)]
     pub fn new() -> Map<K, V> {
-        Map(HashMap::new())
+        Map {
+            entries: Vec::new(),
+            index: Vec::new(),
+            hash: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // Ok with the place in index of the key or Err with where it would go
+    fn search<Q: Ord + ?Sized>(&self, key: &Q) -> Result<usize, usize>
+    where
+        K: Borrow<Q>,
+    {
+        self.index
+            .binary_search_by(|&i| self.entries[i].0.borrow().cmp(key))
+    }
+
+    pub fn get<Q: Ord + ?Sized>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+    {
+        let i = self.search(key).ok()?;
+        Some(&self.entries[self.index[i]].1)
+    }
+
+    pub fn contains_key<Q: Ord + ?Sized>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+    {
+        self.search(key).is_ok()
+    }
+
+    // a key that is already present keeps its place and gets the new value
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        self.hash = self.hash.wrapping_add(entry_hash(&key, &value));
+        match self.search(&key) {
+            Ok(i) => {
+                let old = std::mem::replace(&mut self.entries[self.index[i]].1, value);
+                self.hash = self.hash.wrapping_sub(entry_hash(&key, &old));
+                Some(old)
+            }
+            Err(i) => {
+                self.index.insert(i, self.entries.len());
+                self.entries.push((key, value));
+                None
+            }
+        }
+    }
+
+    // the entries after the removed one keep their order
+    pub fn remove<Q: Ord + ?Sized>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+    {
+        let i = self.search(key).ok()?;
+        let position = self.index.remove(i);
+        for later in self.index.iter_mut().filter(|p| **p > position) {
+            *later -= 1;
+        }
+        let (key, value) = self.entries.remove(position);
+        self.hash = self.hash.wrapping_sub(entry_hash(&key, &value));
+        Some(value)
+    }
+
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = (&K, &V)> + ExactSizeIterator {
+        self.entries.iter().map(|(k, v)| (k, v))
+    }
+
+    pub fn keys(&self) -> impl DoubleEndedIterator<Item = &K> + ExactSizeIterator {
+        self.entries.iter().map(|(k, _)| k)
+    }
+
+    pub fn values(&self) -> impl DoubleEndedIterator<Item = &V> + ExactSizeIterator {
+        self.entries.iter().map(|(_, v)| v)
+    }
+
+    // the entries in key order whatever order they were inserted in
+    pub fn sorted(&self) -> impl DoubleEndedIterator<Item = (&K, &V)> + ExactSizeIterator {
+        self.index
+            .iter()
+            .map(|&i| (&self.entries[i].0, &self.entries[i].1))
     }
 }
 
@@ -26,60 +134,82 @@ macro_rules! map {
     };
 }
 
-impl<K: Eq + Hash + Ord, V: PartialEq + Hash + Ord> PartialEq for Map<K, V> {
-    fn eq(&self, other: &Self) -> bool {
-        if self.0.keys().count() != other.0.keys().count() {
-            return false;
-        }
+impl<K: Eq + Hash + Ord + fmt::Debug, V: PartialEq + Hash + Ord + fmt::Debug> fmt::Debug
+    for Map<K, V>
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
 
-        for (k, v) in self.0.iter() {
-            if other.0.get(k) != Some(v) {
-                return false;
-            }
+impl<K: Eq + Hash + Ord, V: PartialEq + Hash + Ord> FromIterator<(K, V)> for Map<K, V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut map = Map::new();
+        map.extend(iter);
+        map
+    }
+}
+
+impl<K: Eq + Hash + Ord, V: PartialEq + Hash + Ord> Extend<(K, V)> for Map<K, V> {
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        for (k, v) in iter {
+            self.insert(k, v);
         }
-        true
     }
 }
 
-impl<K: Eq + Hash + Ord, V: PartialEq + Hash + Ord> PartialOrd for Map<K, V> {
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        Some(self.cmp(other))
+impl<K: Eq + Hash + Ord, V: PartialEq + Hash + Ord> IntoIterator for Map<K, V> {
+    type Item = (K, V);
+    type IntoIter = std::vec::IntoIter<(K, V)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.into_iter()
     }
 }
 
-impl<K: Eq + Hash + Ord, V: PartialEq + Hash + Ord> Ord for Map<K, V> {
-    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        let mut s: Vec<_> = self.0.iter().collect();
-        s.sort_by_key(|(k, _)| *k);
-        let mut o: Vec<_> = other.0.iter().collect();
-        o.sort_by_key(|(k, _)| *k);
-        s.cmp(&o)
+impl<'a, K: Eq + Hash + Ord, V: PartialEq + Hash + Ord> IntoIterator for &'a Map<K, V> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = std::iter::Map<std::slice::Iter<'a, (K, V)>, fn(&'a (K, V)) -> (&'a K, &'a V)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.iter().map(|(k, v)| (k, v))
     }
 }
 
-impl<K: Eq + Hash + Ord, V: PartialEq + Hash + Ord> Hash for Map<K, V> {
-    fn hash<H: Hasher>(&self, state: &mut H) {
-        let mut pairs: Vec<_> = self.0.iter().collect();
-        pairs.sort_by_key(|(k, _)| *k);
+impl<K: Eq + Hash + Ord + Borrow<Q>, V: PartialEq + Hash + Ord, Q: Ord + ?Sized> Index<&Q>
+    for Map<K, V>
+{
+    type Output = V;
 
-        for (k, v) in pairs {
-            k.hash(state);
-            v.hash(state);
-        }
+    fn index(&self, key: &Q) -> &V {
+        self.get(key).expect("key not in map")
+    }
+}
+
+// equality, ordering and hashing ignore the insertion order
+// maps with different hashes can't be equal so most unequal maps are told apart without a walk
+impl<K: Eq + Hash + Ord, V: PartialEq + Hash + Ord> PartialEq for Map<K, V> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.hash == other.hash && self.sorted().eq(other.sorted())
     }
 }
 
-impl<K: Eq + Hash + Ord, V: PartialEq + Hash + Ord> Deref for Map<K, V> {
-    type Target = HashMap<K, V>;
+impl<K: Eq + Hash + Ord, V: PartialEq + Hash + Ord> PartialOrd for Map<K, V> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
 
-    fn deref(&self) -> &Self::Target {
-        &self.0
+impl<K: Eq + Hash + Ord, V: PartialEq + Hash + Ord> Ord for Map<K, V> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.sorted().cmp(other.sorted())
     }
 }
 
-impl<K: Eq + Hash + Ord, V: PartialEq + Hash + Ord> DerefMut for Map<K, V> {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.0
+impl<K: Eq + Hash + Ord, V: PartialEq + Hash + Ord> Hash for Map<K, V> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_usize(self.len());
+        state.write_u64(self.hash);
     }
 }
 
@@ -89,15 +219,15 @@ mod tests {
 
     #[test]
     fn test_map_eq() {
-        let mut map = Map(HashMap::new());
+        let mut map = Map::new();
         map.insert('a', 42);
         map.insert('c', 56);
 
-        let mut eq = Map(HashMap::new());
+        let mut eq = Map::new();
         eq.insert('c', 56);
         eq.insert('a', 42);
 
-        let mut diff = Map(HashMap::new());
+        let mut diff = Map::new();
         diff.insert('c', 56);
         diff.insert('a', 42);
         diff.insert('j', 79);
@@ -108,7 +238,7 @@ mod tests {
 
     #[test]
     fn test_constructor_macro() {
-        let mut map = Map(HashMap::new());
+        let mut map = Map::new();
         map.insert('a', 42);
         map.insert('c', 56);
         assert_eq!(map, map![{'a', 42}, {'c', 56}],);
@@ -123,15 +253,60 @@ mod tests {
             {'y', two.clone()},
         ];
 
-        assert_eq!(nested.0[&'a'], one);
-        assert_eq!(nested.0[&'y'], two);
+        assert_eq!(nested[&'a'], one);
+        assert_eq!(nested[&'y'], two);
 
         let nested = map![
             {one.clone(), 'a'},
             {two.clone(), 'y'},
         ];
 
-        assert_eq!(nested.0.get(&one), Some(&'a'));
-        assert_eq!(nested.0.get(&two), Some(&'y'));
+        assert_eq!(nested.get(&one), Some(&'a'));
+        assert_eq!(nested.get(&two), Some(&'y'));
+    }
+
+    #[test]
+    fn test_insertion_order() {
+        let mut map = map![{'q', 1}, {'b', 2}, {'x', 3}, {'a', 4}];
+        assert_eq!(map.keys().copied().collect::<String>(), "qbxa");
+        assert_eq!(map.sorted().map(|(k, _)| *k).collect::<String>(), "abqx");
+
+        assert_eq!(map.insert('b', 5), Some(2));
+        assert_eq!(map.remove(&'q'), Some(1));
+        assert_eq!(map.remove(&'q'), None);
+        map.insert('q', 6);
+        assert_eq!(
+            map.iter().map(|(k, v)| (*k, *v)).collect::<Vec<_>>(),
+            vec![('b', 5), ('x', 3), ('a', 4), ('q', 6)]
+        );
+        assert_eq!(map.get(&'x'), Some(&3));
+        assert!(!map.contains_key(&'z'));
+        assert_eq!(format!("{:?}", map), "{'b': 5, 'x': 3, 'a': 4, 'q': 6}");
+    }
+
+    #[test]
+    fn test_order_independent() {
+        use std::collections::hash_map::DefaultHasher;
+
+        let hash = |map: &Map<char, i32>| {
+            let mut hasher = DefaultHasher::new();
+            map.hash(&mut hasher);
+            hasher.finish()
+        };
+        let one = map![{'a', 1}, {'b', 2}];
+        let two = map![{'b', 2}, {'a', 1}];
+        assert_eq!(one, two);
+        assert_eq!(one.cmp(&two), std::cmp::Ordering::Equal);
+        assert_eq!(hash(&one), hash(&two));
+
+        // the cached hash follows replaced and removed entries
+        let mut three = map![{'c', 3}, {'a', 7}, {'b', 2}];
+        three.insert('a', 1);
+        three.remove(&'c');
+        assert_eq!(three, one);
+        assert_eq!(hash(&three), hash(&one));
+        three.remove(&'a');
+        assert_ne!(three, one);
+        assert_eq!(hash(&three), hash(&map![{'b', 2}]));
     }
 }
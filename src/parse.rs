@@ -1,4 +1,7 @@
+use std::fmt;
+
 use crate::map::Map;
+use crate::token::StrPart;
 use crate::token::TokenKind as Tk;
 use crate::{code::Code, queue::Queue, token::Token};
 
@@ -13,83 +16,308 @@ pub fn parse(tokens: &[Token]) -> Result<Vec<Code>, String> {
     Ok(exprs)
 }
 
+// parses keeping the spans of the code, for a SourceMap
+pub fn parse_located(tokens: &[Token]) -> Result<(Vec<Code>, Vec<Spans>), String> {
+    let mut queue = Queue::new(tokens);
+    let mut exprs = Vec::new();
+    let mut spans = Vec::new();
+
+    while let Some((code, span)) = queue.pop_located()? {
+        exprs.push(code);
+        spans.push(span);
+    }
+
+    Ok((exprs, spans))
+}
+
 pub fn parse_to_pgm(tokens: &[Token]) -> Result<Code, String> {
     let mut pgm = vec![Code::Identifier("pgm".to_string())];
     pgm.append(&mut parse(tokens)?);
     Ok(Code::List(pgm))
 }
 
+// where some code is in the source, in chars like the token offsets
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+// the span of some code and the spans of the items of a list or the values of a map
+// code made up by the parser, like that of an interpolated string, has no items
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Spans {
+    pub span: Span,
+    pub items: Vec<Spans>,
+}
+
+// a line and column in the source, both counted from 1
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Location {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+// where the lists of some code were written, shaped like the code so copies of it can take it
+// along, the items are those of a list or the values of a map in the order the map iterates
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Located {
+    pub at: Option<Location>,
+    pub items: Vec<Located>,
+}
+
+// code that wasn't parsed from a source, or that the parser made up
+pub(crate) static NOWHERE: Located = Located {
+    at: None,
+    items: Vec::new(),
+};
+
+impl Located {
+    pub(crate) fn item(&self, i: usize) -> &Located {
+        self.items.get(i).unwrap_or(&NOWHERE)
+    }
+
+    // the locations of the value of a field of the map
+    pub(crate) fn field(&self, map: &Map<Code, Code>, name: &str) -> &Located {
+        if self.items.is_empty() {
+            return &NOWHERE;
+        }
+        let key = Code::Identifier(name.to_string());
+        match map.keys().position(|k| *k == key) {
+            Some(i) => self.item(i),
+            None => &NOWHERE,
+        }
+    }
+}
+
+// the lines of a source, to turn the spans of code parsed from it into locations
+pub struct SourceMap {
+    lines: Vec<usize>,
+}
+
+impl SourceMap {
+    pub fn new(source: &str) -> SourceMap {
+        let mut lines = vec![0];
+        for (at, c) in source.chars().enumerate() {
+            if c == '\n' {
+                lines.push(at + 1);
+            }
+        }
+        SourceMap { lines }
+    }
+
+    pub fn location(&self, at: usize) -> Location {
+        let line = self.lines.partition_point(|&line_start| line_start <= at);
+        let column = at - self.lines[line - 1] + 1;
+        Location { line, column }
+    }
+
+    // where the lists of the code with the spans were written
+    pub fn locate(&self, code: &Code, spans: &Spans) -> Located {
+        let items: Vec<&Code> = match code {
+            Code::List(items) => items.iter().collect(),
+            Code::Map(map) => map.values().collect(),
+            _ => return Located::default(),
+        };
+        Located {
+            at: matches!(code, Code::List(_)).then(|| self.location(spans.span.start)),
+            items: items
+                .into_iter()
+                .zip(&spans.items)
+                .map(|(item, spans)| self.locate(item, spans))
+                .collect(),
+        }
+    }
+}
+
+// "a{x}b" => [concat "a" [display x] "b"]
+fn interpolate(parts: &[StrPart]) -> Result<Code, String> {
+    let mut concat = vec![Code::Identifier("concat".to_string())];
+    for part in parts {
+        match part {
+            StrPart::Text(s) => concat.push(Code::StringLiteral(s.clone())),
+            StrPart::Code(tokens) => {
+                let mut exprs = parse(tokens)?;
+                if exprs.len() != 1 {
+                    return Err(
+                        "String interpolation must contain exactly one expression".to_string()
+                    );
+                }
+                concat.push(Code::List(vec![
+                    Code::Identifier("display".to_string()),
+                    exprs.pop().unwrap(),
+                ]));
+            }
+        }
+    }
+    Ok(Code::List(concat))
+}
+
 impl<'a> Queue<'a, Token> {
     pub fn pop_whitespace(&mut self) {
         self.pop_while(|t| t.is_whitespace());
     }
 
+    fn starts_collection(&self) -> bool {
+        matches!(
+            self.peak().map(|t| &t.kind),
+            Some(Tk::Lfn) | Some(Tk::Lcond)
+        )
+    }
+
+    // skips each '/' right before a list or map and the expression it comments out
+    fn pop_expr_comments(&mut self) -> Result<(), String> {
+        self.pop_whitespace();
+        while matches!(self.peak().map(|t| &t.kind), Some(Tk::Symbol(s)) if s == "/")
+            && matches!(
+                self.peak_nth(1).map(|t| &t.kind),
+                Some(Tk::Lfn) | Some(Tk::Lcond)
+            )
+        {
+            self.pop();
+            self.pop_located()?;
+        }
+        Ok(())
+    }
+
     pub fn pop_code(&mut self) -> Result<Option<Code>, String> {
+        Ok(self.pop_located()?.map(|(code, _)| code))
+    }
+
+    // the code with the spans of it and what is inside it
+    pub fn pop_located(&mut self) -> Result<Option<(Code, Spans)>, String> {
         if self.empty() {
             return Ok(None);
         }
 
-        let code = match &self.pop().unwrap().kind {
+        let at = self.cursor;
+        let token = self.pop().unwrap();
+        let mut items = Vec::new();
+        let code = match &token.kind {
             Tk::Whitespace(_) | Tk::Comment(_) => {
                 self.pop_whitespace();
-                return self.pop_code();
+                return self.pop_located();
             }
             Tk::Integer(num) => Code::Integer(*num),
             Tk::Float(num) => Code::Float(*num),
+            // typed literals become a cast to their suffix type: 42u8 => [u8 42]
+            Tk::TypedInteger(num, typ) => {
+                Code::List(vec![Code::Identifier(typ.clone()), Code::Integer(*num)])
+            }
+            Tk::TypedFloat(num, typ) => {
+                Code::List(vec![Code::Identifier(typ.clone()), Code::Float(*num)])
+            }
             Tk::Character(c) => Code::Character(*c),
             Tk::StringLiteral(s) => Code::StringLiteral(s.clone()),
+            // byte strings use the same list of integers as Vec<u8>
+            Tk::ByteString(b) => Code::List(b.iter().map(|b| Code::Integer((*b).into())).collect()),
+            Tk::Interpolated(parts) => interpolate(parts)?,
             Tk::Identifier(s) => Code::Identifier(s.clone()),
-            Tk::Lfn => self.pop_list()?,
-            Tk::Lcond => self.pop_map()?,
+            // a '/' right before a list or map comments out that expression
+            Tk::Symbol(s) if s == "/" && self.starts_collection() => {
+                self.pop_located()?;
+                return self.pop_located();
+            }
+            // operator symbols are called like any other function name
+            Tk::Symbol(s) => Code::Identifier(s.clone()),
+            Tk::Lfn => {
+                let (list, spans) = self.pop_list()?;
+                items = spans;
+                list
+            }
+            Tk::Lcond => {
+                let (map, spans) = self.pop_map()?;
+                items = spans;
+                map
+            }
             _ => {
                 // we don't want to modify the queue on error
-                self.cursor -= 1;
+                self.cursor = at;
                 return Err("Unexpected Token".to_string());
             }
         };
 
+        // the last token popped closes the code
+        let span = Span {
+            start: token.start,
+            end: self.data[self.cursor - 1].end(),
+        };
         self.pop_whitespace();
-        Ok(Some(code))
+        Ok(Some((code, Spans { span, items })))
     }
 
-    pub fn pop_list(&mut self) -> Result<Code, String> {
+    pub fn pop_list(&mut self) -> Result<(Code, Vec<Spans>), String> {
         let mut parsed = Vec::new();
-        while let Ok(Some(code)) = self.pop_code() {
+        let mut spans = Vec::new();
+        loop {
+            self.pop_expr_comments()?;
+            // the match below checks the list is closed by the right token
+            if matches!(
+                self.peak().map(|t| &t.kind),
+                Some(Tk::Rfn) | Some(Tk::Rcond) | Some(Tk::FieldDelim) | None
+            ) {
+                break;
+            }
+            let (code, span) = self
+                .pop_located()?
+                .ok_or("Reached End of File while parsing List")?;
             parsed.push(code);
+            spans.push(span);
         }
 
         match self.pop().map(|t| &t.kind) {
-            Some(Tk::Rfn) => Ok(Code::List(parsed)),
+            Some(Tk::Rfn) => Ok((Code::List(parsed), spans)),
             None => Err("Reached End of File while parsing List".to_string()),
             _ => Err("Unexpected Token while parsing List".to_string()),
         }
     }
 
-    pub fn pop_map(&mut self) -> Result<Code, String> {
+    // the spans are of the values, in the order the map keeps them
+    pub fn pop_map(&mut self) -> Result<(Code, Vec<Spans>), String> {
         let mut parsed: Map<Code, Code> = Map::new();
+        let mut spans: Vec<Spans> = Vec::new();
         let eof_str = String::from("Reached end of file while parsing map");
 
-        let cop = self.pop_code()?.ok_or(eof_str.clone())?;
+        let (cop, cop_spans) = self.pop_located()?.ok_or(eof_str.clone())?;
         if self.peak().ok_or(eof_str.clone())?.kind == Tk::FieldDelim {
             self.pop();
-            let value = self.pop_code()?.ok_or(eof_str.clone())?;
+            let (value, value_spans) = self.pop_located()?.ok_or(eof_str.clone())?;
             parsed.insert(cop, value);
+            spans.push(value_spans);
         } else {
             parsed.insert(Code::Identifier("head_position_field".to_string()), cop);
+            spans.push(cop_spans);
         }
 
-        while let Ok((field, value)) = self.pop_map_pair() {
+        // a field left without a value is an error rather than the end of the map
+        loop {
+            self.pop_expr_comments()?;
+            if matches!(self.peak().map(|t| &t.kind), Some(Tk::Rcond) | None) {
+                break;
+            }
+            let (field, value, value_spans) = self.pop_map_pair()?;
+            // a repeated field keeps its place with the new value
+            match parsed.keys().position(|key| *key == field) {
+                Some(at) => spans[at] = value_spans,
+                None => spans.push(value_spans),
+            }
             parsed.insert(field, value);
         }
 
         match self.pop().map(|t| &t.kind) {
-            Some(Tk::Rcond) => Ok(Code::Map(parsed)),
+            Some(Tk::Rcond) => Ok((Code::Map(parsed), spans)),
             None => Err("Reached End of File while parsing Map".to_string()),
             _ => Err("Unexpected Token while parsing Map".to_string()),
         }
     }
 
-    pub fn pop_map_pair(&mut self) -> Result<(Code, Code), String> {
+    pub fn pop_map_pair(&mut self) -> Result<(Code, Code, Spans), String> {
         let eof_str = String::from("Reached end of file while parsing map");
 
         let field = self.pop_code()?.ok_or(eof_str.clone())?;
@@ -98,17 +326,17 @@ impl<'a> Queue<'a, Token> {
         } else {
             self.pop();
         }
-        let value = self.pop_code()?.ok_or(eof_str.clone())?;
+        let (value, spans) = self.pop_located()?.ok_or(eof_str.clone())?;
 
-        Ok((field, value))
+        Ok((field, value, spans))
     }
 }
 
 #[cfg(test)]
+#[allow(clippy::approx_constant)]
 mod tests {
     use super::*;
     use crate::code::Code::*;
-    use crate::map;
     use crate::map::Map;
 
     #[test]
@@ -138,6 +366,123 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_typed_numbers() {
+        assert_eq!(
+            Code::from_str("42u8 -3.0f32").unwrap(),
+            vec![
+                List(vec![Identifier("u8".to_string()), Integer(42)]),
+                List(vec![
+                    Identifier("f32".to_string()),
+                    Float((-3.0_f64).to_bits())
+                ]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_byte_string() {
+        assert_eq!(
+            Code::from_str("b\"hi\"").unwrap()[0],
+            List(vec![Integer(104), Integer(105)])
+        );
+    }
+
+    #[test]
+    fn test_interpolation() {
+        assert_eq!(
+            Code::from_str("\"total: {[plus a b]}!\"").unwrap()[0],
+            List(vec![
+                Identifier("concat".to_string()),
+                StringLiteral("total: ".to_string()),
+                List(vec![
+                    Identifier("display".to_string()),
+                    List(vec![
+                        Identifier("plus".to_string()),
+                        Identifier("a".to_string()),
+                        Identifier("b".to_string()),
+                    ]),
+                ]),
+                StringLiteral("!".to_string()),
+            ])
+        );
+        assert_eq!(
+            Code::from_str("\"{a b}\"").unwrap_err(),
+            "String interpolation must contain exactly one expression"
+        );
+    }
+
+    #[test]
+    fn test_symbols() {
+        assert_eq!(
+            Code::from_str("[<= a [+ 1 2]]").unwrap()[0],
+            List(vec![
+                Identifier("<=".to_string()),
+                Identifier("a".to_string()),
+                List(vec![Identifier("+".to_string()), Integer(1), Integer(2)]),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_expression_comments() {
+        assert_eq!(
+            Code::from_str("/[print 1] 2 /{if c: x} [f /[g /[h]] 3 /[i]]").unwrap(),
+            vec![
+                Integer(2),
+                List(vec![Identifier("f".to_string()), Integer(3)]),
+            ]
+        );
+        assert_eq!(
+            Code::from_str("{a: 1 /[b 2] c: /{d} 3}").unwrap()[0],
+            Code::Map(map![
+                {Identifier("a".to_string()), Integer(1)},
+                {Identifier("c".to_string()), Integer(3)},
+            ])
+        );
+        // with whitespace after it '/' is just the divide operator
+        assert_eq!(
+            Code::from_str("[/ 6 3]").unwrap()[0],
+            List(vec![Identifier("/".to_string()), Integer(6), Integer(3)])
+        );
+        assert_eq!(Code::from_str("/[a] /[b]").unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_expression_comments_keep_tokens() {
+        let tokens = crate::token::tokenize_from_str("/[a]").unwrap();
+        assert_eq!(tokens.len(), 4);
+        assert_eq!(tokens[0].kind, Tk::Symbol("/".to_string()));
+        assert_eq!(parse(&tokens).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_locations() {
+        let source = "[f 1]\n  {if c: [g 'x'] do: [h /[skipped] [i]]}";
+        let tokens = crate::token::tokenize_from_str(source).unwrap();
+        let (code, spans) = parse_located(&tokens).unwrap();
+        assert_eq!(code, Code::from_str(source).unwrap());
+        assert_eq!(spans[0].span, Span { start: 0, end: 5 });
+        assert_eq!(spans[1].span, Span { start: 8, end: 46 });
+
+        let source = SourceMap::new(source);
+        let at = |line, column| Some(Location { line, column });
+        let first = source.locate(&code[0], &spans[0]);
+        assert_eq!(first.at, at(1, 1));
+        let Map(form) = &code[1] else {
+            panic!("expected a map")
+        };
+        let located = source.locate(&code[1], &spans[1]);
+        assert_eq!(located.field(form, "c").at, at(2, 10));
+        let call = located.field(form, "do");
+        assert_eq!(call.at, at(2, 22));
+        assert_eq!(call.item(1).at, at(2, 36));
+        // maps and code that isn't a list have no location
+        assert_eq!(located.at, None);
+        assert_eq!(call.item(0).at, None);
+        assert_eq!(located.field(form, "else"), &NOWHERE);
+    }
+
     #[test]
     fn test_hello_world() {
         assert_eq!(
@@ -170,6 +515,16 @@ mod tests {
                 List(vec![Integer(1), List(vec![Integer(2), Integer(3)])]),
             ])]
         );
+
+        // errors inside the list aren't lost
+        assert_eq!(
+            Code::from_str("[a \"{b c}\" d]").unwrap_err(),
+            "String interpolation must contain exactly one expression"
+        );
+        assert_eq!(
+            Code::from_str("[a b: c]").unwrap_err(),
+            "Unexpected Token while parsing List"
+        );
     }
 
     #[test]
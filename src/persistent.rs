@@ -0,0 +1,883 @@
+use std::borrow::Borrow;
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops::Index;
+use std::rc::Rc;
+
+use crate::gc::{Trace, Tracer};
+
+// persistent collections share structure between versions
+// cloning is O(1) and an update copies only the O(log n) nodes on the path it changes,
+// nodes that are not shared are updated in place
+
+const BITS: usize = 5;
+const WIDTH: usize = 1 << BITS;
+const MASK: usize = WIDTH - 1;
+
+#[derive(Clone)]
+enum VecNode<T> {
+    Branch(Vec<Rc<VecNode<T>>>),
+    Leaf(Vec<T>),
+}
+
+// a 32 way trie with the last up to 32 items kept in a tail
+// so pushing and popping at the end is usually O(1)
+#[derive(Clone)]
+pub struct PVec<T: Clone> {
+    len: usize,
+    // the bits the index is shifted by at the root
+    shift: usize,
+    root: Rc<VecNode<T>>,
+    tail: Rc<Vec<T>>,
+}
+
+impl<T: Clone> PVec<T> {
+    pub fn new() -> PVec<T> {
+        PVec {
+            len: 0,
+            shift: BITS,
+            root: Rc::new(VecNode::Branch(Vec::new())),
+            tail: Rc::new(Vec::new()),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // index of the first item in the tail
+    fn tail_offset(&self) -> usize {
+        if self.len < WIDTH {
+            0
+        } else {
+            ((self.len - 1) >> BITS) << BITS
+        }
+    }
+
+    // the leaf of the trie holding the index
+    fn leaf(&self, index: usize) -> &[T] {
+        let mut node = &self.root;
+        let mut level = self.shift;
+        loop {
+            match node.as_ref() {
+                VecNode::Branch(children) => node = &children[(index >> level) & MASK],
+                VecNode::Leaf(items) => return items,
+            }
+            level -= BITS;
+        }
+    }
+
+    pub fn get(&self, index: usize) -> Option<&T> {
+        if index >= self.len {
+            None
+        } else if index >= self.tail_offset() {
+            Some(&self.tail[index - self.tail_offset()])
+        } else {
+            Some(&self.leaf(index)[index & MASK])
+        }
+    }
+
+    pub fn first(&self) -> Option<&T> {
+        self.get(0)
+    }
+
+    pub fn last(&self) -> Option<&T> {
+        self.len.checked_sub(1).and_then(|i| self.get(i))
+    }
+
+    // replaces the item at the index, returning false if it is out of range
+    pub fn set(&mut self, index: usize, value: T) -> bool {
+        if index >= self.len {
+            return false;
+        }
+        let offset = self.tail_offset();
+        if index >= offset {
+            Rc::make_mut(&mut self.tail)[index - offset] = value;
+            return true;
+        }
+        let mut node = &mut self.root;
+        let mut level = self.shift;
+        loop {
+            match Rc::make_mut(node) {
+                VecNode::Branch(children) => node = &mut children[(index >> level) & MASK],
+                VecNode::Leaf(items) => {
+                    items[index & MASK] = value;
+                    return true;
+                }
+            }
+            level -= BITS;
+        }
+    }
+
+    pub fn push(&mut self, value: T) {
+        if self.tail.len() < WIDTH {
+            Rc::make_mut(&mut self.tail).push(value);
+            self.len += 1;
+            return;
+        }
+
+        // the tail is full so it moves into the trie
+        let leaf = Rc::new(VecNode::Leaf(std::mem::replace(
+            Rc::make_mut(&mut self.tail),
+            vec![value],
+        )));
+        let tail_index = self.len - WIDTH;
+        if (self.len >> BITS) > (1 << self.shift) {
+            // the trie is full so it gets a new root a level higher
+            let path = new_path(self.shift, leaf);
+            let old = std::mem::replace(&mut self.root, Rc::new(VecNode::Branch(Vec::new())));
+            self.root = Rc::new(VecNode::Branch(vec![old, path]));
+            self.shift += BITS;
+        } else {
+            push_leaf(&mut self.root, self.shift, tail_index, leaf);
+        }
+        self.len += 1;
+    }
+
+    pub fn pop(&mut self) -> Option<T> {
+        if self.len == 0 {
+            return None;
+        }
+        if self.tail.len() > 1 || self.len == 1 {
+            self.len -= 1;
+            return Rc::make_mut(&mut self.tail).pop();
+        }
+
+        // the tail would be empty so the last leaf of the trie becomes the tail
+        let value = Rc::make_mut(&mut self.tail).pop();
+        self.len -= 1;
+        self.tail = Rc::new(pop_leaf(&mut self.root, self.shift, self.len - 1));
+        if self.shift > BITS {
+            if let VecNode::Branch(children) = self.root.as_ref() {
+                if children.len() == 1 {
+                    self.root = children[0].clone();
+                    self.shift -= BITS;
+                }
+            }
+        }
+        value
+    }
+
+    pub fn iter(&self) -> PVecIter<'_, T> {
+        PVecIter {
+            vec: self,
+            index: 0,
+            leaf: &[],
+        }
+    }
+}
+
+// a branch down to the level holding only the leaf
+fn new_path<T>(level: usize, leaf: Rc<VecNode<T>>) -> Rc<VecNode<T>> {
+    if level == 0 {
+        leaf
+    } else {
+        Rc::new(VecNode::Branch(vec![new_path(level - BITS, leaf)]))
+    }
+}
+
+// index is the index of the first item of the leaf
+fn push_leaf<T: Clone>(
+    node: &mut Rc<VecNode<T>>,
+    level: usize,
+    index: usize,
+    leaf: Rc<VecNode<T>>,
+) {
+    if let VecNode::Branch(children) = Rc::make_mut(node) {
+        let sub = (index >> level) & MASK;
+        if level == BITS {
+            children.push(leaf);
+        } else if sub < children.len() {
+            push_leaf(&mut children[sub], level - BITS, index, leaf);
+        } else {
+            children.push(new_path(level - BITS, leaf));
+        }
+    }
+}
+
+// removes the leaf holding the index, dropping branches it leaves empty
+fn pop_leaf<T: Clone>(node: &mut Rc<VecNode<T>>, level: usize, index: usize) -> Vec<T> {
+    let VecNode::Branch(children) = Rc::make_mut(node) else {
+        unreachable!("pop_leaf is only called on branches")
+    };
+    let sub = (index >> level) & MASK;
+    if level == BITS {
+        let leaf = children.pop().unwrap();
+        return match Rc::try_unwrap(leaf) {
+            Ok(VecNode::Leaf(items)) => items,
+            Ok(VecNode::Branch(_)) => unreachable!(),
+            Err(shared) => match shared.as_ref() {
+                VecNode::Leaf(items) => items.clone(),
+                VecNode::Branch(_) => unreachable!(),
+            },
+        };
+    }
+    let items = pop_leaf(&mut children[sub], level - BITS, index);
+    if matches!(children[sub].as_ref(), VecNode::Branch(c) if c.is_empty()) {
+        children.pop();
+    }
+    items
+}
+
+impl<T: Clone> Default for PVec<T> {
+    fn default() -> Self {
+        PVec::new()
+    }
+}
+
+pub struct PVecIter<'a, T: Clone> {
+    vec: &'a PVec<T>,
+    index: usize,
+    leaf: &'a [T],
+}
+
+impl<'a, T: Clone> Iterator for PVecIter<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        if self.index >= self.vec.len {
+            return None;
+        }
+        // a whole leaf is looked up at a time instead of walking the trie per item
+        if self.index & MASK == 0 || self.leaf.is_empty() {
+            let offset = self.vec.tail_offset();
+            self.leaf = if self.index >= offset {
+                &self.vec.tail[self.index - offset..]
+            } else {
+                &self.vec.leaf(self.index)[self.index & MASK..]
+            };
+        }
+        let (item, rest) = self.leaf.split_first()?;
+        self.leaf = rest;
+        self.index += 1;
+        Some(item)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let left = self.vec.len - self.index;
+        (left, Some(left))
+    }
+}
+
+impl<T: Clone> ExactSizeIterator for PVecIter<'_, T> {}
+
+impl<'a, T: Clone> IntoIterator for &'a PVec<T> {
+    type Item = &'a T;
+    type IntoIter = PVecIter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<T: Clone> FromIterator<T> for PVec<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut vec = PVec::new();
+        vec.extend(iter);
+        vec
+    }
+}
+
+impl<T: Clone> Extend<T> for PVec<T> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        for item in iter {
+            self.push(item);
+        }
+    }
+}
+
+impl<T: Clone> Index<usize> for PVec<T> {
+    type Output = T;
+
+    fn index(&self, index: usize) -> &T {
+        self.get(index).expect("index out of range")
+    }
+}
+
+impl<T: Clone + fmt::Debug> fmt::Debug for PVec<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl<T: Clone + PartialEq> PartialEq for PVec<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len == other.len && self.iter().eq(other.iter())
+    }
+}
+
+impl<T: Clone + Eq> Eq for PVec<T> {}
+
+impl<T: Clone + PartialOrd> PartialOrd for PVec<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        self.iter().partial_cmp(other.iter())
+    }
+}
+
+impl<T: Clone + Ord> Ord for PVec<T> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.iter().cmp(other.iter())
+    }
+}
+
+impl<T: Clone + Hash> Hash for PVec<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_usize(self.len);
+        self.iter().for_each(|item| item.hash(state));
+    }
+}
+
+// the nodes are boxes of their own, shared between versions
+impl<T: Trace + 'static> Trace for VecNode<T> {
+    fn trace(&self, tracer: &mut Tracer) {
+        match self {
+            VecNode::Branch(children) => children.iter().for_each(|c| tracer.reference(c)),
+            VecNode::Leaf(items) => items.trace(tracer),
+        }
+    }
+}
+
+impl<T: Clone + Trace + 'static> Trace for PVec<T> {
+    fn trace(&self, tracer: &mut Tracer) {
+        tracer.reference(&self.root);
+        tracer.reference(&self.tail);
+    }
+}
+
+#[derive(Clone)]
+enum MapEntry<K, V> {
+    Pair(u64, K, V),
+    Node(Rc<MapNode<K, V>>),
+    // keys whose whole hashes are equal
+    Collision(u64, Rc<Vec<(K, V)>>),
+}
+
+// bit i of the bitmap is set when the node has an entry for the 5 hash bits i,
+// the entries are stored densely in bit order
+#[derive(Clone)]
+struct MapNode<K, V> {
+    bitmap: u32,
+    entries: Vec<MapEntry<K, V>>,
+}
+
+// a hash array mapped trie, each level uses the next 5 bits of the key's hash
+#[derive(Clone)]
+pub struct PMap<K: Hash + Eq + Clone, V: Clone> {
+    len: usize,
+    root: Rc<MapNode<K, V>>,
+}
+
+// the hasher has fixed keys so a map iterates in the same order on every run
+fn key_hash<Q: Hash + ?Sized>(key: &Q) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn bit(hash: u64, shift: usize) -> u32 {
+    1 << ((hash >> shift) as usize & MASK)
+}
+
+impl<K: Clone, V: Clone> MapNode<K, V> {
+    fn position(&self, bit: u32) -> usize {
+        (self.bitmap & (bit - 1)).count_ones() as usize
+    }
+
+    // a node holding two pairs with different keys
+    fn pair(shift: usize, one: MapEntry<K, V>, two: MapEntry<K, V>) -> MapNode<K, V> {
+        let (MapEntry::Pair(h1, ..), MapEntry::Pair(h2, ..)) = (&one, &two) else {
+            unreachable!("only pairs are split")
+        };
+        let (b1, b2) = (bit(*h1, shift), bit(*h2, shift));
+        let entries = if b1 == b2 {
+            vec![MapEntry::Node(Rc::new(MapNode::pair(
+                shift + BITS,
+                one,
+                two,
+            )))]
+        } else if b1 < b2 {
+            vec![one, two]
+        } else {
+            vec![two, one]
+        };
+        MapNode {
+            bitmap: b1 | b2,
+            entries,
+        }
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> PMap<K, V> {
+    pub fn new() -> PMap<K, V> {
+        PMap {
+            len: 0,
+            root: Rc::new(MapNode {
+                bitmap: 0,
+                entries: Vec::new(),
+            }),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get<Q: Hash + Eq + ?Sized>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+    {
+        let hash = key_hash(key);
+        let mut node = &self.root;
+        let mut shift = 0;
+        loop {
+            let bit = bit(hash, shift);
+            if node.bitmap & bit == 0 {
+                return None;
+            }
+            match &node.entries[node.position(bit)] {
+                MapEntry::Pair(h, k, v) => {
+                    return (*h == hash && k.borrow() == key).then_some(v);
+                }
+                MapEntry::Collision(h, pairs) => {
+                    if *h != hash {
+                        return None;
+                    }
+                    return pairs
+                        .iter()
+                        .find(|(k, _)| k.borrow() == key)
+                        .map(|(_, v)| v);
+                }
+                MapEntry::Node(child) => node = child,
+            }
+            shift += BITS;
+        }
+    }
+
+    pub fn contains_key<Q: Hash + Eq + ?Sized>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+    {
+        self.get(key).is_some()
+    }
+
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let hash = key_hash(&key);
+        let old = insert_entry(&mut self.root, 0, hash, key, value);
+        if old.is_none() {
+            self.len += 1;
+        }
+        old
+    }
+
+    pub fn remove<Q: Hash + Eq + ?Sized>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+    {
+        // checking first keeps a missing key from copying shared nodes
+        if !self.contains_key(key) {
+            return None;
+        }
+        let old = remove_entry(&mut self.root, 0, key_hash(key), key);
+        self.len -= 1;
+        old
+    }
+
+    pub fn iter(&self) -> PMapIter<'_, K, V> {
+        PMapIter {
+            stack: vec![self.root.entries.iter()],
+            collision: [].iter(),
+            left: self.len,
+        }
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.iter().map(|(k, _)| k)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &V> {
+        self.iter().map(|(_, v)| v)
+    }
+}
+
+fn insert_entry<K: Eq + Clone, V: Clone>(
+    node: &mut Rc<MapNode<K, V>>,
+    shift: usize,
+    hash: u64,
+    key: K,
+    value: V,
+) -> Option<V> {
+    let node = Rc::make_mut(node);
+    let bit = bit(hash, shift);
+    let position = node.position(bit);
+    if node.bitmap & bit == 0 {
+        node.bitmap |= bit;
+        node.entries
+            .insert(position, MapEntry::Pair(hash, key, value));
+        return None;
+    }
+
+    let entry = &mut node.entries[position];
+    match entry {
+        MapEntry::Node(child) => insert_entry(child, shift + BITS, hash, key, value),
+        MapEntry::Pair(h, k, v) if *h == hash && *k == key => Some(std::mem::replace(v, value)),
+        MapEntry::Pair(h, k, v) if *h == hash => {
+            let pairs = vec![(k.clone(), v.clone()), (key, value)];
+            *entry = MapEntry::Collision(hash, Rc::new(pairs));
+            None
+        }
+        MapEntry::Pair(..) => {
+            let old = std::mem::replace(entry, MapEntry::Collision(0, Rc::new(Vec::new())));
+            let new = MapEntry::Pair(hash, key, value);
+            *entry = MapEntry::Node(Rc::new(MapNode::pair(shift + BITS, old, new)));
+            None
+        }
+        MapEntry::Collision(h, pairs) if *h == hash => {
+            let pairs = Rc::make_mut(pairs);
+            match pairs.iter_mut().find(|(k, _)| *k == key) {
+                Some((_, v)) => Some(std::mem::replace(v, value)),
+                None => {
+                    pairs.push((key, value));
+                    None
+                }
+            }
+        }
+        MapEntry::Collision(h, _) => {
+            // split the bucket from the new key on the bits after this level
+            let old_hash = *h;
+            let old = std::mem::replace(entry, MapEntry::Collision(0, Rc::new(Vec::new())));
+            let mut child = MapNode {
+                bitmap: 0,
+                entries: Vec::new(),
+            };
+            let old_bit = self::bit(old_hash, shift + BITS);
+            child.bitmap = old_bit;
+            child.entries.push(old);
+            let mut child = Rc::new(child);
+            let result = insert_entry(&mut child, shift + BITS, hash, key, value);
+            *entry = MapEntry::Node(child);
+            result
+        }
+    }
+}
+
+// the key is known to be present
+fn remove_entry<K: Eq + Clone + Borrow<Q>, V: Clone, Q: Eq + ?Sized>(
+    node: &mut Rc<MapNode<K, V>>,
+    shift: usize,
+    hash: u64,
+    key: &Q,
+) -> Option<V> {
+    let node = Rc::make_mut(node);
+    let bit = bit(hash, shift);
+    let position = node.position(bit);
+    if let MapEntry::Pair(..) = node.entries[position] {
+        node.bitmap &= !bit;
+        return match node.entries.remove(position) {
+            MapEntry::Pair(_, _, v) => Some(v),
+            _ => unreachable!(),
+        };
+    }
+
+    let entry = &mut node.entries[position];
+    let old = match entry {
+        MapEntry::Pair(..) => unreachable!(),
+        MapEntry::Collision(h, pairs) => {
+            let pairs_mut = Rc::make_mut(pairs);
+            let index = pairs_mut.iter().position(|(k, _)| k.borrow() == key)?;
+            let (_, v) = pairs_mut.remove(index);
+            if pairs_mut.len() == 1 {
+                let (k, single) = pairs_mut.pop().unwrap();
+                *entry = MapEntry::Pair(*h, k, single);
+            }
+            Some(v)
+        }
+        MapEntry::Node(child) => remove_entry(child, shift + BITS, hash, key),
+    };
+
+    // a child left holding a single pair or bucket is pulled up into this node
+    if let MapEntry::Node(child) = entry {
+        if child.entries.len() == 1 && !matches!(child.entries[0], MapEntry::Node(_)) {
+            *entry = child.entries[0].clone();
+        }
+    }
+    old
+}
+
+impl<K: Trace + 'static, V: Trace + 'static> Trace for MapNode<K, V> {
+    fn trace(&self, tracer: &mut Tracer) {
+        for entry in &self.entries {
+            match entry {
+                MapEntry::Pair(_, key, value) => {
+                    key.trace(tracer);
+                    value.trace(tracer);
+                }
+                MapEntry::Node(node) => tracer.reference(node),
+                MapEntry::Collision(_, pairs) => tracer.reference(pairs),
+            }
+        }
+    }
+}
+
+impl<K: Hash + Eq + Clone + Trace + 'static, V: Clone + Trace + 'static> Trace for PMap<K, V> {
+    fn trace(&self, tracer: &mut Tracer) {
+        tracer.reference(&self.root);
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> Default for PMap<K, V> {
+    fn default() -> Self {
+        PMap::new()
+    }
+}
+
+pub struct PMapIter<'a, K, V> {
+    stack: Vec<std::slice::Iter<'a, MapEntry<K, V>>>,
+    collision: std::slice::Iter<'a, (K, V)>,
+    left: usize,
+}
+
+impl<'a, K, V> Iterator for PMapIter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<(&'a K, &'a V)> {
+        loop {
+            if let Some((k, v)) = self.collision.next() {
+                self.left -= 1;
+                return Some((k, v));
+            }
+            match self.stack.last_mut()?.next() {
+                Some(MapEntry::Pair(_, k, v)) => {
+                    self.left -= 1;
+                    return Some((k, v));
+                }
+                Some(MapEntry::Collision(_, pairs)) => self.collision = pairs.iter(),
+                Some(MapEntry::Node(child)) => self.stack.push(child.entries.iter()),
+                None => {
+                    self.stack.pop();
+                }
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.left, Some(self.left))
+    }
+}
+
+impl<K, V> ExactSizeIterator for PMapIter<'_, K, V> {}
+
+impl<'a, K: Hash + Eq + Clone, V: Clone> IntoIterator for &'a PMap<K, V> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = PMapIter<'a, K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> FromIterator<(K, V)> for PMap<K, V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut map = PMap::new();
+        map.extend(iter);
+        map
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> Extend<(K, V)> for PMap<K, V> {
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        for (k, v) in iter {
+            self.insert(k, v);
+        }
+    }
+}
+
+impl<K: Hash + Eq + Clone + Borrow<Q>, V: Clone, Q: Hash + Eq + ?Sized> Index<&Q> for PMap<K, V> {
+    type Output = V;
+
+    fn index(&self, key: &Q) -> &V {
+        self.get(key).expect("key not in map")
+    }
+}
+
+impl<K: Hash + Eq + Clone + fmt::Debug, V: Clone + fmt::Debug> fmt::Debug for PMap<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone + PartialEq> PartialEq for PMap<K, V> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len == other.len && self.iter().all(|(k, v)| other.get(k) == Some(v))
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone + Eq> Eq for PMap<K, V> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    // a small deterministic generator so the random operations are reproducible
+    fn xorshift(state: &mut u64) -> u64 {
+        *state ^= *state << 13;
+        *state ^= *state >> 7;
+        *state ^= *state << 17;
+        *state
+    }
+
+    #[test]
+    fn test_vec_push_pop() {
+        let mut vec = PVec::new();
+        let mut model = Vec::new();
+        for i in 0..40_000 {
+            vec.push(i);
+            model.push(i);
+        }
+        assert_eq!(vec.len(), model.len());
+        assert!(vec.iter().eq(model.iter()));
+        assert_eq!(vec.get(33_000), Some(&33_000));
+        assert_eq!(vec.get(40_000), None);
+
+        while let Some(item) = model.pop() {
+            assert_eq!(vec.pop(), Some(item));
+            assert_eq!(vec.len(), model.len());
+            if model.len().is_multiple_of(997) {
+                assert!(vec.iter().eq(model.iter()));
+            }
+        }
+        assert_eq!(vec.pop(), None);
+        assert!(vec.is_empty());
+    }
+
+    #[test]
+    fn test_vec_random() {
+        let mut state = 0x9e3779b97f4a7c15;
+        let mut vec = PVec::new();
+        let mut model = Vec::new();
+        let mut versions = Vec::new();
+        for step in 0..50_000usize {
+            match xorshift(&mut state) % 5 {
+                0 if !model.is_empty() => {
+                    assert_eq!(vec.pop(), model.pop());
+                }
+                1 if !model.is_empty() => {
+                    let i = xorshift(&mut state) as usize % model.len();
+                    assert!(vec.set(i, step));
+                    model[i] = step;
+                }
+                _ => {
+                    vec.push(step);
+                    model.push(step);
+                }
+            }
+            if step.is_multiple_of(5_000) {
+                versions.push((vec.clone(), model.clone()));
+            }
+        }
+        assert!(vec.iter().eq(model.iter()));
+        // older versions are untouched by later updates
+        for (vec, model) in versions {
+            assert_eq!(vec.len(), model.len());
+            assert!(vec.iter().eq(model.iter()));
+        }
+    }
+
+    #[test]
+    fn test_vec_sharing() {
+        let one: PVec<usize> = (0..2_000).collect();
+        let mut two = one.clone();
+        two.set(5, 99);
+        assert_eq!(one[5], 5);
+        assert_eq!(two[5], 99);
+
+        // only the path to the changed leaf was copied
+        let (VecNode::Branch(a), VecNode::Branch(b)) = (one.root.as_ref(), two.root.as_ref())
+        else {
+            panic!("roots are branches")
+        };
+        assert!(!Rc::ptr_eq(&a[0], &b[0]));
+        assert!(a[1..]
+            .iter()
+            .zip(b[1..].iter())
+            .all(|(x, y)| Rc::ptr_eq(x, y)));
+        assert!(Rc::ptr_eq(&one.tail, &two.tail));
+    }
+
+    // hashes every key to the same few values so collisions are exercised
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    struct Colliding(u32);
+
+    impl Hash for Colliding {
+        fn hash<H: Hasher>(&self, state: &mut H) {
+            (self.0 % 7).hash(state);
+        }
+    }
+
+    #[test]
+    fn test_map_random() {
+        let mut state = 0x2545f4914f6cdd1d;
+        let mut map = PMap::new();
+        let mut model = HashMap::new();
+        let mut versions = Vec::new();
+        for step in 0..50_000u64 {
+            let key = xorshift(&mut state) % 3_000;
+            if xorshift(&mut state).is_multiple_of(3) {
+                assert_eq!(map.remove(&key), model.remove(&key));
+            } else {
+                assert_eq!(map.insert(key, step), model.insert(key, step));
+            }
+            assert_eq!(map.len(), model.len());
+            if step.is_multiple_of(5_000) {
+                versions.push((map.clone(), model.clone()));
+            }
+        }
+        for (map, model) in versions {
+            assert_eq!(map.iter().count(), model.len());
+            assert!(model.iter().all(|(k, v)| map.get(k) == Some(v)));
+        }
+    }
+
+    #[test]
+    fn test_map_collisions() {
+        let mut map = PMap::new();
+        for i in 0..100 {
+            map.insert(Colliding(i), i);
+        }
+        assert_eq!(map.len(), 100);
+        assert!((0..100).all(|i| map.get(&Colliding(i)) == Some(&i)));
+        let before = map.clone();
+        for i in (0..100).step_by(2) {
+            assert_eq!(map.remove(&Colliding(i)), Some(i));
+        }
+        assert_eq!(map.remove(&Colliding(0)), None);
+        assert_eq!(map.len(), 50);
+        assert!((0..100).all(|i| map.contains_key(&Colliding(i)) == (i % 2 == 1)));
+        assert_eq!(before.len(), 100);
+        assert_eq!(before.iter().count(), 100);
+    }
+
+    #[test]
+    fn test_map_eq() {
+        let one: PMap<String, i32> = [("a".to_string(), 1), ("b".to_string(), 2)]
+            .into_iter()
+            .collect();
+        let mut two = PMap::new();
+        two.insert("b".to_string(), 2);
+        two.insert("a".to_string(), 1);
+        assert_eq!(one, two);
+        assert_eq!(one["a"], 1);
+        two.insert("a".to_string(), 3);
+        assert_ne!(one, two);
+        assert_eq!(one["a"], 1);
+    }
+}
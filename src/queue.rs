@@ -4,10 +4,11 @@ pub struct Queue<'a, T: PartialEq> {
 }
 
 impl<'a, T: PartialEq> Queue<'a, T> {
-    pub fn new(data: &[T]) -> Queue<T> {
+    pub fn new(data: &[T]) -> Queue<'_, T> {
         Queue { data, cursor: 0 }
     }
 
+    #[allow(clippy::len_without_is_empty)]
     pub fn len(&self) -> usize {
         self.data.len()
     }
@@ -28,7 +29,11 @@ impl<'a, T: PartialEq> Queue<'a, T> {
         self.data.get(self.cursor)
     }
 
-    pub fn pop(&mut self) -> Option<&T> {
+    pub fn peak_nth(&self, n: usize) -> Option<&T> {
+        self.data.get(self.cursor + n)
+    }
+
+    pub fn pop(&mut self) -> Option<&'a T> {
         match self.data.get(self.cursor) {
             Some(t) => {
                 self.cursor += 1;
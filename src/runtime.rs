@@ -1,70 +1,1898 @@
-use std::vec;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+use crate::builtin;
+use crate::capability::Capabilities;
+use crate::compile::{compile, compile_located, Proto};
+use crate::datum::{FromDatum, ToDatum};
+use crate::error::{Error, Kind};
+use crate::gc::{GcStats, Heap};
+use crate::map::Map;
+use crate::parse::{Located, Location, NOWHERE};
+use crate::persistent::{PMap, PVec};
+use crate::value::{Handle, HostObject, Value};
+use crate::vm::Cell;
 use crate::{code::Code, lamp_type::LampType, utils::ts};
-use crate::datum::Datum;
 
+// the variables of one scope, names not found are looked up in the parent
+#[derive(Default)]
+pub(crate) struct Scope {
+    pub(crate) variables: Vec<(String, Value)>,
+    pub(crate) parent: Option<Env>,
+}
+
+// closures share the scope they were made in
+pub(crate) type Env = Rc<RefCell<Scope>>;
+
+pub(crate) fn lookup(env: &Env, name: &str) -> Option<Value> {
+    let mut env = env.clone();
+    loop {
+        let parent = {
+            let scope = env.borrow();
+            if let Some((_, value)) = scope.variables.iter().rev().find(|(n, _)| n == name) {
+                return Some(value.clone());
+            }
+            scope.parent.clone()?
+        };
+        env = parent;
+    }
+}
+
+// defining a name already in the scope replaces it
+pub(crate) fn define(env: &Env, name: &str, value: Value) {
+    let mut scope = env.borrow_mut();
+    match scope.variables.iter_mut().find(|(n, _)| n == name) {
+        Some((_, old)) => *old = value,
+        None => scope.variables.push((ts(name), value)),
+    }
+}
+
+// sets the variable in the nearest scope that has it
+pub(crate) fn assign(env: &Env, name: &str, value: Value) -> bool {
+    let mut env = env.clone();
+    loop {
+        let parent = {
+            let mut scope = env.borrow_mut();
+            if let Some((_, old)) = scope.variables.iter_mut().rev().find(|(n, _)| n == name) {
+                *old = value;
+                return true;
+            }
+            match scope.parent.clone() {
+                Some(parent) => parent,
+                None => return false,
+            }
+        };
+        env = parent;
+    }
+}
+
+// the value of nothing, what a missing else or a finished while gives back
+pub(crate) fn nothing() -> Value {
+    Value::List(PVec::new())
+}
+
+fn field<'a>(map: &'a Map<Code, Code>, name: &str) -> Option<&'a Code> {
+    map.get(&Code::Identifier(ts(name)))
+}
+
+pub(crate) fn truthy(form: &str, value: Value) -> Result<bool, Error> {
+    match value {
+        Value::Symbol(b) if &*b == "true" => Ok(true),
+        Value::Symbol(b) if &*b == "false" => Ok(false),
+        _ => Err(Error::new(
+            Kind::TypeError,
+            format!("{} condition must be true or false but got {}", form, value),
+        )),
+    }
+}
+
+// bare names are the types without arguments, [Vec [u8]] and the like are written out
+pub(crate) fn parse_type(code: &Code) -> Result<LampType, String> {
+    let typ = match code {
+        Code::Identifier(_) => LampType::from_code(&Code::List(vec![code.clone()])),
+        _ => LampType::from_code(code),
+    };
+    typ.map_err(|_| format!("Unknown type {}", code))
+}
+
+// how much a run may use, None is no limit
+// a run is one eval or call from rust, each starts with a full budget
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Limits {
+    // pieces of code evaluated
+    pub steps: Option<u64>,
+    // functions called inside each other
+    pub depth: Option<usize>,
+    // bytes of native stack the calls of a run may use before they fail with a depth error
+    // so deep recursion stops before the stack overflows, keep it below the stack of the
+    // thread the runtime runs on
+    pub stack: Option<usize>,
+    // items in one list or map, or bytes in one string
+    pub allocation: Option<usize>,
+    // wall-clock time from the start of the run
+    pub deadline: Option<Duration>,
+}
+
+// the stack budget fits the main thread and threads spawned by std
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            steps: None,
+            depth: None,
+            stack: Some(1 << 20),
+            allocation: None,
+            deadline: None,
+        }
+    }
+}
+
+// where the native stack is now, it grows down on the platforms rust runs on
+fn stack_position() -> usize {
+    let marker = 0u8;
+    std::hint::black_box(&marker) as *const u8 as usize
+}
+
+// stops the run a runtime is in from another thread or a signal handler
+// the run ends with an Interrupted error at the next loop or call
+// and the token is cleared for the next run
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    // forgets a cancel that came while nothing was running
+    pub fn reset(&self) {
+        self.0.store(false, Ordering::Relaxed);
+    }
+
+    pub(crate) fn check(&self) -> Result<(), Error> {
+        // loading first keeps the common case from writing to the flag
+        match self.0.load(Ordering::Relaxed) && self.0.swap(false, Ordering::Relaxed) {
+            true => Err(Error::interrupted()),
+            false => Ok(()),
+        }
+    }
+}
 
 pub struct Runtime {
-    variables: Vec<(String, Datum)>,
+    pub(crate) globals: Env,
+    // registering a name again replaces it
+    functions: HashMap<String, Rc<Function>>,
+    // the names the runtime defines itself, register_fn won't replace them
+    builtins: HashSet<String>,
+    // host type names, method names and the methods
+    methods: Vec<(&'static str, String, Rc<Function>)>,
+    // operator symbols and the names of the functions they call
+    operators: HashMap<String, String>,
+    limits: Limits,
+    // what the current run has used
+    steps: u64,
+    depth: usize,
+    // where the native stack was when the run started
+    stack_base: usize,
+    deadline: Option<Instant>,
+    pub(crate) cancel: CancelToken,
+    capabilities: Capabilities,
+    // the cells and scopes of the running program
+    pub(crate) heap: Heap,
+    // the lamp functions running, innermost last
+    frames: Vec<Frame>,
+    // where the call the vm is making was written, taken by the frame it makes
+    pub(crate) call_site: Option<Location>,
+}
+
+// a call to a lamp function and where it was made, if the code came with a SourceMap
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub name: String,
+    pub at: Option<Location>,
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.at {
+            Some(at) => write!(f, "{} at {}", self.name, at),
+            None => write!(f, "{}", self.name),
+        }
+    }
+}
+
+impl Frame {
+    // {name: "f" line: 2 column: 5}, what call_stack gives scripts
+    fn to_value(&self) -> Value {
+        let mut map = PMap::new();
+        map.insert(Value::symbol("name"), Value::string(&self.name));
+        if let Some(at) = self.at {
+            map.insert(Value::symbol("line"), Value::Integer(at.line as i128));
+            map.insert(Value::symbol("column"), Value::Integer(at.column as i128));
+        }
+        Value::Map(map)
+    }
 }
 
 impl Runtime {
+    // a runtime whose scripts can't touch anything outside it
     pub fn new() -> Runtime {
+        Runtime::with_capabilities(Capabilities::pure())
+    }
+
+    // the built-ins for files, the environment, the clock and programs check the capabilities
+    pub fn with_capabilities(capabilities: Capabilities) -> Runtime {
         let mut runtime = Runtime {
-            variables: Vec::new(),
+            globals: Env::default(),
+            functions: HashMap::new(),
+            builtins: HashSet::new(),
+            methods: Vec::new(),
+            operators: HashMap::new(),
+            limits: Limits::default(),
+            steps: 0,
+            depth: 0,
+            stack_base: 0,
+            deadline: None,
+            cancel: CancelToken::default(),
+            capabilities,
+            heap: Heap::default(),
+            frames: Vec::new(),
+            call_site: None,
         };
 
-        runtime.add_variable("pi", LampType::F64, Code::from_float(std::f64::consts::PI));
+        runtime.add_variable("pi", Value::from_float(std::f64::consts::PI));
+        for (name, args, rest) in builtin::BUILTINS {
+            runtime.add_function(name, Function::builtin(name, args, *rest));
+        }
+        for (symbol, name) in builtin::OPERATORS {
+            runtime.set_operator(symbol, name);
+        }
+        // needs the runtime so it isn't one of the plain built-ins
+        runtime.add_function(
+            "gc_stats",
+            Function {
+                args: Vec::new(),
+                rest: None,
+                runable: Runable::Native(Rc::new(|runtime, _| Ok(runtime.gc_stats().to_value()))),
+                returns: Some(GcStats::to_lamp_type()),
+            },
+        );
+        runtime.add_function(
+            "call_stack",
+            Function {
+                args: Vec::new(),
+                rest: None,
+                runable: Runable::Native(Rc::new(|runtime, _| {
+                    let frames = runtime.frames.iter().map(Frame::to_value);
+                    Ok(Value::List(frames.collect()))
+                })),
+                returns: None,
+            },
+        );
+        runtime.add_function(
+            "raise",
+            Function {
+                args: vec![Arg {
+                    name: ts("value"),
+                    typ: LampType::Code,
+                    default: None,
+                }],
+                rest: None,
+                runable: Runable::Native(Rc::new(|_, mut args| {
+                    Err(Error::raised(args.pop().unwrap_or_else(nothing)))
+                })),
+                returns: None,
+            },
+        );
         runtime
     }
 
-    fn add_variable(&mut self, name: &str, typ: LampType, data: Code) {
-        self.variables.push((ts(name), Datum { typ, data }));
+    fn add_variable(&mut self, name: &str, value: Value) {
+        define(&self.globals, name, value);
+    }
+
+    fn add_function(&mut self, name: &str, func: Function) {
+        self.builtins.insert(ts(name));
+        self.functions.insert(ts(name), Rc::new(func));
+    }
+
+    // makes a rust closure callable from lamp by name
+    // the argument tuple and the result are converted with FromDatum and ToDatum
+    // and their lamp types become the signature, a tuple of n types takes n arguments
+    // registering a name again replaces the earlier function, built-ins can't be replaced
+    pub fn register_fn<A, R, E, F>(&mut self, name: &str, func: F) -> Result<(), String>
+    where
+        A: FromDatum,
+        R: ToDatum,
+        E: fmt::Display,
+        F: Fn(A) -> Result<R, E> + 'static,
+    {
+        if self.builtins.contains(name) {
+            return Err(format!("{} is a built-in and can't be replaced", name));
+        }
+        let function = native_function(name, None, move |_, args| {
+            func(args).map_err(|err| err.to_string())
+        });
+        self.functions.insert(ts(name), Rc::new(function));
+        Ok(())
+    }
+
+    // a function called on handles to T, [name handle args..]
+    // methods are looked up by the type of the first argument before registered functions
+    // so every host type can have its own read or close
+    pub fn register_method<T, A, R, E, F>(&mut self, name: &str, func: F)
+    where
+        T: HostObject,
+        A: FromDatum,
+        R: ToDatum,
+        E: fmt::Display,
+        F: Fn(&T, A) -> Result<R, E> + 'static,
+    {
+        let receiver = Arg {
+            name: ts("self"),
+            typ: T::lamp_type(),
+            default: None,
+        };
+        let function = native_function(name, Some(receiver), move |receiver, args| {
+            let receiver = receiver.ok_or("methods need a handle")?;
+            let handle = Handle::<T>::from_value(&receiver).map_err(|err| err.to_string())?;
+            func(&handle, args).map_err(|err| err.to_string())
+        });
+        self.methods
+            .push((T::TYPE_NAME, ts(name), Rc::new(function)));
+    }
+
+    // the signature of a registered function as a fn form without a body
+    pub fn signature(&self, name: &str) -> Option<Code> {
+        let (_, func) = self.function(name)?;
+        let ident = |name: &str| Code::Identifier(ts(name));
+        let arg = |arg: &Arg| Code::List(vec![ident(&arg.name), arg.typ.to_code()]);
+        let mut form = map![
+            {ident("head_position_field"), ident("fn")},
+            {ident("a"), Code::List(func.args.iter().map(arg).collect())},
+        ];
+        if let Some(rest) = &func.rest {
+            form.insert(ident("rest"), arg(rest));
+        }
+        if let Some(typ) = &func.returns {
+            form.insert(ident("r"), typ.to_code());
+        }
+        Some(Code::Map(form))
+    }
+
+    // makes the symbol call the named function, replacing any previous binding
+    pub fn set_operator(&mut self, symbol: &str, function: &str) {
+        self.operators.insert(ts(symbol), ts(function));
+    }
+
+    pub fn remove_operator(&mut self, symbol: &str) -> Option<String> {
+        self.operators.remove(symbol)
+    }
+
+    pub fn operator(&self, symbol: &str) -> Option<&str> {
+        self.operators.get(symbol).map(|s| s.as_str())
+    }
+
+    // the method of the type of handle the value holds
+    fn method(&self, name: &str, value: Option<&Value>) -> Option<Rc<Function>> {
+        let Some(Value::Host(host)) = value else {
+            return None;
+        };
+        self.methods
+            .iter()
+            .rev()
+            .find(|(typ, n, _)| *typ == host.type_name() && n == name)
+            .map(|(_, _, f)| f.clone())
+    }
+
+    // the registered function and the name it was registered under
+    pub(crate) fn function(&self, name: &str) -> Option<(&str, Rc<Function>)> {
+        let name = self.operator(name).unwrap_or(name);
+        self.functions
+            .get_key_value(name)
+            .map(|(n, f)| (n.as_str(), f.clone()))
+    }
+
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
     }
 
-    fn add_function(&mut self, func: Function) {
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    pub fn capabilities(&self) -> &Capabilities {
+        &self.capabilities
+    }
+
+    // frees the cells and scopes only kept alive by cycles, giving back how many there were
+    pub fn collect_garbage(&mut self) -> usize {
+        self.heap.collect()
+    }
+
+    pub fn gc_stats(&self) -> GcStats {
+        self.heap.stats()
+    }
+
+    // collects before every cell or scope is made, slow but finds anything freed too early
+    pub fn set_gc_stress(&mut self, stress: bool) {
+        self.heap.stress = stress;
+    }
+
+    // a token that stops whatever this runtime is running
+    pub fn cancel_token(&self) -> CancelToken {
+        self.cancel.clone()
+    }
+
+    // native functions calling back into lamp share the budget of the run they are in
+    pub(crate) fn run<T>(
+        &mut self,
+        run: impl FnOnce(&mut Runtime) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        if self.depth > 0 {
+            return run(self);
+        }
+        self.steps = 0;
+        self.stack_base = stack_position();
+        self.deadline = self.limits.deadline.map(|d| Instant::now() + d);
+        self.frames.clear();
+        self.call_site = None;
+        run(self)
+    }
+
+    // counts a step, the clock is only read every so often
+    pub(crate) fn step(&mut self) -> Result<(), Error> {
+        self.steps += 1;
+        if let Some(max) = self.limits.steps {
+            if self.steps > max {
+                return Err(Error::limit(format!("Step limit of {} reached", max)));
+            }
+        }
+        if let Some(deadline) = self.deadline {
+            if self.steps.is_multiple_of(256) && Instant::now() >= deadline {
+                return Err(Error::limit(format!(
+                    "Deadline of {:?} reached",
+                    self.limits.deadline.unwrap_or_default()
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    // checked as lists, maps and strings are made
+    pub(crate) fn allocated(&self, value: Value) -> Result<Value, Error> {
+        let Some(max) = self.limits.allocation else {
+            return Ok(value);
+        };
+        let len = match &value {
+            Value::List(list) => list.len(),
+            Value::Map(map) => map.len(),
+            Value::String(s) => s.len(),
+            _ => 0,
+        };
+        if len > max {
+            return Err(Error::limit(format!(
+                "Allocation limit of {} reached by a {} of {}",
+                max,
+                value.kind(),
+                len
+            )));
+        }
+        Ok(value)
+    }
+
+    // compiles the code and runs it on the vm, so calls in tail position don't use up the stack
+    pub fn eval(&mut self, code: &Code) -> Result<Value, Error> {
+        self.exec(&compile(code)?)
+    }
+
+    // eval of code with the locations a SourceMap found for it
+    // errors say where the calls they came out of were made
+    pub fn eval_located(&mut self, code: &Code, at: &Located) -> Result<Value, Error> {
+        self.exec(&compile_located(code, at)?)
+    }
+
+    // walks the code without compiling it, the reference the vm is tested against
+    pub fn interpret(&mut self, code: &Code) -> Result<Value, Error> {
+        self.interpret_located(code, &NOWHERE)
+    }
+
+    pub fn interpret_located(&mut self, code: &Code, at: &Located) -> Result<Value, Error> {
+        let env = self.globals.clone();
+        self.run(|runtime| runtime.eval_in(code, at, &env))
+    }
+
+    // at is where the code was written, NOWHERE for code without a source
+    fn eval_in(&mut self, code: &Code, at: &Located, env: &Env) -> Result<Value, Error> {
+        self.step()?;
+        match code {
+            // unbound names that aren't functions evaluate to themselves
+            Code::Identifier(name) => {
+                Ok(
+                    lookup(env, name).unwrap_or_else(|| match self.function(name) {
+                        Some((name, _)) => Value::BuiltIn(name.into()),
+                        None => Value::symbol(name),
+                    }),
+                )
+            }
+            Code::List(list) => match list.first() {
+                None => Ok(nothing()),
+                Some(Code::Identifier(name)) => match name.as_str() {
+                    "var" | "set" => self.eval_binding(name, &list[1..], at, env),
+                    "pgm" => {
+                        let scope = self.heap.scope(env);
+                        let mut last = nothing();
+                        for (i, item) in list.iter().enumerate().skip(1) {
+                            last = self.eval_in(item, at.item(i), &scope)?;
+                        }
+                        Ok(last)
+                    }
+                    _ => {
+                        let args = list
+                            .iter()
+                            .enumerate()
+                            .skip(1)
+                            .map(|(i, arg)| self.eval_in(arg, at.item(i), env))
+                            .collect::<Result<Vec<_>, _>>()?;
+                        self.call_site = at.at;
+                        self.call_in(name, args, env)
+                    }
+                },
+                // lists that don't start with a function name are data
+                Some(_) => {
+                    let list = list
+                        .iter()
+                        .enumerate()
+                        .map(|(i, item)| self.eval_in(item, at.item(i), env))
+                        .collect::<Result<_, _>>()?;
+                    self.allocated(Value::List(list))
+                }
+            },
+            Code::Map(map) => match field(map, "head_position_field") {
+                Some(Code::Identifier(form)) if form == "if" => {
+                    let cond = field(map, "c").ok_or("if needs a condition c:")?;
+                    let branch = match truthy("if", self.eval_in(cond, at.field(map, "c"), env)?)? {
+                        true => "do",
+                        false => "else",
+                    };
+                    match field(map, branch) {
+                        Some(body) => self.eval_body(body, at.field(map, branch), env),
+                        None => Ok(nothing()),
+                    }
+                }
+                Some(Code::Identifier(form)) if form == "while" => {
+                    let cond = field(map, "c").ok_or("while needs a condition c:")?;
+                    while truthy("while", self.eval_in(cond, at.field(map, "c"), env)?)? {
+                        if let Some(body) = field(map, "do") {
+                            self.eval_body(body, at.field(map, "do"), env)?;
+                        }
+                        self.cancel.check()?;
+                    }
+                    Ok(nothing())
+                }
+                Some(Code::Identifier(form)) if form == "fn" => self.eval_fn(code, map, at, env),
+                // the closures are made of the code in the try like the compiler does,
+                // so calls in them keep where they were written
+                Some(Code::Identifier(form)) if form == "try" => {
+                    let (body, handler, finally) = try_parts(map)?;
+                    let none = Code::List(Vec::new());
+                    let part = |name| (field(map, name).unwrap_or(&none), at.field(map, name));
+                    let nothing = (&none, &NOWHERE);
+                    let body = self.closure(&body, nothing, None, part("do"), env)?;
+                    let handler = handler
+                        .map(|h| self.closure(&h, part("catch"), None, part("handle"), env))
+                        .transpose()?;
+                    let finally = finally
+                        .map(|f| self.closure(&f, nothing, None, part("finally"), env))
+                        .transpose()?;
+                    self.try_call(&body, handler.as_ref(), finally.as_ref())
+                }
+                // other maps are data with their values evaluated
+                _ => {
+                    let map = map
+                        .iter()
+                        .enumerate()
+                        .map(|(i, (k, v))| {
+                            Ok((Value::from_code(k), self.eval_in(v, at.item(i), env)?))
+                        })
+                        .collect::<Result<_, Error>>()?;
+                    self.allocated(Value::Map(map))
+                }
+            },
+            _ => Ok(Value::from_code(code)),
+        }
+    }
+
+    // a body that is a list of statements runs each in turn giving back the last
+    fn eval_body(&mut self, code: &Code, at: &Located, env: &Env) -> Result<Value, Error> {
+        match code {
+            Code::List(items) if !matches!(items.first(), None | Some(Code::Identifier(_))) => {
+                let mut last = nothing();
+                for (i, item) in items.iter().enumerate() {
+                    last = self.eval_in(item, at.item(i), env)?;
+                }
+                Ok(last)
+            }
+            _ => self.eval_in(code, at, env),
+        }
+    }
+
+    // [var name value] defines in the current scope, [set name value] changes an existing variable
+    fn eval_binding(
+        &mut self,
+        form: &str,
+        args: &[Code],
+        at: &Located,
+        env: &Env,
+    ) -> Result<Value, Error> {
+        let (name, value) = match args {
+            [Code::Identifier(name), value] => (name, value),
+            _ => return Err(format!("{} expects a name and a value", form).into()),
+        };
+        let value = self.eval_in(value, at.item(2), env)?;
+        if form == "var" {
+            define(env, name, value.clone());
+        } else if !assign(env, name, value.clone()) {
+            return Err(Error::new(
+                Kind::NameError,
+                format!("Cannot set {} because it is not defined", name),
+            ));
+        }
+        Ok(value)
+    }
+
+    // {fn a: [[name type default]] r: type c: body}
+    fn eval_fn(
+        &mut self,
+        code: &Code,
+        map: &Map<Code, Code>,
+        at: &Located,
+        env: &Env,
+    ) -> Result<Value, Error> {
+        let none = Code::List(Vec::new());
+        let body = field(map, "c").ok_or("fn needs a body c:")?;
+        let specs = field(map, "a").unwrap_or(&none);
+        self.closure(
+            code,
+            (specs, at.field(map, "a")),
+            field(map, "r"),
+            (body, at.field(map, "c")),
+            env,
+        )
+    }
+
+    // the specs and the body come with where they were written, the body keeps a copy of that
+    // defaults are evaluated when the function is made
+    fn closure(
+        &mut self,
+        code: &Code,
+        (specs, specs_at): (&Code, &Located),
+        returns: Option<&Code>,
+        (body, body_at): (&Code, &Located),
+        env: &Env,
+    ) -> Result<Value, Error> {
+        let specs = match specs {
+            Code::List(specs) => specs,
+            other => return Err(format!("fn arguments must be a list but got {}", other).into()),
+        };
+        let mut args = Vec::new();
+        for (i, spec) in specs.iter().enumerate() {
+            args.push(self.eval_arg(spec, specs_at.item(i), env)?);
+        }
+        let returns = returns.map(parse_type).transpose()?;
+        Ok(Value::Closure(Rc::new(Closure {
+            function: Function {
+                args,
+                rest: None,
+                runable: Runable::Code(body.clone(), body_at.clone()),
+                returns,
+            },
+            env: env.clone(),
+            code: code.clone(),
+            captures: Vec::new(),
+        })))
+    }
+
+    fn eval_arg(&mut self, spec: &Code, at: &Located, env: &Env) -> Result<Arg, Error> {
+        let (name, typ, default) = match spec {
+            Code::Identifier(name) => (name, None, None),
+            Code::List(items) => match items.as_slice() {
+                [Code::Identifier(name)] => (name, None, None),
+                [Code::Identifier(name), typ] => (name, Some(typ), None),
+                [Code::Identifier(name), typ, default] => (name, Some(typ), Some(default)),
+                _ => return Err(format!("Invalid fn argument {}", spec).into()),
+            },
+            _ => return Err(format!("Invalid fn argument {}", spec).into()),
+        };
+        Ok(Arg {
+            name: name.clone(),
+            typ: typ.map(parse_type).transpose()?.unwrap_or(LampType::Code),
+            default: default
+                .map(|code| self.eval_in(code, at.item(2), env))
+                .transpose()?,
+        })
+    }
+
+    pub fn call(&mut self, name: &str, args: Vec<Value>) -> Result<Value, Error> {
+        let env = self.globals.clone();
+        self.run(|runtime| runtime.call_in(name, args, &env))
+    }
+
+    // variables holding functions come before registered functions
+    pub(crate) fn call_in(
+        &mut self,
+        name: &str,
+        args: Vec<Value>,
+        env: &Env,
+    ) -> Result<Value, Error> {
+        if let Some(func) = lookup(env, name) {
+            return self.call_value(name, &func, args);
+        }
+        if let Some(method) = self.method(name, args.first()) {
+            return self.apply_registered(name, method, args);
+        }
+        // a call on a handle is a method call even when no function has the name
+        let (_, func) = self.function(name).ok_or_else(|| match args.first() {
+            Some(Value::Host(host)) => no_method(host.type_name(), name),
+            _ => unknown(name),
+        })?;
+        self.apply_registered(name, func, args)
+    }
+
+    // calls a closure or built-in, name is what it was called as for errors
+    pub fn call_value(
+        &mut self,
+        name: &str,
+        func: &Value,
+        args: Vec<Value>,
+    ) -> Result<Value, Error> {
+        match func {
+            Value::Closure(closure) => {
+                let closure = closure.clone();
+                self.apply(name, &closure.function, args, |runtime, args| {
+                    runtime.run_closure(&closure, args)
+                })
+            }
+            Value::BuiltIn(builtin) => {
+                let (_, func) = self.function(builtin).ok_or_else(|| unknown(builtin))?;
+                self.apply_registered(name, func, args)
+            }
+            _ => Err(Error::new(
+                Kind::TypeError,
+                format!("{} is a {} not a function", name, func.kind()),
+            )),
+        }
+    }
+
+    fn apply_registered(
+        &mut self,
+        name: &str,
+        func: Rc<Function>,
+        args: Vec<Value>,
+    ) -> Result<Value, Error> {
+        self.apply(name, &func, args, |runtime, args| match &func.runable {
+            Runable::BuiltIn(builtin) => builtin::call(builtin, args, &runtime.capabilities),
+            Runable::Native(native) => native(runtime, args),
+            Runable::Code(..) | Runable::Compiled(_) => {
+                Err(format!("{} has no closure to run in", name).into())
+            }
+        })
+    }
+
+    // checks and completes the arguments before running the function
+    fn apply(
+        &mut self,
+        name: &str,
+        func: &Function,
+        args: Vec<Value>,
+        run: impl FnOnce(&mut Runtime, Vec<Value>) -> Result<Value, Error>,
+    ) -> Result<Value, Error> {
+        let at = self.call_site.take();
+        let args = arguments(name, func, args)?;
+        self.cancel.check()?;
+        if let Some(max) = self.limits.depth {
+            if self.depth >= max {
+                return Err(Error::limit(format!("Depth limit of {} reached", max)));
+            }
+        }
+        if let Some(max) = self.limits.stack {
+            if self.stack_base.saturating_sub(stack_position()) > max {
+                return Err(Error::limit(format!(
+                    "Depth limit reached, the stack is full {} calls deep",
+                    self.depth
+                )));
+            }
+        }
+        let framed = matches!(func.runable, Runable::Code(..) | Runable::Compiled(_));
+        if framed {
+            self.frames.push(Frame { name: ts(name), at });
+        }
+        self.depth += 1;
+        let mut result = run(self, args).and_then(|result| self.returned(name, func, result));
+        self.depth -= 1;
+        // the innermost call an error leaves gives it the frames it came out of
+        if let Err(err) = &mut result {
+            if err.backtrace.is_none() {
+                err.backtrace = Some(self.frames.iter().rev().cloned().collect());
+            }
+        }
+        if framed {
+            self.frames.pop();
+        }
+        result
+    }
+
+    // the vm runs a tail call in place of the frame that made it
+    pub(crate) fn replace_frame(&mut self, name: &str) {
+        let at = self.call_site.take();
+        if let Some(frame) = self.frames.last_mut() {
+            *frame = Frame { name: ts(name), at };
+        }
+    }
+
+    fn returned(&self, name: &str, func: &Function, result: Value) -> Result<Value, Error> {
+        let result = self.allocated(result)?;
+        // native functions return what their rust signature says
+        if let (Some(typ), false) = (&func.returns, matches!(func.runable, Runable::Native(_))) {
+            result
+                .check(typ, "result")
+                .map_err(|err| Error::new(Kind::TypeError, format!("{}: {}", name, err)))?;
+        }
+        Ok(result)
+    }
+
+    // runs the body of a try, giving an error it fails with to the handler
+    // the finally runs after either, limits and interrupts stop the script and aren't caught
+    pub(crate) fn try_call(
+        &mut self,
+        body: &Value,
+        handler: Option<&Value>,
+        finally: Option<&Value>,
+    ) -> Result<Value, Error> {
+        let body = self.call_value("try", body, Vec::new());
+        // a call that failed before making its frame leaves its location behind
+        self.call_site = None;
+        let result = match (body, handler) {
+            (Err(err), _) if err.is_fatal() => return Err(err),
+            (Err(err), Some(handler)) => self.call_value("catch", handler, vec![err.to_value()]),
+            (result, _) => result,
+        };
+        if let Some(finally) = finally {
+            self.call_value("finally", finally, Vec::new())?;
+        }
+        result
+    }
+
+    fn run_closure(&mut self, closure: &Closure, mut args: Vec<Value>) -> Result<Value, Error> {
+        let (body, at) = match &closure.function.runable {
+            Runable::Code(body, at) => (body, at),
+            Runable::Compiled(proto) => return self.execute(proto, &closure.captures, args),
+            _ => return Err("Closures run lamp code".to_string().into()),
+        };
+        let scope = self.heap.scope(&closure.env);
+        let rest = args.split_off(closure.function.args.len());
+        for (arg, value) in closure.function.args.iter().zip(args) {
+            define(&scope, &arg.name, value);
+        }
+        if let Some(arg) = &closure.function.rest {
+            define(&scope, &arg.name, Value::List(rest.into_iter().collect()));
+        }
+        self.eval_body(body, at, &scope)
+    }
+}
+
+// {try do: body catch: [e] handle: body finally: body} as fn forms of the body, the handler
+// and the finally, both engines make closures of them for try_call
+pub(crate) fn try_parts(
+    map: &Map<Code, Code>,
+) -> Result<(Code, Option<Code>, Option<Code>), String> {
+    let ident = |name: &str| Code::Identifier(ts(name));
+    let form = |args: &Code, body: &Code| {
+        Code::Map(map![
+            {ident("head_position_field"), ident("fn")},
+            {ident("a"), args.clone()},
+            {ident("c"), body.clone()},
+        ])
+    };
+    let none = Code::List(Vec::new());
+    let body = field(map, "do").ok_or("try needs a body do:")?;
+    let handler = match (field(map, "catch"), field(map, "handle")) {
+        (None, None) => None,
+        (Some(args), handle) => Some(form(args, handle.unwrap_or(&none))),
+        (None, Some(_)) => return Err("try needs catch: [name] to handle errors".to_string()),
+    };
+    let finally = field(map, "finally").map(|body| form(&none, body));
+    Ok((form(&none, body), handler, finally))
+}
+
+fn unknown(name: &str) -> Error {
+    Error::new(Kind::NameError, format!("Unknown function \"{}\"", name))
+}
+
+fn no_method(typ: &str, name: &str) -> Error {
+    Error::new(Kind::NameError, format!("{} has no method {}", typ, name))
+}
+
+// checks the arguments against the function and adds the defaults of those left out
+pub(crate) fn arguments(
+    name: &str,
+    func: &Function,
+    mut args: Vec<Value>,
+) -> Result<Vec<Value>, Error> {
+    let required = func.args.iter().take_while(|a| a.default.is_none()).count();
+    if args.len() < required || (func.rest.is_none() && args.len() > func.args.len()) {
+        return Err(Error::new(
+            Kind::ArityError,
+            format!(
+                "{} expects {}{} arguments but got {}",
+                name,
+                if func.rest.is_some() { "at least " } else { "" },
+                required,
+                args.len()
+            ),
+        ));
+    }
+    for arg in func.args[args.len().min(func.args.len())..].iter() {
+        args.push(arg.default.clone().unwrap());
+    }
+    for (arg, value) in func.args.iter().zip(args.iter()) {
+        value
+            .check(&arg.typ, &arg.name)
+            .map_err(|err| Error::new(Kind::TypeError, format!("{}: {}", name, err)))?;
+    }
+    Ok(args)
+}
+
+impl Default for Runtime {
+    fn default() -> Self {
+        Runtime::new()
     }
 }
 
 // rust representation of the function type
-struct Arg {
-    name: String,
-    typ: LampType,
-    default: Option<Code>,
+pub(crate) struct Arg {
+    pub(crate) name: String,
+    pub(crate) typ: LampType,
+    pub(crate) default: Option<Value>,
 }
 
+// a rust closure registered with register_fn, the arguments are already checked
+type NativeFn = Rc<dyn Fn(&mut Runtime, Vec<Value>) -> Result<Value, Error>>;
+
 // don't want people to add own runnables or use builtin runnables
 // don't want people to make their own Function types
-enum Runable {
+pub(crate) enum Runable {
     BuiltIn(String),
-    Code(Code),
+    Native(NativeFn),
+    // the body and where it was written
+    Code(Code, Located),
+    // compiled by the bytecode compiler, run by the vm
+    Compiled(Rc<Proto>),
+}
+
+pub(crate) struct Function {
+    pub(crate) args: Vec<Arg>,
+    // extra arguments are passed on after the named ones
+    pub(crate) rest: Option<Arg>,
+    pub(crate) runable: Runable,
+    pub(crate) returns: Option<LampType>,
+}
+
+// a lamp function along with the scope it was made in
+pub struct Closure {
+    pub(crate) function: Function,
+    pub(crate) env: Env,
+    // the fn form that made the closure
+    pub(crate) code: Code,
+    // the variables of the frames around a compiled closure it uses
+    pub(crate) captures: Vec<Cell>,
 }
 
-struct Function {
-    args: Vec<Arg>,
-    runable: Runable,
-    returns: Option<LampType>,
+impl Closure {
+    pub fn code(&self) -> &Code {
+        &self.code
+    }
+}
+
+impl fmt::Debug for Closure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.code.to_source())
+    }
 }
 
-// the calling stack frame will be an environment variable that can be used
-// to implement looping functions like for and the like
+// the calling stack frames are kept in Runtime::frames and call_stack gives them to scripts,
+// a frame could become an environment variable to implement looping functions like for
+
+// a function running a rust closure, methods take the handle they are called on first
+fn native_function<A, R>(
+    name: &str,
+    receiver: Option<Arg>,
+    func: impl Fn(Option<Value>, A) -> Result<R, String> + 'static,
+) -> Function
+where
+    A: FromDatum,
+    R: ToDatum,
+{
+    let (types, tuple) = match A::to_lamp_type() {
+        LampType::List(types) => (types, true),
+        typ => (vec![typ], false),
+    };
+    // the arguments are numbered by where they are in the call, a receiver is arg0's place
+    let method = receiver.is_some();
+    let args = receiver
+        .into_iter()
+        .chain(types.into_iter().enumerate().map(|(i, typ)| Arg {
+            name: format!("arg{}", i + method as usize),
+            typ,
+            default: None,
+        }))
+        .collect();
+    let registered = ts(name);
+    let native = move |_: &mut Runtime, mut args: Vec<Value>| {
+        let receiver = if method && !args.is_empty() {
+            Some(args.remove(0))
+        } else {
+            None
+        };
+        let value = if tuple {
+            Value::List(args.into_iter().collect())
+        } else {
+            args.into_iter().next().unwrap_or_else(nothing)
+        };
+        let args = A::from_value(&value)
+            .map_err(|err| Error::new(Kind::TypeError, format!("{}: {}", registered, err)))?;
+        match func(receiver, args) {
+            Ok(result) => Ok(result.to_value()),
+            Err(err) => Err(format!("{}: {}", registered, err).into()),
+        }
+    };
+    Function {
+        args,
+        rest: None,
+        runable: Runable::Native(Rc::new(native)),
+        returns: Some(R::to_lamp_type()),
+    }
+}
 
 impl Function {
-    fn plus() -> Function {
+    fn builtin(name: &str, args: &[&str], rest: Option<&str>) -> Function {
+        let arg = |name: &str| Arg {
+            name: ts(name),
+            typ: LampType::Code,
+            default: None,
+        };
         Function {
-            args: vec![Arg{
-                name: ts("lhs"),
-                typ: LampType::F64,
-                default: None,
+            args: args.iter().map(|name| arg(name)).collect(),
+            rest: rest.map(arg),
+            runable: Runable::BuiltIn(ts(name)),
+            returns: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::{self, Cause};
+    use crate::parse::SourceMap;
+
+    fn eval(code: &str) -> Result<Value, String> {
+        let mut runtime = Runtime::new();
+        let mut last = nothing();
+        for code in Code::from_str(code)? {
+            last = runtime.eval(&code).map_err(|e| e.to_string())?;
+        }
+        Ok(last)
+    }
+
+    #[test]
+    fn test_operators() {
+        assert_eq!(eval("[+ 1 2]"), Ok(Value::Integer(3)));
+        assert_eq!(eval("[- 1 2.5]"), Ok(Value::from_float(-1.5)));
+        assert_eq!(eval("[* [+ 1 2] [/ 9 2]]"), Ok(Value::Integer(12)));
+        assert_eq!(eval("[/ [+ 1 2] 3]"), Ok(Value::Integer(1)));
+        assert_eq!(eval("[<= 2 2]"), Ok(Value::boolean(true)));
+        assert_eq!(eval("[!= 'a' 'a']"), Ok(Value::boolean(false)));
+        assert_eq!(eval("[% 7 0]"), Err("Division by zero".to_string()));
+    }
+
+    #[test]
+    fn test_named_builtins() {
+        assert_eq!(eval("[plus 1 2]"), Ok(Value::Integer(3)));
+        assert_eq!(eval("[u8 300]"), Err("300 does not fit in u8".to_string()));
+        assert_eq!(
+            eval("\"total: {[+ 1 2]} {'c'}\""),
+            Ok(Value::string("total: 3 c"))
+        );
+        assert_eq!(
+            eval("[json_stringify [json_parse \"\\{\\\"a\\\": [1, 2.5, null]\\}\"]]"),
+            Ok(Value::string("{\"a\":[1,2.5,null]}"))
+        );
+        assert_eq!(
+            eval("[+ 1]"),
+            Err("+ expects 2 arguments but got 1".to_string())
+        );
+    }
+
+    #[test]
+    fn test_operator_table() {
+        let mut runtime = Runtime::new();
+        let code = Code::from_str("[<+> 'a' \"b\"]").unwrap();
+        assert_eq!(
+            runtime.eval(&code[0]).map_err(|e| e.to_string()),
+            Err("Unknown function \"<+>\"".to_string())
+        );
+
+        runtime.set_operator("<+>", "concat");
+        assert_eq!(runtime.operator("<+>"), Some("concat"));
+        assert_eq!(runtime.eval(&code[0]), Ok(Value::string("ab")));
+
+        assert_eq!(runtime.remove_operator("+"), Some("plus".to_string()));
+        let code = Code::from_str("[+ 1 2]").unwrap();
+        assert_eq!(
+            runtime.eval(&code[0]).map_err(|e| e.to_string()),
+            Err("Unknown function \"+\"".to_string())
+        );
+    }
+
+    #[test]
+    fn test_variables() {
+        assert_eq!(eval("[var x 2] [set x [* x 3]] x"), Ok(Value::Integer(6)));
+        assert_eq!(eval("[pgm [var y 1] y]"), Ok(Value::Integer(1)));
+        // pgm has its own scope
+        assert_eq!(eval("[pgm [var y 1]] y"), Ok(Value::symbol("y")));
+        assert_eq!(
+            eval("[set z 1]"),
+            Err("Cannot set z because it is not defined".to_string())
+        );
+        assert_eq!(
+            eval("[var l [1 [+ 1 1]]] l"),
+            Ok(Value::List(
+                [Value::Integer(1), Value::Integer(2)].into_iter().collect()
+            ))
+        );
+        assert_eq!(eval("plus"), Ok(Value::BuiltIn("plus".into())));
+        assert_eq!(eval("+"), Ok(Value::BuiltIn("plus".into())));
+    }
+
+    #[test]
+    fn test_control_flow() {
+        assert_eq!(
+            eval("{if c: [< 1 2] do: \"yes\" else: \"no\"}"),
+            Ok(Value::string("yes"))
+        );
+        assert_eq!(eval("{if c: false do: 1}"), Ok(nothing()));
+        assert_eq!(
+            eval("{if c: 1 do: 2}"),
+            Err("if condition must be true or false but got 1".to_string())
+        );
+        assert_eq!(
+            eval("[var i 0] [var total 0] {while c: [< i 5] do: [[set i [+ i 1]] [set total [+ total i]]]} total"),
+            Ok(Value::Integer(15))
+        );
+    }
+
+    #[test]
+    fn test_closures() {
+        let factorial = "[var factorial {fn r: u64 a: [[n u64]] c: [
+            [var acc 1]
+            {while c: [greater_than n 1] do: [
+                [set acc [multiply acc n]]
+                [set n [plus n -1]]
+            ]}
+            acc
+        ]}]";
+        assert_eq!(
+            eval(&format!("{} [factorial 10]", factorial)),
+            Ok(Value::Integer(3628800))
+        );
+        assert_eq!(
+            eval(&format!("{} [factorial -1]", factorial)),
+            Err("factorial: n: -1 does not fit in [u64]".to_string())
+        );
+
+        // closures keep the scope they were made in
+        let counter = "[var make {fn a: [start] c: [
+            [var n start]
+            {fn c: [[set n [+ n 1]] n]}
+        ]}] [var next [make 10]] [next] [next]";
+        assert_eq!(eval(counter), Ok(Value::Integer(12)));
+
+        let recursive = "[var fib {fn a: [[n i64] [k i64 0]] c:
+            {if c: [< n 2] do: [+ n k] else: [+ [fib [- n 1]] [fib [- n 2]]]}}] [fib 15]";
+        assert_eq!(eval(recursive), Ok(Value::Integer(610)));
+        assert_eq!(
+            eval("[var f {fn a: [x] c: x}] [f]"),
+            Err("f expects 1 arguments but got 0".to_string())
+        );
+        assert_eq!(
+            eval("[var x 1] [x 2]"),
+            Err("x is a integer not a function".to_string())
+        );
+        assert_eq!(
+            eval("[var f {fn r: char c: 1}] [f]"),
+            Err("f: result: expected [char] but found 1\n  in f".to_string())
+        );
+    }
+
+    #[test]
+    fn test_register_fn() {
+        let mut runtime = Runtime::new();
+        let run = |runtime: &mut Runtime, code: &str| -> Result<Value, String> {
+            let mut last = nothing();
+            for code in Code::from_str(code)? {
+                last = runtime.eval(&code).map_err(|e| e.to_string())?;
+            }
+            Ok(last)
+        };
+        runtime
+            .register_fn("scale", |(n, by): (u64, f64)| -> Result<f64, String> {
+                Ok(n as f64 * by)
+            })
+            .unwrap();
+        runtime
+            .register_fn("root", |n: f64| {
+                if n < 0.0 {
+                    Err(format!("{} has no square root", n))
+                } else {
+                    Ok(n.sqrt())
+                }
+            })
+            .unwrap();
+        runtime
+            .register_fn("answer", |()| Ok::<_, String>(42u8))
+            .unwrap();
+        runtime
+            .register_fn("upto", |(n,): (u8,)| {
+                Ok::<_, String>((0..n).collect::<Vec<u8>>())
+            })
+            .unwrap();
+        runtime
+            .register_fn("sum", |numbers: Vec<i64>| {
+                Ok::<_, String>(numbers.iter().sum::<i64>())
+            })
+            .unwrap();
+
+        assert_eq!(
+            run(&mut runtime, "[scale 3 1.5]"),
+            Ok(Value::from_float(4.5))
+        );
+        assert_eq!(
+            run(&mut runtime, "[root [scale 8 2.0]]"),
+            Ok(Value::from_float(4.0))
+        );
+        assert_eq!(run(&mut runtime, "[+ [answer] 1]"), Ok(Value::Integer(43)));
+        assert_eq!(
+            run(&mut runtime, "[upto 3]"),
+            Ok(Value::List((0..3).map(Value::Integer).collect()))
+        );
+        assert_eq!(run(&mut runtime, "[sum [upto 5]]"), Ok(Value::Integer(10)));
+        // registered functions are values like built-ins
+        assert_eq!(
+            run(&mut runtime, "[var f scale] [f 2 0.5]"),
+            Ok(Value::from_float(1.0))
+        );
+
+        assert_eq!(
+            run(&mut runtime, "[root -4.0]"),
+            Err("root: -4 has no square root".to_string())
+        );
+        assert_eq!(
+            run(&mut runtime, "[scale -3 1.5]"),
+            Err("scale: arg0: -3 does not fit in [u64]".to_string())
+        );
+        assert_eq!(
+            run(&mut runtime, "[scale 3]"),
+            Err("scale expects 2 arguments but got 1".to_string())
+        );
+        assert_eq!(
+            runtime.signature("scale"),
+            Some(
+                Code::from_str("{fn a: [[arg0 [u64]] [arg1 [f64]]] r: [f64]}").unwrap()[0].clone()
+            )
+        );
+        assert_eq!(runtime.signature("nope"), None);
+
+        // registering again replaces the earlier function but not a built-in
+        runtime
+            .register_fn("answer", |()| Ok::<_, String>('x'))
+            .unwrap();
+        assert_eq!(run(&mut runtime, "[answer]"), Ok(Value::Character('x')));
+        assert_eq!(
+            runtime.register_fn("plus", |()| Ok::<_, String>(0u8)),
+            Err("plus is a built-in and can't be replaced".to_string())
+        );
+        assert_eq!(run(&mut runtime, "[plus 1 2]"), Ok(Value::Integer(3)));
+    }
+
+    #[test]
+    fn test_limits() {
+        let run = |limits: Limits, code: &str| -> Result<Value, String> {
+            let mut runtime = Runtime::new();
+            runtime.set_limits(limits);
+            let mut last = nothing();
+            for code in Code::from_str(code)? {
+                last = runtime.eval(&code).map_err(|e| e.to_string())?;
+            }
+            Ok(last)
+        };
+        let forever = "{while c: true do: [+ 1 1]}";
+        assert_eq!(
+            run(
+                Limits {
+                    steps: Some(1000),
+                    ..Limits::default()
+                },
+                forever
+            ),
+            Err("Step limit of 1000 reached".to_string())
+        );
+        assert_eq!(
+            run(
+                Limits {
+                    deadline: Some(Duration::from_millis(20)),
+                    ..Limits::default()
+                },
+                forever
+            ),
+            Err("Deadline of 20ms reached".to_string())
+        );
+
+        let deep = "[var f {fn a: [n] c: [+ 1 [f [+ n 1]]]}] [f 0]";
+        assert_eq!(
+            run(
+                Limits {
+                    depth: Some(50),
+                    ..Limits::default()
+                },
+                deep
+            ),
+            // only the innermost frames are shown
+            Err(format!(
+                "Depth limit of 50 reached{}\n  ... 40 more",
+                "\n  in f".repeat(10)
+            ))
+        );
+
+        // a limit deeper than the stack goes stops once the stack is used up instead
+        let deeper = "[var d {fn a: [n] c: {if c: [= n 0] do: 0 else: [+ 1 [d [- n 1]]]}}]
+                      [d 50000]";
+        let limits = Limits {
+            depth: Some(100000),
+            ..Limits::default()
+        };
+        let code = Code::from_str(deeper).unwrap();
+        for tree in [false, true] {
+            let mut runtime = Runtime::new();
+            runtime.set_limits(limits.clone());
+            let mut result = Ok(nothing());
+            for code in &code {
+                result = match tree {
+                    true => runtime.interpret(code),
+                    false => runtime.eval(code),
+                };
+            }
+            let err = result.unwrap_err();
+            assert!(err.is_fatal());
+            assert!(err
+                .message
+                .starts_with("Depth limit reached, the stack is full"));
+        }
+        // a smaller stack budget stops sooner
+        let shallow = "[var d {fn a: [n] c: {if c: [= n 0] do: 0 else: [+ 1 [d [- n 1]]]}}] [d 20]";
+        assert_eq!(run(Limits::default(), shallow), Ok(Value::Integer(20)));
+        assert!(run(
+            Limits {
+                stack: Some(16 << 10),
+                ..Limits::default()
+            },
+            shallow
+        )
+        .unwrap_err()
+        .starts_with("Depth limit reached, the stack is full"));
+
+        assert_eq!(
+            run(
+                Limits {
+                    allocation: Some(4),
+                    ..Limits::default()
+                },
+                "[1 2 3 4] {k: 1} [5 6 7 8 9]"
+            ),
+            Err("Allocation limit of 4 reached by a list of 5".to_string())
+        );
+        assert_eq!(
+            run(
+                Limits {
+                    allocation: Some(10),
+                    steps: Some(1000),
+                    ..Limits::default()
+                },
+                "[var s \"abcd\"] {while c: true do: [set s [concat s s]]}"
+            ),
+            Err("Allocation limit of 10 reached by a string of 16".to_string())
+        );
+
+        // every run starts with a fresh budget
+        let mut runtime = Runtime::new();
+        runtime.set_limits(Limits {
+            steps: Some(50),
+            depth: Some(5),
+            ..Limits::default()
+        });
+        let count = Code::from_str("[var n 0] {while c: [< n 5] do: [set n [+ n 1]]}").unwrap();
+        for _ in 0..3 {
+            assert_eq!(runtime.eval(&count[0]), Ok(Value::Integer(0)));
+            assert_eq!(runtime.eval(&count[1]), Ok(nothing()));
+        }
+        // the depth is back to zero after an error
+        let deep = Code::from_str(deep).unwrap();
+        runtime.eval(&deep[0]).unwrap();
+        assert_eq!(
+            runtime.eval(&deep[1]).map_err(|e| e.to_string()),
+            Err(format!("Depth limit of 5 reached{}", "\n  in f".repeat(5)))
+        );
+        assert_eq!(
+            runtime.call("plus", vec![Value::Integer(1), Value::Integer(2)]),
+            Ok(Value::Integer(3))
+        );
+    }
+
+    #[test]
+    fn test_tail_calls() {
+        // none of these would fit on the stack without tail calls
+        assert_eq!(
+            eval(
+                "[var count {fn a: [n] c: {if c: [= n 0] do: done else: [count [- n 1]]}}]
+                 [count 1000000]"
+            ),
+            Ok(Value::symbol("done"))
+        );
+    }
+
+    #[test]
+    fn test_mutual_tail_calls() {
+        assert_eq!(
+            eval(
+                "[var even {fn a: [n] c: {if c: [= n 0] do: true else: [odd [- n 1]]}}]
+                 [var odd {fn a: [n] c: {if c: [= n 0] do: false else: [even [- n 1]]}}]
+                 [even 1000001]"
+            ),
+            Ok(Value::boolean(false))
+        );
+    }
+
+    #[test]
+    fn test_tail_call_checks() {
+        // local functions and typed ones
+        assert_eq!(
+            eval(
+                "[pgm
+                  [var down {fn a: [[n u64] [steps u64 0]] r: u64 c: {if c: [= n 0] do: steps else: [[var m [- n 1]] [down m [+ steps 1]]]}}]
+                  [down 100000]]"
+            ),
+            Ok(Value::Integer(100000))
+        );
+
+        // tail calls don't count towards the depth but other calls still do
+        let mut runtime = Runtime::new();
+        runtime.set_limits(Limits {
+            depth: Some(10),
+            ..Limits::default()
+        });
+        let code = Code::from_str(
+            "[var loop {fn a: [n] c: {if c: [= n 0] do: done else: [loop [- n 1]]}}]
+             [loop 100000]
+             [var deep {fn a: [n] c: {if c: [= n 0] do: 0 else: [+ 1 [deep [- n 1]]]}}]
+             [deep 100]",
+        )
+        .unwrap();
+        let results: Vec<_> = code
+            .iter()
+            .map(|code| runtime.eval(code).map_err(|e| e.to_string()))
+            .collect();
+        assert_eq!(results[1], Ok(Value::symbol("done")));
+        assert_eq!(
+            results[3],
+            Err(format!(
+                "Depth limit of 10 reached{}",
+                "\n  in deep".repeat(10)
+            ))
+        );
+
+        // a tail call to a function with another result type is checked on the way back
+        assert_eq!(
+            eval(
+                "[var text {fn c: \"x\"}]
+                 [var number {fn r: u64 c: [text]}]
+                 [number]"
+            ),
+            Err("number: result: expected [u64] but found \"x\"\n  in number".to_string())
+        );
+    }
+
+    #[test]
+    fn test_exceptions() {
+        // anything can be raised and catch gets it back
+        assert_eq!(
+            eval("{try do: [raise 42] catch: [e] handle: [+ e 1]}"),
+            Ok(Value::Integer(43))
+        );
+        assert_eq!(
+            eval("{try do: 1 catch: [e] handle: 2}"),
+            Ok(Value::Integer(1))
+        );
+        // failures of built-ins are caught as error values
+        assert_eq!(
+            eval("{try do: [/ 1 0] catch: [e] handle: e}"),
+            Ok(error::error_value("division_by_zero", "Division by zero"))
+        );
+        let kinds = [
+            ("[/ 1 0]", "arithmetic_error"),
+            ("[+ 1 a]", "type_error"),
+            ("[[var f {fn a: [x] c: x}] [f]]", "arity_error"),
+            ("[nope 1]", "name_error"),
+            ("[read_file \"x\"]", "capability_error"),
+            (
+                "[raise {error: parse_failure message: \"bad\"}]",
+                "parse_failure",
+            ),
+        ];
+        for (body, kind) in kinds {
+            let code = format!(
+                "{{try do: {} catch: [e] handle: [is_error e {}]}}",
+                body, kind
+            );
+            assert_eq!(eval(&code), Ok(Value::boolean(true)), "for {}", body);
+        }
+        assert_eq!(
+            eval("{try do: [raise 1] catch: [e] handle: [is_error e error]}"),
+            Ok(Value::boolean(false))
+        );
+        // the kind comes from the built-in, not from what its message says
+        assert_eq!(
+            eval("{try do: [json_parse \"[1,\"] catch: [e] handle: [is_error e type_error]}"),
+            Ok(Value::boolean(false))
+        );
+
+        // the body shares the variables around it
+        assert_eq!(
+            eval("[pgm [var n 1] {try do: [set n [+ n 1]] catch: [e] handle: 0} n]"),
+            Ok(Value::Integer(2))
+        );
+        // raising a caught error again fails the way it did the first time
+        assert_eq!(
+            eval("{try do: {try do: [/ 1 0] catch: [e] handle: [raise e]} catch: [e] handle: e}"),
+            Ok(error::error_value("division_by_zero", "Division by zero"))
+        );
+        assert_eq!(eval("[/ 1 0]"), Err("Division by zero".to_string()));
+        assert_eq!(
+            eval("{try catch: [e]}"),
+            Err("try needs a body do:".to_string())
+        );
+    }
+
+    #[test]
+    fn test_finally() {
+        let mut runtime = Runtime::new();
+        let run = |runtime: &mut Runtime, code: &str| -> Result<Value, String> {
+            let mut last = nothing();
+            for code in Code::from_str(code)? {
+                last = runtime.eval(&code).map_err(|e| e.to_string())?;
+            }
+            Ok(last)
+        };
+        assert_eq!(
+            run(
+                &mut runtime,
+                "[var log 0] {try do: 1 finally: [set log [+ log 1]]}"
+            ),
+            Ok(Value::Integer(1))
+        );
+        assert_eq!(
+            run(
+                &mut runtime,
+                "{try do: [raise oops] finally: [set log [+ log 10]]}"
+            ),
+            Err("Uncaught error oops\n  in try".to_string())
+        );
+        assert_eq!(
+            run(
+                &mut runtime,
+                "{try do: [raise oops] catch: [e] handle: e finally: [set log [+ log 100]]}"
+            ),
+            Ok(Value::symbol("oops"))
+        );
+        assert_eq!(run(&mut runtime, "log"), Ok(Value::Integer(111)));
+
+        // errors that aren't caught come with the calls they left
+        let result = run(
+            &mut runtime,
+            "[var inner {fn c: [raise bad]}]
+             [var outer {fn c: [+ 1 [inner]]}]
+             [outer]",
+        );
+        assert_eq!(
+            result,
+            Err("Uncaught error bad\n  in inner\n  in outer".to_string())
+        );
+
+        // raised values are caught whatever their message says
+        assert_eq!(
+            run(
+                &mut runtime,
+                "{try do: [raise {error: x message: \"Interrupted\"}] catch: [e] handle: caught}"
+            ),
+            Ok(Value::symbol("caught"))
+        );
+
+        // limits stop the script whatever it catches
+        runtime.set_limits(Limits {
+            steps: Some(100),
+            ..Limits::default()
+        });
+        let forever = Code::from_str(
+            "{try do: {while c: true do: []} catch: [e] handle: caught finally: [set log 0]}",
+        )
+        .unwrap();
+        assert_eq!(
+            runtime.eval(&forever[0]).map_err(|e| e.to_string()),
+            Err("Step limit of 100 reached\n  in try".to_string())
+        );
+    }
+
+    #[test]
+    fn test_call_stack() {
+        let run_in = |runtime: &mut Runtime, source: &str, tree: bool| -> Result<Value, Error> {
+            let tokens = crate::token::tokenize_from_str(source)?;
+            let (code, spans) = crate::parse::parse_located(&tokens)?;
+            let source = SourceMap::new(source);
+            let mut last = nothing();
+            for (code, spans) in code.iter().zip(&spans) {
+                let at = source.locate(code, spans);
+                last = match tree {
+                    true => runtime.interpret_located(code, &at),
+                    false => runtime.eval_located(code, &at),
+                }?;
+            }
+            Ok(last)
+        };
+        let run = |runtime: &mut Runtime, source: &str| run_in(runtime, source, false);
+        let at = |line, column| Some(Location { line, column });
+
+        // frames say where they were called from, outer ran [middle 2] as a tail call
+        // so its frame was taken over by middle
+        let mut runtime = Runtime::new();
+        let code = "[var inner {fn a: [n] c: [/ n 0]}]\n\
+                    [var middle {fn a: [n] c: [+ 1 [inner n]]}]\n\
+                    [var outer {fn c: [middle 2]}]\n\
+                    [outer]";
+        let err = run(&mut runtime, code).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Division by zero\n  in inner at 2:32\n  in middle at 3:19"
+        );
+        assert_eq!(
+            err.backtrace(),
+            [
+                Frame {
+                    name: ts("inner"),
+                    at: at(2, 32)
+                },
+                Frame {
+                    name: ts("middle"),
+                    at: at(3, 19)
+                },
+            ]
+        );
+        // the tree walker finds them in the copies its closures keep of their bodies
+        assert_eq!(
+            run_in(&mut Runtime::new(), code, true).map_err(|e| e.to_string()),
+            Err(
+                "Division by zero\n  in inner at 2:32\n  in middle at 3:19\n  in outer at 4:1"
+                    .to_string()
+            )
+        );
+
+        // code without a source map has no locations, the tree walker makes no tail calls
+        for (tree, trace) in [
+            (false, "inner\n  in middle"),
+            (true, "inner\n  in middle\n  in outer"),
+        ] {
+            let mut runtime = Runtime::new();
+            let mut result = Ok(nothing());
+            for code in Code::from_str(code).unwrap() {
+                result = match tree {
+                    true => runtime.interpret(&code),
+                    false => runtime.eval(&code),
+                };
+            }
+            assert_eq!(
+                result.map_err(|e| e.to_string()),
+                Err(format!("Division by zero\n  in {}", trace))
+            );
+        }
+
+        // both find them in the parts of a try, which are made into functions
+        // the vm calls boom in place of the handler
+        let code = "[var boom {fn c: [/ 1 0]}]\n\
+                    {try do: [boom] catch: [e] handle: [pgm\n  [boom]]}";
+        for (tree, trace) in [(false, ""), (true, "\n  in catch")] {
+            let err = run_in(&mut Runtime::new(), code, tree).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                format!("Division by zero\n  in boom at 3:3{}", trace)
+            );
+        }
+
+        // scripts get the frames they are running in, outermost first
+        let code = "[var here {fn c: [call_stack]}]\n\
+                    [var there {fn c: [pgm [var s [here]] s]}]\n\
+                    [there]";
+        let frames = [
+            Frame {
+                name: ts("there"),
+                at: at(3, 1),
             },
-            Arg{
-                name: ts("rhs"),
-                typ: LampType::F64,
-                default: None,
+            Frame {
+                name: ts("here"),
+                at: at(2, 31),
+            },
+        ];
+        for tree in [false, true] {
+            assert_eq!(
+                run_in(&mut runtime, code, tree),
+                Ok(Value::List(frames.iter().map(Frame::to_value).collect()))
+            );
+        }
+        assert_eq!(
+            run(&mut runtime, "[call_stack]"),
+            Ok(Value::List(PVec::new()))
+        );
+    }
+
+    #[test]
+    fn test_cancel() {
+        let mut runtime = Runtime::new();
+        let token = runtime.cancel_token();
+        let canceller = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            token.cancel();
+        });
+        let forever = Code::from_str("{while c: true do: [+ 1 1]}").unwrap();
+        assert_eq!(
+            runtime.eval(&forever[0]).map_err(|e| e.cause),
+            Err(Cause::Interrupted)
+        );
+        canceller.join().unwrap();
 
-            }],
-            runable: Runable::BuiltIn(ts("plus")),
-            returns: Some(LampType::F64),
+        // the runtime is usable again and calls are checked as well as loops
+        let token = runtime.cancel_token();
+        assert!(!token.is_cancelled());
+        assert_eq!(
+            runtime.call("plus", vec![Value::Integer(1), Value::Integer(2)]),
+            Ok(Value::Integer(3))
+        );
+        token.cancel();
+        assert_eq!(
+            runtime
+                .call("plus", vec![Value::Integer(1), Value::Integer(2)])
+                .map_err(|e| e.cause),
+            Err(Cause::Interrupted)
+        );
+        token.cancel();
+        token.reset();
+        assert_eq!(runtime.eval(&Code::Integer(1)), Ok(Value::Integer(1)));
+    }
+
+    #[test]
+    fn test_capabilities() {
+        assert_eq!(
+            eval("[read_file \"Cargo.toml\"]"),
+            Err("Capability Error: reading Cargo.toml is not allowed".to_string())
+        );
+        let mut runtime = Runtime::with_capabilities(Capabilities {
+            read: vec!["Cargo.toml".into()],
+            ..Capabilities::pure()
+        });
+        let code = Code::from_str("[read_file \"Cargo.toml\"]").unwrap();
+        match runtime.eval(&code[0]) {
+            Ok(Value::String(text)) => assert!(text.contains("lamp_lang")),
+            other => panic!("expected the manifest but got {:?}", other),
         }
+        let code = Code::from_str("[clock]").unwrap();
+        assert!(runtime.eval(&code[0]).is_err());
+        assert!(runtime.capabilities().read.len() == 1);
+    }
+
+    struct Entity {
+        name: char,
+        position: RefCell<i64>,
+    }
+
+    impl HostObject for Entity {
+        const TYPE_NAME: &'static str = "Entity";
+    }
+
+    struct Door;
+
+    impl HostObject for Door {
+        const TYPE_NAME: &'static str = "Door";
+    }
+
+    #[test]
+    fn test_host_methods() {
+        let mut runtime = Runtime::new();
+        let hero = Handle::new(Entity {
+            name: 'h',
+            position: RefCell::new(0),
+        });
+        let door = Handle::new(Door);
+        runtime.add_variable("hero", hero.to_value());
+        runtime.add_variable("door", door.to_value());
+        runtime
+            .register_fn("summon", |name: char| {
+                Ok::<_, String>(Handle::new(Entity {
+                    name,
+                    position: RefCell::new(100),
+                }))
+            })
+            .unwrap();
+        runtime.register_method("walk", |entity: &Entity, by: i64| {
+            *entity.position.borrow_mut() += by;
+            Ok::<_, String>(*entity.position.borrow())
+        });
+        runtime.register_method("name", |entity: &Entity, ()| Ok::<_, String>(entity.name));
+        runtime.register_method("name", |_: &Door, ()| Ok::<_, String>('d'));
+        runtime
+            .register_fn("same", |(a, b): (Handle<Entity>, Handle<Entity>)| {
+                Ok::<_, String>(std::ptr::eq(&*a, &*b))
+            })
+            .unwrap();
+
+        let mut run = |code: &str| -> Result<Value, String> {
+            let mut last = nothing();
+            for code in Code::from_str(code)? {
+                last = runtime.eval(&code).map_err(|e| e.to_string())?;
+            }
+            Ok(last)
+        };
+        assert_eq!(run("[walk hero 3] [walk hero 4]"), Ok(Value::Integer(7)));
+        assert_eq!(*hero.position.borrow(), 7);
+        assert_eq!(run("[name hero]"), Ok(Value::Character('h')));
+        assert_eq!(run("[name door]"), Ok(Value::Character('d')));
+        assert_eq!(
+            run("[var e [summon 'e']] [walk e -1]"),
+            Ok(Value::Integer(99))
+        );
+        assert_eq!(run("[name e]"), Ok(Value::Character('e')));
+        assert_eq!(run("[same hero hero]"), Ok(Value::boolean(true)));
+        assert_eq!(run("[same hero e]"), Ok(Value::boolean(false)));
+        assert_eq!(
+            run("[walk door 1]"),
+            Err("Door has no method walk".to_string())
+        );
+        assert_eq!(
+            run("[same hero door]"),
+            Err("same: arg1: expected [Host Entity] but found <host Door>".to_string())
+        );
+        assert_eq!(
+            run("[walk hero 'x']"),
+            Err("walk: arg1: expected [i64] but found 'x'".to_string())
+        );
+        assert!(run("[+ hero 1]").is_err());
+
+        // scripts hold a reference until the runtime goes away
+        assert_eq!(hero.ref_count(), 2);
+        drop(runtime);
+        assert_eq!(hero.ref_count(), 1);
     }
 }
@@ -13,18 +13,31 @@ pub enum TokenKind {
     // literals
     Integer(i128),
     Float(u64), // code needs to be hashable
+    // numbers with a type suffix like 42u8 or 3.0f32
+    TypedInteger(i128, String),
+    TypedFloat(u64, String),
     Character(char),
     StringLiteral(String),
+    ByteString(Vec<u8>),
+    // string with {expr} parts spliced in
+    Interpolated(Vec<StrPart>),
     // identifier
     Identifier(String),
     // symbol - sequence of non-alphanumeric, non-whitespade chars
     Symbol(String),
     // comment out fn_exprs by add '/' in front - handled in parser
+    // the commented expression stays in the token stream
     // single line comment '#'
     // multi line comment ###
     Comment(String),
 }
 
+#[derive(PartialEq, Clone, Debug)]
+pub enum StrPart {
+    Text(String),
+    Code(Vec<Token>),
+}
+
 #[derive(PartialEq, Clone, Debug)]
 pub struct Token {
     pub start: usize,
@@ -42,11 +55,7 @@ impl Token {
     }
 
     pub fn is_whitespace(&self) -> bool {
-        match self.kind {
-            Whitespace(_) => true,
-            Comment(_) => true,
-            _ => false,
-        }
+        matches!(self.kind, Whitespace(_) | Comment(_))
     }
 
     pub fn end(&self) -> usize {
@@ -69,13 +78,11 @@ impl<'a> Queue<'a, char> {
 
     pub fn pop_char(&mut self, end: char) -> Result<Option<char>, String> {
         Ok(Some(match self.pop() {
-            Some('\\') => match self.pop() {
-                Some('n') => '\n',
-                Some('r') => '\r',
-                Some('t') => '\t',
-                Some(&c) => c,
-                None => return Err("Reached end of file while parsing string/char".to_string()),
-            },
+            Some('\\') => {
+                let code = self.pop_escape(false)?;
+                // pop_escape only returns valid scalar values outside of byte mode
+                char::from_u32(code).unwrap()
+            }
             Some(&c) => {
                 if c == end {
                     return Ok(None);
@@ -86,23 +93,527 @@ impl<'a> Queue<'a, char> {
             None => return Err("Reached end of file while parsing string/char".to_string()),
         }))
     }
+
+    pub fn pop_byte(&mut self, end: char) -> Result<Option<u8>, String> {
+        Ok(Some(match self.pop() {
+            Some('\\') => self.pop_escape(true)? as u8,
+            Some(&c) if c == end => return Ok(None),
+            Some(&c) if c.is_ascii() => c as u8,
+            Some(&c) => {
+                return Err(format!(
+                    "Scanner Error: Non ascii character '{}' in byte string",
+                    c
+                ))
+            }
+            None => return Err("Reached end of file while parsing byte string".to_string()),
+        }))
+    }
+
+    // parses what follows a '\' and returns the escaped code point
+    // bytes allow \x escapes up to 0xFF but no unicode escapes
+    fn pop_escape(&mut self, byte: bool) -> Result<u32, String> {
+        Ok(match self.pop() {
+            Some('n') => '\n' as u32,
+            Some('r') => '\r' as u32,
+            Some('t') => '\t' as u32,
+            Some('0') => 0,
+            Some('\\') => '\\' as u32,
+            Some('{') => '{' as u32,
+            Some('}') => '}' as u32,
+            Some('"') => '"' as u32,
+            Some('\'') => '\'' as u32,
+            Some('x') => {
+                let start = self.cursor;
+                let digits = self.s_pop_while(|c| c.is_ascii_hexdigit());
+                let max = if byte { 0xFF } else { 0x7F };
+                match u32::from_str_radix(&digits, 16) {
+                    Ok(code) if digits.len() == 2 && code <= max => code,
+                    _ => {
+                        return Err(format!(
+                            "Scanner Error: Invalid escape \"\\x{}\", expected two hex digits up to {:X}",
+                            self.range_string(start),
+                            max
+                        ))
+                    }
+                }
+            }
+            Some('u') if !byte => {
+                let start = self.cursor;
+                if self.pop() != Some(&'{') {
+                    return Err(
+                        "Scanner Error: Unicode escape must look like \\u{XXXX}".to_string()
+                    );
+                }
+                let digits = self.s_pop_while(|c| c.is_ascii_hexdigit());
+                let closed = self.pop() == Some(&'}');
+                let code = u32::from_str_radix(&digits, 16).ok();
+                match code.filter(|c| closed && digits.len() <= 6 && char::from_u32(*c).is_some()) {
+                    Some(code) => code,
+                    None => {
+                        return Err(format!(
+                            "Scanner Error: Invalid unicode escape \"\\u{}\"",
+                            self.range_string(start)
+                        ))
+                    }
+                }
+            }
+            Some(&c) => {
+                return Err(format!(
+                    "Scanner Error: Unknown escape sequence \"\\{}\"",
+                    c
+                ))
+            }
+            None => return Err("Reached end of file while parsing string/char".to_string()),
+        })
+    }
+}
+
+// """ strings can span lines and have {expr} parts like "" strings
+// the indentation shared by every non blank line is stripped along with
+// the line breaks after the opening and before the closing quotes
+fn pop_multiline_string(queue: &mut Queue<char>) -> Result<TokenKind, String> {
+    let start = queue.cursor;
+    let mut raw = String::new();
+    loop {
+        match queue.pop().copied() {
+            Some('"') if queue.peak() == Some(&'"') && queue.peak_nth(1) == Some(&'"') => {
+                queue.pop();
+                queue.pop();
+                break;
+            }
+            Some('\\') => {
+                raw.push('\\');
+                if let Some(&c) = queue.pop() {
+                    raw.push(c);
+                }
+            }
+            Some(c) => raw.push(c),
+            None => return Err("Reached end of file while parsing string".to_string()),
+        }
+    }
+    let end = queue.cursor - 3;
+
+    // the lines from first up to last are kept
+    let lines: Vec<&str> = raw.split('\n').collect();
+    let first = (lines.len() > 1 && lines[0].trim().is_empty()) as usize;
+    let last = match lines.len() > first + 1 && lines[lines.len() - 1].trim().is_empty() {
+        true => lines.len() - 1,
+        false => lines.len(),
+    };
+    let indent = lines[first..last]
+        .iter()
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| l.chars().take_while(|c| *c == ' ' || *c == '\t').count())
+        .min()
+        .unwrap_or(0);
+    // where each line starts in the source
+    let starts: Vec<usize> = lines
+        .iter()
+        .scan(start, |next, l| {
+            let here = *next;
+            *next += l.chars().count() + 1;
+            Some(here)
+        })
+        .collect();
+    let stop = match last < lines.len() {
+        true => starts[last] - 1,
+        false => end,
+    };
+
+    // the body is read again from the source so interpolated code keeps its positions
+    let mut body = Queue {
+        data: &queue.data[..end],
+        cursor: starts[first],
+    };
+    let mut parts = Vec::new();
+    let mut s = String::new();
+    let mut at_line_start = true;
+    while body.cursor < stop {
+        if at_line_start {
+            at_line_start = false;
+            let line = starts.partition_point(|&at| at <= body.cursor) - 1;
+            body.cursor += match lines[line].trim().is_empty() {
+                true => lines[line].chars().count(),
+                false => indent,
+            };
+            continue;
+        }
+        match body.peak() {
+            Some('\n') => {
+                body.pop();
+                s.push('\n');
+                at_line_start = true;
+            }
+            Some('{') => {
+                body.pop();
+                let tokens = pop_interpolation(&mut body)?;
+                if !s.is_empty() {
+                    parts.push(StrPart::Text(std::mem::take(&mut s)));
+                }
+                parts.push(StrPart::Code(tokens));
+            }
+            // a lone quote is just a quote inside a multi-line string
+            _ => s.push(body.pop_char('"')?.unwrap_or('"')),
+        }
+    }
+    Ok(string_parts(parts, s))
+}
+
+// tokens of an interpolated expression up to the closing '}'
+fn pop_interpolation(queue: &mut Queue<char>) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut depth = 0;
+    loop {
+        let start = queue.cursor;
+        // a string left open inside the expression ran into the end of the outer string
+        let token = pop_token(queue).map_err(|err| match queue.empty() {
+            true => INTERPOLATION_EOF.to_string(),
+            false => err,
+        })?;
+        match token {
+            None => return Err(INTERPOLATION_EOF.to_string()),
+            Some(Rcond) if depth == 0 => break,
+            Some(kind) => {
+                match kind {
+                    Lcond => depth += 1,
+                    Rcond => depth -= 1,
+                    _ => (),
+                }
+                tokens.push(Token::new(kind, start, queue.cursor - start));
+            }
+        }
+    }
+
+    if tokens.iter().all(|t| t.is_whitespace()) {
+        return Err("Scanner Error: Empty interpolation in string".to_string());
+    }
+    Ok(tokens)
+}
+
+const INTERPOLATION_EOF: &str = "Reached end of file while parsing string interpolation";
+
+// a '{' in a string starts an interpolated expression, "\{" is a literal brace
+fn pop_string(queue: &mut Queue<char>) -> Result<TokenKind, String> {
+    let mut parts = Vec::new();
+    let mut s = String::new();
+    loop {
+        if queue.peak() == Some(&'{') {
+            queue.pop();
+            let tokens = pop_interpolation(queue)?;
+            if !s.is_empty() {
+                parts.push(StrPart::Text(std::mem::take(&mut s)));
+            }
+            parts.push(StrPart::Code(tokens));
+            continue;
+        }
+        match queue.pop_char('"')? {
+            Some(c) => s.push(c),
+            None => break,
+        }
+    }
+    Ok(string_parts(parts, s))
+}
+
+// a plain string literal unless there were interpolated parts, rest is the text after the last
+fn string_parts(mut parts: Vec<StrPart>, rest: String) -> TokenKind {
+    if parts.is_empty() {
+        return StringLiteral(rest);
+    }
+    if !rest.is_empty() {
+        parts.push(StrPart::Text(rest));
+    }
+    Interpolated(parts)
+}
+
+// handles the r"", r#""#, b"" and br"" string prefixes
+fn pop_prefixed_string(queue: &mut Queue<char>) -> Result<Option<TokenKind>, String> {
+    let byte = queue.peak() == Some(&'b');
+    let mut offset = byte as usize;
+    let raw = queue.peak_nth(offset) == Some(&'r');
+    offset += raw as usize;
+    if !byte && !raw {
+        return Ok(None);
+    }
+    let mut hashes = 0;
+    while raw && queue.peak_nth(offset + hashes) == Some(&'#') {
+        hashes += 1;
+    }
+    if queue.peak_nth(offset + hashes) != Some(&'"') {
+        return Ok(None);
+    }
+    for _ in 0..offset + hashes + 1 {
+        queue.pop();
+    }
+
+    if !raw {
+        let mut bytes = Vec::new();
+        while let Some(b) = queue.pop_byte('"')? {
+            bytes.push(b);
+        }
+        return Ok(Some(ByteString(bytes)));
+    }
+
+    let mut s = String::new();
+    loop {
+        match queue.pop().copied() {
+            Some('"') if (0..hashes).all(|n| queue.peak_nth(n) == Some(&'#')) => {
+                for _ in 0..hashes {
+                    queue.pop();
+                }
+                break;
+            }
+            Some(c) => s.push(c),
+            None => return Err("Reached end of file while parsing raw string".to_string()),
+        }
+    }
+
+    if !byte {
+        return Ok(Some(StringLiteral(s)));
+    }
+    match s.chars().find(|c| !c.is_ascii()) {
+        Some(c) => Err(format!(
+            "Scanner Error: Non ascii character '{}' in byte string",
+            c
+        )),
+        None => Ok(Some(ByteString(s.into_bytes()))),
+    }
 }
 
 // helper function
 fn is_symbol(c: char) -> bool {
     !c.is_whitespace()
         && !c.is_alphanumeric()
-        && match c {
-            '[' => false,
-            ']' => false,
-            '{' => false,
-            '}' => false,
-            '#' => false,
-            ':' => false,
-            '"' => false,
-            '\'' => false,
-            _ => true,
+        && !matches!(c, '[' | ']' | '{' | '}' | '#' | ':' | '"' | '\'')
+}
+
+const INTEGER_SUFFIXES: [&str; 8] = ["u8", "u16", "u32", "u64", "i8", "i16", "i32", "i64"];
+const FLOAT_SUFFIXES: [&str; 2] = ["f32", "f64"];
+
+// the range of values a typed integer literal can hold
+fn suffix_range(suffix: &str) -> (i128, i128) {
+    match suffix {
+        "u8" => (0, u8::MAX.into()),
+        "u16" => (0, u16::MAX.into()),
+        "u32" => (0, u32::MAX.into()),
+        "u64" => (0, u64::MAX.into()),
+        "i8" => (i8::MIN.into(), i8::MAX.into()),
+        "i16" => (i16::MIN.into(), i16::MAX.into()),
+        "i32" => (i32::MIN.into(), i32::MAX.into()),
+        "i64" => (i64::MIN.into(), i64::MAX.into()),
+        _ => (i128::MIN, i128::MAX),
+    }
+}
+
+// does the queue start with a number, including signed and leading dot forms
+fn starts_number(queue: &Queue<char>) -> bool {
+    let digit_at = |n| queue.peak_nth(n).is_some_and(|c: &char| c.is_ascii_digit());
+    match queue.peak() {
+        Some(c) if c.is_ascii_digit() => true,
+        Some('.') => digit_at(1),
+        Some('-') | Some('+') => digit_at(1) || (queue.peak_nth(1) == Some(&'.') && digit_at(2)),
+        _ => false,
+    }
+}
+
+// the floats written without digits, +inf, -inf, +nan and -nan
+fn pop_special_float(queue: &mut Queue<char>) -> Option<f64> {
+    let sign = match queue.peak() {
+        Some('+') => 1.0,
+        Some('-') => -1.0,
+        _ => return None,
+    };
+    let word: String = (1..4).filter_map(|n| queue.peak_nth(n)).collect();
+    let ends = queue
+        .peak_nth(4)
+        .is_none_or(|c| !c.is_alphanumeric() && *c != '_');
+    let num = match word.as_str() {
+        "inf" if ends => f64::INFINITY,
+        "nan" if ends => f64::NAN,
+        _ => return None,
+    };
+    for _ in 0..4 {
+        queue.pop();
+    }
+    Some(num.copysign(sign))
+}
+
+// checks '_' digit separators are only used between digits
+fn check_separators(digits: &str, lexeme: &str) -> Result<(), String> {
+    if digits.starts_with('_') || digits.ends_with('_') || digits.contains("__") {
+        return Err(format!(
+            "Scanner Error: Misplaced digit separator in \"{}\"",
+            lexeme
+        ));
+    }
+    Ok(())
+}
+
+fn pop_number(queue: &mut Queue<char>) -> Result<TokenKind, String> {
+    let start = queue.cursor;
+    let negative = match queue.peak() {
+        Some('-') => {
+            queue.pop();
+            true
+        }
+        Some('+') => {
+            queue.pop();
+            false
+        }
+        _ => false,
+    };
+
+    let radix = match (queue.peak(), queue.peak_nth(1)) {
+        (Some('0'), Some('x')) => 16,
+        (Some('0'), Some('o')) => 8,
+        (Some('0'), Some('b')) => 2,
+        _ => 10,
+    };
+
+    let mut is_float = false;
+    let mut text = String::new();
+    if radix != 10 {
+        queue.pop();
+        queue.pop();
+        let digits = queue.s_pop_while(|c| c.is_digit(radix) || c == '_');
+        if digits.chars().all(|c| c == '_') {
+            return Err(format!(
+                "Scanner Error: Missing digits after base prefix in \"{}\"",
+                queue.range_string(start)
+            ));
+        }
+        check_separators(&digits, &queue.range_string(start))?;
+        text.push_str(&digits);
+        if let Some(c) = queue.peak().filter(|c| c.is_ascii_digit()) {
+            return Err(format!(
+                "Scanner Error: Invalid digit '{}' for base {} literal \"{}\"",
+                c,
+                radix,
+                queue.range_string(start)
+            ));
+        }
+    } else {
+        let int_part = queue.s_pop_while(|c| c.is_ascii_digit() || c == '_');
+        check_separators(&int_part, &queue.range_string(start))?;
+        text.push_str(&int_part);
+
+        if queue.peak() == Some(&'.') && queue.peak_nth(1) != Some(&'.') {
+            queue.pop();
+            is_float = true;
+            let frac = queue.s_pop_while(|c| c.is_ascii_digit() || c == '_');
+            if !frac.is_empty() {
+                check_separators(&frac, &queue.range_string(start))?;
+            }
+            text.push('.');
+            text.push_str(&frac);
+        }
+
+        if matches!(queue.peak(), Some('e') | Some('E')) {
+            queue.pop();
+            is_float = true;
+            text.push('e');
+            if let Some(&sign) = queue.peak().filter(|c| **c == '-' || **c == '+') {
+                queue.pop();
+                text.push(sign);
+            }
+            let exp = queue.s_pop_while(|c| c.is_ascii_digit() || c == '_');
+            if !exp.chars().any(|c| c.is_ascii_digit()) {
+                return Err(format!(
+                    "Scanner Error: Missing exponent digits in \"{}\"",
+                    queue.range_string(start)
+                ));
+            }
+            check_separators(&exp, &queue.range_string(start))?;
+            text.push_str(&exp);
+        }
+    }
+    text.retain(|c| c != '_');
+
+    let suffix = queue.s_pop_while(|c| c.is_alphanumeric() || c == '_');
+    let lexeme = queue.range_string(start);
+    let is_float_suffix = FLOAT_SUFFIXES.contains(&suffix.as_str());
+    if !suffix.is_empty() && !is_float_suffix && !INTEGER_SUFFIXES.contains(&suffix.as_str()) {
+        return Err(format!(
+            "Scanner Error: Invalid suffix \"{}\" on number \"{}\"",
+            suffix, lexeme
+        ));
+    }
+
+    if is_float || is_float_suffix {
+        if radix != 10 {
+            return Err(format!(
+                "Scanner Error: Base {} literal \"{}\" cannot have a float suffix",
+                radix, lexeme
+            ));
         }
+        if is_float && !suffix.is_empty() && !is_float_suffix {
+            return Err(format!(
+                "Scanner Error: Decimal \"{}\" cannot have an integer suffix",
+                lexeme
+            ));
+        }
+        let mut num = match text.parse::<f64>() {
+            Ok(n) => n,
+            Err(_) => {
+                return Err(format!(
+                    "Scanner Error: Cannot parse \"{}\" as decimal",
+                    lexeme
+                ))
+            }
+        };
+        if negative {
+            num = -num;
+        }
+        if suffix == "f32" {
+            num = num as f32 as f64;
+        }
+        if num.is_infinite() {
+            return Err(format!(
+                "Scanner Error: Decimal \"{}\" is out of range",
+                lexeme
+            ));
+        }
+        return Ok(if suffix.is_empty() {
+            Float(num.to_bits())
+        } else {
+            TypedFloat(num.to_bits(), suffix)
+        });
+    }
+
+    let magnitude = match u128::from_str_radix(&text, radix) {
+        Ok(n) => n,
+        Err(_) => {
+            return Err(format!(
+                "Scanner Error: Integer \"{}\" is out of range",
+                lexeme
+            ))
+        }
+    };
+    let number = if negative {
+        0i128.checked_sub_unsigned(magnitude)
+    } else {
+        i128::try_from(magnitude).ok()
+    };
+    let number = match number {
+        Some(n) => n,
+        None => {
+            return Err(format!(
+                "Scanner Error: Integer \"{}\" is out of range",
+                lexeme
+            ))
+        }
+    };
+
+    if suffix.is_empty() {
+        return Ok(Integer(number));
+    }
+    let (min, max) = suffix_range(&suffix);
+    if number < min || number > max {
+        return Err(format!(
+            "Scanner Error: Integer \"{}\" does not fit in {}",
+            lexeme, suffix
+        ));
+    }
+    Ok(TypedInteger(number, suffix))
 }
 
 pub fn tokenize_from_str(code: &str) -> Result<Vec<Token>, String> {
@@ -126,6 +637,10 @@ pub fn pop_token(queue: &mut Queue<char>) -> Result<Option<TokenKind>, String> {
         return Ok(None);
     }
 
+    if let Some(string) = pop_prefixed_string(queue)? {
+        return Ok(Some(string));
+    }
+
     if queue.head().is_alphabetic() {
         return Ok(Some(Identifier(
             queue.s_pop_while(|c| c.is_alphanumeric() || c == '_'),
@@ -136,32 +651,14 @@ pub fn pop_token(queue: &mut Queue<char>) -> Result<Option<TokenKind>, String> {
         return Ok(Some(Whitespace(queue.s_pop_while(|c| c.is_whitespace()))));
     }
 
-    if queue.head().is_ascii_digit() {
-        let mut num = queue.s_pop_while(|c| c.is_ascii_digit());
-        return if queue.peak() == Some(&'.') {
-            num.push(*queue.pop().unwrap());
-            num.push_str(&queue.s_pop_while(|c| c.is_ascii_digit()));
-            match num.parse::<f64>() {
-                Ok(n) => Ok(Some(Float(n.to_bits()))),
-                Err(_) => Err(format!(
-                    "Scanner Error: Cannot parse \"{}\" as decimal",
-                    num
-                )),
-            }
-        } else {
-            match num.parse() {
-                Ok(number) => Ok(Some(Integer(number))),
-                Err(_) => {
-                    return Err(format!(
-                        "Scanner Error: Cannot parse \"{}\" as integer",
-                        num,
-                    ))
-                }
-            }
-        };
+    if let Some(num) = pop_special_float(queue) {
+        return Ok(Some(Float(num.to_bits())));
+    }
+    if starts_number(queue) {
+        return pop_number(queue).map(Some);
     }
 
-    Ok(Some(match *queue.pop().unwrap() {
+    Ok(Some(match queue.pop().copied().unwrap() {
         '[' => Lfn,
         ']' => Rfn,
         '{' => Lcond,
@@ -179,13 +676,12 @@ pub fn pop_token(queue: &mut Queue<char>) -> Result<Option<TokenKind>, String> {
             }
             Comment(queue.range_string(start))
         }
-        '"' => {
-            let mut s = String::new();
-            while let Some(c) = queue.pop_char('"')? {
-                s.push(c);
-            }
-            StringLiteral(s)
+        '"' if queue.peak() == Some(&'"') && queue.peak_nth(1) == Some(&'"') => {
+            queue.pop();
+            queue.pop();
+            pop_multiline_string(queue)?
         }
+        '"' => pop_string(queue)?,
         '\'' => {
             if let Some(c) = queue.pop_char('\'')? {
                 match queue.pop() {
@@ -208,9 +704,20 @@ pub fn pop_token(queue: &mut Queue<char>) -> Result<Option<TokenKind>, String> {
 }
 
 #[cfg(test)]
+#[allow(clippy::approx_constant)]
 mod tests {
     use super::*;
 
+    // token kinds of the code with whitespace left out
+    fn kinds(code: &str) -> Vec<TokenKind> {
+        tokenize_from_str(code)
+            .unwrap()
+            .into_iter()
+            .map(|t| t.kind)
+            .filter(|k| !matches!(k, Whitespace(_)))
+            .collect()
+    }
+
     #[test]
     fn test_delimiters() {
         let code: Vec<char> = "[] \n {}:".chars().collect();
@@ -272,6 +779,123 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_signed_and_scientific() {
+        assert_eq!(
+            tokenize_from_str("-5 +7 .5 -.25 1e9 1.5E-3").unwrap(),
+            [
+                Token::new(Integer(-5), 0, 2),
+                Token::new(Whitespace(" ".to_string()), 2, 1),
+                Token::new(Integer(7), 3, 2),
+                Token::new(Whitespace(" ".to_string()), 5, 1),
+                Token::new(Float(0.5_f64.to_bits()), 6, 2),
+                Token::new(Whitespace(" ".to_string()), 8, 1),
+                Token::new(Float((-0.25_f64).to_bits()), 9, 4),
+                Token::new(Whitespace(" ".to_string()), 13, 1),
+                Token::new(Float(1e9_f64.to_bits()), 14, 3),
+                Token::new(Whitespace(" ".to_string()), 17, 1),
+                Token::new(Float(1.5e-3_f64.to_bits()), 18, 6),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_radix_and_separators() {
+        assert_eq!(
+            kinds("0xFF 0o17 0b1010 1_000_000 -0x80"),
+            [
+                Integer(255),
+                Integer(15),
+                Integer(10),
+                Integer(1_000_000),
+                Integer(-128)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_special_floats() {
+        assert_eq!(
+            kinds("+inf -inf +nan -nan -info inf"),
+            [
+                Float(f64::INFINITY.to_bits()),
+                Float(f64::NEG_INFINITY.to_bits()),
+                Float(f64::NAN.to_bits()),
+                Float((-f64::NAN).to_bits()),
+                Symbol("-".to_string()),
+                Identifier("info".to_string()),
+                Identifier("inf".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_number_suffixes() {
+        assert_eq!(
+            kinds("42u8 3.0f32 -1i64 2f64 0xFFu8"),
+            [
+                TypedInteger(42, "u8".to_string()),
+                TypedFloat(3.0_f64.to_bits(), "f32".to_string()),
+                TypedInteger(-1, "i64".to_string()),
+                TypedFloat(2.0_f64.to_bits(), "f64".to_string()),
+                TypedInteger(255, "u8".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_malformed_numbers() {
+        let err = |s| tokenize_from_str(s).unwrap_err();
+        assert_eq!(
+            err("1__0"),
+            "Scanner Error: Misplaced digit separator in \"1__0\""
+        );
+        assert_eq!(
+            err("0x"),
+            "Scanner Error: Missing digits after base prefix in \"0x\""
+        );
+        assert_eq!(
+            err("0b102"),
+            "Scanner Error: Invalid digit '2' for base 2 literal \"0b10\""
+        );
+        assert_eq!(
+            err("1e"),
+            "Scanner Error: Missing exponent digits in \"1e\""
+        );
+        assert_eq!(
+            err("12abc"),
+            "Scanner Error: Invalid suffix \"abc\" on number \"12abc\""
+        );
+        assert_eq!(
+            err("256u8"),
+            "Scanner Error: Integer \"256u8\" does not fit in u8"
+        );
+        assert_eq!(
+            err("-1u32"),
+            "Scanner Error: Integer \"-1u32\" does not fit in u32"
+        );
+        assert_eq!(
+            err("1.5u8"),
+            "Scanner Error: Decimal \"1.5u8\" cannot have an integer suffix"
+        );
+        assert_eq!(
+            err("1e999"),
+            "Scanner Error: Decimal \"1e999\" is out of range"
+        );
+        assert_eq!(
+            err("0x1_0000_0000_0000_0000_0000_0000_0000_0000"),
+            "Scanner Error: Integer \"0x1_0000_0000_0000_0000_0000_0000_0000_0000\" is out of range"
+        );
+    }
+
+    #[test]
+    fn test_minus_symbol() {
+        assert_eq!(
+            tokenize_from_str("- 5").unwrap()[0],
+            Token::new(Symbol("-".to_string()), 0, 1)
+        );
+    }
+
     #[test]
     fn test_char_literals() {
         let code: Vec<char> = "'a''\\n''\\''".chars().collect();
@@ -297,6 +921,187 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_escapes() {
+        assert_eq!(
+            kinds(r#""\0\\\x41\u{1F600}\u{e9}" '\x7f' '\u{3bb}'"#),
+            [
+                StringLiteral("\0\\A\u{1F600}\u{e9}".to_string()),
+                Character('\x7f'),
+                Character('\u{3bb}'),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_bad_escapes() {
+        let err = |s| tokenize_from_str(s).unwrap_err();
+        assert_eq!(
+            err(r#""\q""#),
+            "Scanner Error: Unknown escape sequence \"\\q\""
+        );
+        assert_eq!(
+            err(r#""\xFF""#),
+            "Scanner Error: Invalid escape \"\\xFF\", expected two hex digits up to 7F"
+        );
+        assert_eq!(
+            err(r#""\u{D800}""#),
+            "Scanner Error: Invalid unicode escape \"\\u{D800}\""
+        );
+        assert_eq!(
+            err(r#""\u{1234567}""#),
+            "Scanner Error: Invalid unicode escape \"\\u{1234567}\""
+        );
+        assert_eq!(
+            err(r#""\u41""#),
+            "Scanner Error: Unicode escape must look like \\u{XXXX}"
+        );
+    }
+
+    #[test]
+    fn test_raw_strings() {
+        let code = r###"r"a\nb" r#"say "hi""# r##"a "# b"## rx"###;
+        assert_eq!(
+            kinds(code),
+            [
+                StringLiteral("a\\nb".to_string()),
+                StringLiteral("say \"hi\"".to_string()),
+                StringLiteral("a \"# b".to_string()),
+                Identifier("rx".to_string()),
+            ]
+        );
+        assert_eq!(
+            tokenize_from_str("r#\"abc\"").unwrap_err(),
+            "Reached end of file while parsing raw string"
+        );
+    }
+
+    #[test]
+    fn test_byte_strings() {
+        assert_eq!(
+            kinds(r#"b"a\xFF\n" br"\n" b"#),
+            [
+                ByteString(vec![b'a', 0xFF, b'\n']),
+                ByteString(vec![b'\\', b'n']),
+                Identifier("b".to_string()),
+            ]
+        );
+        assert_eq!(
+            tokenize_from_str("b\"é\"").unwrap_err(),
+            "Scanner Error: Non ascii character 'é' in byte string"
+        );
+        assert_eq!(
+            tokenize_from_str(r#"b"\u{41}""#).unwrap_err(),
+            "Scanner Error: Unknown escape sequence \"\\u\""
+        );
+    }
+
+    #[test]
+    fn test_multiline_strings() {
+        let code = "\"\"\"\n    first\n      \"second\"\\t\n\n    third\n    \"\"\"";
+        assert_eq!(
+            tokenize_from_str(code).unwrap(),
+            [Token::new(
+                StringLiteral("first\n  \"second\"\t\n\nthird".to_string()),
+                0,
+                code.chars().count()
+            )]
+        );
+        assert_eq!(
+            tokenize_from_str("\"\"\"one line\"\"\" \"\"").unwrap()[2].kind,
+            StringLiteral(String::new())
+        );
+        assert_eq!(
+            tokenize_from_str("\"\"\"abc\"\"").unwrap_err(),
+            "Reached end of file while parsing string"
+        );
+    }
+
+    #[test]
+    fn test_interpolation() {
+        assert_eq!(
+            tokenize_from_str("\"a{x}b\"").unwrap(),
+            [Token::new(
+                Interpolated(vec![
+                    StrPart::Text("a".to_string()),
+                    StrPart::Code(vec![Token::new(Identifier("x".to_string()), 3, 1)]),
+                    StrPart::Text("b".to_string()),
+                ]),
+                0,
+                7
+            )]
+        );
+        assert_eq!(
+            kinds("\"{[f {g: \"}\"}]}\""),
+            [Interpolated(vec![StrPart::Code(vec![
+                Token::new(Lfn, 2, 1),
+                Token::new(Identifier("f".to_string()), 3, 1),
+                Token::new(Whitespace(" ".to_string()), 4, 1),
+                Token::new(Lcond, 5, 1),
+                Token::new(Identifier("g".to_string()), 6, 1),
+                Token::new(FieldDelim, 7, 1),
+                Token::new(Whitespace(" ".to_string()), 8, 1),
+                Token::new(StringLiteral("}".to_string()), 9, 3),
+                Token::new(Rcond, 12, 1),
+                Token::new(Rfn, 13, 1),
+            ])])]
+        );
+    }
+
+    #[test]
+    fn test_multiline_interpolation() {
+        // the code keeps its place in the source while the text loses its indentation
+        let code = "\"\"\"\n  a{x}\n    \\{b\\}\n  \"\"\"";
+        assert_eq!(
+            kinds(code),
+            [Interpolated(vec![
+                StrPart::Text("a".to_string()),
+                StrPart::Code(vec![Token::new(Identifier("x".to_string()), 8, 1)]),
+                StrPart::Text("\n  {b}".to_string()),
+            ])]
+        );
+        assert_eq!(
+            kinds("\"\"\"{[f\n  1]} \"\"\""),
+            [Interpolated(vec![
+                StrPart::Code(vec![
+                    Token::new(Lfn, 4, 1),
+                    Token::new(Identifier("f".to_string()), 5, 1),
+                    Token::new(Whitespace("\n  ".to_string()), 6, 3),
+                    Token::new(Integer(1), 9, 1),
+                    Token::new(Rfn, 10, 1),
+                ]),
+                StrPart::Text(" ".to_string()),
+            ])]
+        );
+    }
+
+    #[test]
+    fn test_interpolation_escapes() {
+        assert_eq!(
+            kinds(r#""\{not code\}" r"{raw}""#),
+            [
+                StringLiteral("{not code}".to_string()),
+                StringLiteral("{raw}".to_string()),
+            ]
+        );
+        assert_eq!(
+            tokenize_from_str("\"{ }\"").unwrap_err(),
+            "Scanner Error: Empty interpolation in string"
+        );
+        assert_eq!(
+            tokenize_from_str("\"{x\"").unwrap_err(),
+            "Reached end of file while parsing string interpolation"
+        );
+        assert_eq!(
+            tokenize_from_str("\"{x").unwrap_err(),
+            "Reached end of file while parsing string interpolation"
+        );
+        assert_eq!(
+            tokenize_from_str("\"\"\"{x\"\"\"").unwrap_err(),
+            "Reached end of file while parsing string interpolation"
+        );
+    }
+
     #[test]
     fn test_comments() {
         let code: Vec<char> = "foo#bar\nfoo\nbar##foo\nbar##bazz#fizzbuzz"
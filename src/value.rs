@@ -0,0 +1,431 @@
+use std::any::Any;
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops::Deref;
+use std::rc::Rc;
+
+use crate::code::Code;
+use crate::datum::{wrong_shape, DatumError, FromDatum, ToDatum};
+use crate::lamp_type::LampType;
+use crate::map::Map;
+use crate::persistent::{PMap, PVec};
+use crate::runtime::Closure;
+use crate::utils::ts;
+
+// what code evaluates to
+// code is the syntax of a program, values are what it computes with
+// lists and maps are persistent so copying a value never copies the collection
+#[derive(Debug, Clone)]
+pub enum Value {
+    Integer(i128),
+    Float(u64),
+    Character(char),
+    String(Rc<str>),
+    // identifiers that aren't bound to anything, true and false are symbols
+    Symbol(Rc<str>),
+    List(PVec<Value>),
+    Map(PMap<Value, Value>),
+    Closure(Rc<Closure>),
+    // a function registered with the runtime by name
+    BuiltIn(Rc<str>),
+    Type(Rc<LampType>),
+    Host(Host),
+}
+
+// a rust type whose objects can be handed to scripts
+// the name is the lamp type of handles to it, [Host Name]
+pub trait HostObject: Any {
+    const TYPE_NAME: &'static str;
+
+    fn lamp_type() -> LampType {
+        LampType::Host(ts(Self::TYPE_NAME))
+    }
+}
+
+// a rust object handed to scripts, scripts can pass it around but not look inside
+// the object lives as long as any value or handle refers to it
+#[derive(Clone)]
+pub struct Host {
+    type_name: &'static str,
+    object: Rc<dyn Any>,
+}
+
+impl Host {
+    pub fn new<T: HostObject>(object: T) -> Host {
+        Handle::new(object).host()
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        self.type_name
+    }
+
+    pub fn downcast_ref<T: HostObject>(&self) -> Option<&T> {
+        self.object.downcast_ref()
+    }
+
+    // a typed handle sharing the object, None when it is some other type
+    pub fn downcast<T: HostObject>(&self) -> Option<Handle<T>> {
+        self.object.clone().downcast().ok().map(Handle)
+    }
+
+    // how many values and handles share the object
+    pub fn ref_count(&self) -> usize {
+        Rc::strong_count(&self.object)
+    }
+
+    // hosts are the same when they share the object
+    fn address(&self) -> *const u8 {
+        Rc::as_ptr(&self.object) as *const u8
+    }
+}
+
+impl fmt::Debug for Host {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<host {}>", self.type_name)
+    }
+}
+
+// the rust side of a host object, what native functions take and return
+pub struct Handle<T: HostObject>(Rc<T>);
+
+impl<T: HostObject> Handle<T> {
+    pub fn new(object: T) -> Handle<T> {
+        Handle(Rc::new(object))
+    }
+
+    pub fn host(&self) -> Host {
+        Host {
+            type_name: T::TYPE_NAME,
+            object: self.0.clone(),
+        }
+    }
+
+    pub fn ref_count(&self) -> usize {
+        Rc::strong_count(&self.0)
+    }
+}
+
+impl<T: HostObject> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        Handle(self.0.clone())
+    }
+}
+
+impl<T: HostObject> Deref for Handle<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: HostObject> fmt::Debug for Handle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<host {}>", T::TYPE_NAME)
+    }
+}
+
+impl<T: HostObject> ToDatum for Handle<T> {
+    fn to_lamp_type() -> LampType {
+        T::lamp_type()
+    }
+
+    // handles have no code, this only names what was there
+    fn to_code(&self) -> Code {
+        Code::Identifier(format!("{:?}", self))
+    }
+
+    fn to_value(&self) -> Value {
+        Value::Host(self.host())
+    }
+}
+
+impl<T: HostObject> FromDatum for Handle<T> {
+    fn from_code(code: &Code) -> Result<Self, DatumError> {
+        Err(wrong_shape::<Self>(code))
+    }
+
+    fn from_value(value: &Value) -> Result<Self, DatumError> {
+        let found = match value {
+            Value::Host(host) => match host.downcast() {
+                Some(handle) => return Ok(handle),
+                None => format!("{:?}", host),
+            },
+            _ => value.to_string(),
+        };
+        Err(DatumError::Invalid {
+            path: ts("value"),
+            reason: format!("expected {} but found {}", T::lamp_type().to_code(), found),
+        })
+    }
+}
+
+impl Value {
+    pub fn from_float(num: f64) -> Value {
+        Value::Float(num.to_bits())
+    }
+
+    pub fn string(s: &str) -> Value {
+        Value::String(s.into())
+    }
+
+    pub fn symbol(s: &str) -> Value {
+        Value::Symbol(s.into())
+    }
+
+    pub fn boolean(b: bool) -> Value {
+        Value::symbol(if b { "true" } else { "false" })
+    }
+
+    // the value code stands for without evaluating it
+    pub fn from_code(code: &Code) -> Value {
+        match code {
+            Code::Integer(num) => Value::Integer(*num),
+            Code::Float(num) => Value::Float(*num),
+            Code::Character(c) => Value::Character(*c),
+            Code::StringLiteral(s) => Value::string(s),
+            Code::Identifier(i) => Value::symbol(i),
+            Code::List(list) => Value::List(list.iter().map(Value::from_code).collect()),
+            Code::Map(map) => Value::Map(
+                map.iter()
+                    .map(|(k, v)| (Value::from_code(k), Value::from_code(v)))
+                    .collect(),
+            ),
+        }
+    }
+
+    // closures become the code that made them and built-ins their names,
+    // host objects have no code
+    pub fn to_code(&self) -> Result<Code, String> {
+        Ok(match self {
+            Value::Integer(num) => Code::Integer(*num),
+            Value::Float(num) => Code::Float(*num),
+            Value::Character(c) => Code::Character(*c),
+            Value::String(s) => Code::StringLiteral(s.to_string()),
+            Value::Symbol(s) | Value::BuiltIn(s) => Code::Identifier(s.to_string()),
+            Value::List(list) => {
+                Code::List(list.iter().map(Value::to_code).collect::<Result<_, _>>()?)
+            }
+            Value::Map(map) => {
+                let mut code = Map::new();
+                for (k, v) in map.iter() {
+                    code.insert(k.to_code()?, v.to_code()?);
+                }
+                Code::Map(code)
+            }
+            Value::Closure(closure) => closure.code().clone(),
+            Value::Type(typ) => typ.to_code(),
+            Value::Host(host) => {
+                return Err(format!("{:?} cannot be turned into code", host));
+            }
+        })
+    }
+
+    // the kind of value for error messages
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Value::Integer(_) => "integer",
+            Value::Float(_) => "float",
+            Value::Character(_) => "character",
+            Value::String(_) => "string",
+            Value::Symbol(_) => "symbol",
+            Value::List(_) => "list",
+            Value::Map(_) => "map",
+            Value::Closure(_) | Value::BuiltIn(_) => "function",
+            Value::Type(_) => "type",
+            Value::Host(_) => "host object",
+        }
+    }
+
+    // checks the value is a value of the type, name is used as the path in errors
+    pub fn check(&self, typ: &LampType, name: &str) -> Result<(), DatumError> {
+        typ.validate_value(self, name)
+    }
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Value::Integer(l), Value::Integer(r)) => l == r,
+            (Value::Float(l), Value::Float(r)) => l == r,
+            (Value::Character(l), Value::Character(r)) => l == r,
+            (Value::String(l), Value::String(r)) => l == r,
+            (Value::Symbol(l), Value::Symbol(r)) => l == r,
+            (Value::List(l), Value::List(r)) => l == r,
+            (Value::Map(l), Value::Map(r)) => l == r,
+            (Value::Closure(l), Value::Closure(r)) => Rc::ptr_eq(l, r),
+            (Value::BuiltIn(l), Value::BuiltIn(r)) => l == r,
+            (Value::Type(l), Value::Type(r)) => l == r,
+            (Value::Host(l), Value::Host(r)) => l.address() == r.address(),
+            _ => false,
+        }
+    }
+}
+
+impl Eq for Value {}
+
+impl Hash for Value {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            Value::Integer(num) => num.hash(state),
+            Value::Float(num) => num.hash(state),
+            Value::Character(c) => c.hash(state),
+            Value::String(s) | Value::Symbol(s) | Value::BuiltIn(s) => s.hash(state),
+            Value::List(list) => list.hash(state),
+            // maps are unordered so the hashes of the entries are summed
+            Value::Map(map) => map
+                .iter()
+                .map(|(k, v)| {
+                    let mut hasher = DefaultHasher::new();
+                    k.hash(&mut hasher);
+                    v.hash(&mut hasher);
+                    hasher.finish()
+                })
+                .fold(0u64, u64::wrapping_add)
+                .hash(state),
+            Value::Closure(closure) => Rc::as_ptr(closure).hash(state),
+            Value::Type(typ) => typ.hash(state),
+            Value::Host(host) => host.address().hash(state),
+        }
+    }
+}
+
+// the display protocol used by string interpolation
+// top level strings and characters are shown without quotes
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::String(s) => write!(f, "{}", s),
+            Value::Character(c) => write!(f, "{}", c),
+            Value::Host(host) => write!(f, "{:?}", host),
+            _ => match self.to_code() {
+                Ok(code) => write!(f, "{}", code.to_source()),
+                // a host object somewhere inside
+                Err(_) => write!(f, "<{}>", self.kind()),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_code_round_trip() {
+        let code = Code::from_str("[f {if c: [g 1.5 -2] do: \"x\"} 'q' [1 [2 3]]]").unwrap();
+        let value = Value::from_code(&code[0]);
+        assert_eq!(value.to_code(), Ok(code[0].clone()));
+        match &value {
+            Value::List(list) => {
+                assert_eq!(list[0], Value::symbol("f"));
+                assert_eq!(list[2], Value::Character('q'));
+            }
+            _ => panic!("lists become lists"),
+        }
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct Bytes(Vec<u8>);
+
+    impl HostObject for Bytes {
+        const TYPE_NAME: &'static str = "Bytes";
+    }
+
+    struct Other;
+
+    impl HostObject for Other {
+        const TYPE_NAME: &'static str = "Other";
+    }
+
+    #[test]
+    fn test_host() {
+        let host = Host::new(Bytes(vec![1u8, 2]));
+        let value = Value::Host(host.clone());
+        assert_eq!(host.downcast_ref::<Bytes>(), Some(&Bytes(vec![1, 2])));
+        assert!(host.downcast_ref::<Other>().is_none());
+        assert_eq!(value, Value::Host(host));
+        assert_ne!(value, Value::Host(Host::new(Bytes(vec![1u8, 2]))));
+        assert_eq!(
+            value.to_code(),
+            Err("<host Bytes> cannot be turned into code".to_string())
+        );
+        assert_eq!(
+            Value::List([value, Value::Integer(1)].into_iter().collect()).to_string(),
+            "<list>"
+        );
+    }
+
+    #[test]
+    fn test_check() {
+        assert_eq!(Value::Integer(3).check(&LampType::U8, "n"), Ok(()));
+        assert_eq!(
+            Value::Integer(300)
+                .check(&LampType::U8, "n")
+                .unwrap_err()
+                .to_string(),
+            "n: 300 does not fit in [u8]"
+        );
+        assert_eq!(Value::boolean(false).check(&LampType::Bool, "b"), Ok(()));
+        let list = Value::from_code(&Code::from_str("[1 2]").unwrap()[0]);
+        assert_eq!(
+            list.check(&LampType::Vector(Box::new(LampType::U64)), "l"),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_handle() {
+        let handle = Handle::new(Bytes(vec![3]));
+        let value = handle.to_value();
+        assert_eq!(handle.ref_count(), 2);
+        assert_eq!(value.check(&Bytes::lamp_type(), "b"), Ok(()));
+        assert_eq!(
+            value
+                .check(&Other::lamp_type(), "b")
+                .unwrap_err()
+                .to_string(),
+            "b: expected [Host Other] but found <host Bytes>"
+        );
+        assert_eq!(
+            value.check(&LampType::U8, "b").unwrap_err().to_string(),
+            "b: expected [u8] but found <host Bytes>"
+        );
+        // values are checked as they are so hosts inside collections are fine
+        let list = Value::List([value.clone(), value.clone()].into_iter().collect());
+        assert_eq!(
+            list.check(&LampType::Vector(Box::new(Bytes::lamp_type())), "l"),
+            Ok(())
+        );
+        assert_eq!(
+            list.check(&LampType::Vector(Box::new(LampType::U8)), "l")
+                .unwrap_err()
+                .to_string(),
+            "l[0]: expected [u8] but found <host Bytes>"
+        );
+        drop(list);
+
+        // the handle back out of the value is the same object
+        let back = Handle::<Bytes>::from_value(&value).unwrap();
+        assert_eq!(*back, Bytes(vec![3]));
+        assert_eq!(handle.ref_count(), 3);
+        drop(value);
+        drop(back);
+        assert_eq!(handle.ref_count(), 1);
+
+        assert_eq!(
+            Handle::<Other>::from_value(&handle.to_value())
+                .unwrap_err()
+                .to_string(),
+            "value: expected [Host Other] but found <host Bytes>"
+        );
+        assert!(Handle::<Bytes>::from_value(&Value::Integer(1)).is_err());
+
+        // handles inside tuples and lists keep their objects
+        let pair = (handle.clone(), vec![handle.clone()]).to_value();
+        let (one, many) = <(Handle<Bytes>, Vec<Handle<Bytes>>)>::from_value(&pair).unwrap();
+        assert_eq!(one.host().address(), many[0].host().address());
+    }
+}
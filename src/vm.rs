@@ -0,0 +1,429 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::compile::{Capture, Local, Op, Program, Proto};
+use crate::error::{Error, Kind};
+use crate::runtime::{
+    arguments, assign, define, lookup, nothing, truthy, Arg, Closure, Function, Runable, Runtime,
+};
+use crate::value::Value;
+
+// a variable shared between a frame and the closures made in it
+pub(crate) type Cell = Rc<RefCell<Value>>;
+
+// how a frame finished
+enum Exit {
+    Return(Value),
+    // the closure to run next with its checked arguments
+    TailCall(Rc<Closure>, Vec<Value>),
+}
+
+// pops the count of values off the stack keeping their order
+fn pop_n(stack: &mut Vec<Value>, count: u32) -> Vec<Value> {
+    stack.split_off(stack.len() - count as usize)
+}
+
+impl Runtime {
+    // runs a compiled program, what eval does for the code it was compiled from
+    pub fn exec(&mut self, program: &Program) -> Result<Value, Error> {
+        self.run(|runtime| runtime.execute(&program.proto, &[], Vec::new()))
+    }
+
+    // runs the proto with the arguments, captures are the cells of its closure
+    // tail calls come back here to run in place of the frame that made them
+    pub(crate) fn execute(
+        &mut self,
+        proto: &Proto,
+        captures: &[Cell],
+        args: Vec<Value>,
+    ) -> Result<Value, Error> {
+        let mut exit = self.frame(proto, captures, args)?;
+        loop {
+            match exit {
+                Exit::Return(value) => return Ok(value),
+                Exit::TailCall(closure, args) => {
+                    let Runable::Compiled(proto) = &closure.function.runable else {
+                        unreachable!("only compiled closures are tail called");
+                    };
+                    exit = self.frame(proto, &closure.captures, args)?;
+                }
+            }
+        }
+    }
+
+    // a compiled closure that returns the same type as the frame calling it can take its place
+    // anything else is called as usual
+    fn tail_call(
+        &mut self,
+        proto: &Proto,
+        name: &str,
+        func: &Value,
+        args: Vec<Value>,
+    ) -> Result<Exit, Error> {
+        if let Value::Closure(closure) = func {
+            let function = &closure.function;
+            if matches!(function.runable, Runable::Compiled(_)) && function.returns == proto.returns
+            {
+                self.cancel.check()?;
+                let args = arguments(name, function, args)?;
+                self.replace_frame(name);
+                return Ok(Exit::TailCall(closure.clone(), args));
+            }
+        }
+        self.call_value(name, func, args).map(Exit::Return)
+    }
+
+    fn frame(&mut self, proto: &Proto, captures: &[Cell], args: Vec<Value>) -> Result<Exit, Error> {
+        let mut slots = vec![nothing(); proto.slots];
+        let mut cells: Vec<Cell> = (0..proto.cells)
+            .map(|_| self.heap.cell(nothing()))
+            .collect();
+        for (param, value) in proto.params.iter().zip(args) {
+            match param.local {
+                Local::Slot(i) => slots[i as usize] = value,
+                Local::Cell(i) => *cells[i as usize].borrow_mut() = value,
+            }
+        }
+
+        let mut stack: Vec<Value> = Vec::new();
+        let mut pc = 0;
+        while let Some(&op) = proto.ops.get(pc) {
+            pc += 1;
+            if op.is_step() {
+                self.step()?;
+            }
+            if op.is_call() {
+                self.call_site = proto.locations[pc - 1];
+            }
+            match op {
+                Op::Constant(i) => stack.push(proto.constants[i as usize].clone()),
+                Op::Nothing => stack.push(nothing()),
+                Op::Pop => {
+                    stack.pop();
+                }
+                Op::LoadSlot(i) => stack.push(slots[i as usize].clone()),
+                Op::StoreSlot(i) => slots[i as usize] = stack.last().unwrap().clone(),
+                Op::LoadCell(i) => stack.push(cells[i as usize].borrow().clone()),
+                Op::StoreCell(i) => {
+                    *cells[i as usize].borrow_mut() = stack.last().unwrap().clone();
+                }
+                Op::FreshCells(first, count) => {
+                    for fresh in &mut cells[first as usize..(first + count) as usize] {
+                        *fresh = self.heap.cell(nothing());
+                    }
+                }
+                Op::LoadCapture(i) => stack.push(captures[i as usize].borrow().clone()),
+                Op::StoreCapture(i) => {
+                    *captures[i as usize].borrow_mut() = stack.last().unwrap().clone();
+                }
+                Op::LoadGlobal(i) => {
+                    let name = &proto.names[i as usize];
+                    let value =
+                        lookup(&self.globals, name).unwrap_or_else(|| match self.function(name) {
+                            Some((name, _)) => Value::BuiltIn(name.into()),
+                            None => Value::symbol(name),
+                        });
+                    stack.push(value);
+                }
+                Op::DefineGlobal(i) => {
+                    define(
+                        &self.globals,
+                        &proto.names[i as usize],
+                        stack.last().unwrap().clone(),
+                    );
+                }
+                Op::SetGlobal(i) => {
+                    let name = &proto.names[i as usize];
+                    if !assign(&self.globals, name, stack.last().unwrap().clone()) {
+                        return Err(Error::new(
+                            Kind::NameError,
+                            format!("Cannot set {} because it is not defined", name),
+                        ));
+                    }
+                }
+                Op::MakeList(count) => {
+                    let list = pop_n(&mut stack, count).into_iter().collect();
+                    stack.push(self.allocated(Value::List(list))?);
+                }
+                Op::MakeMap(count) => {
+                    let pairs = pop_n(&mut stack, count * 2);
+                    let mut pairs = pairs.into_iter();
+                    let mut map = crate::persistent::PMap::new();
+                    while let (Some(k), Some(v)) = (pairs.next(), pairs.next()) {
+                        map.insert(k, v);
+                    }
+                    stack.push(self.allocated(Value::Map(map))?);
+                }
+                Op::Jump(to) => pc = to as usize,
+                Op::IfFalse(to) => {
+                    if !truthy("if", stack.pop().unwrap())? {
+                        pc = to as usize;
+                    }
+                }
+                Op::WhileFalse(to) => {
+                    if !truthy("while", stack.pop().unwrap())? {
+                        pc = to as usize;
+                    }
+                }
+                Op::Loop(to) => {
+                    self.cancel.check()?;
+                    pc = to as usize;
+                }
+                Op::Call(name, argc) => {
+                    let args = pop_n(&mut stack, argc);
+                    let globals = self.globals.clone();
+                    stack.push(self.call_in(&proto.names[name as usize], args, &globals)?);
+                }
+                Op::CallValue(name, argc) => {
+                    let func = stack.pop().unwrap();
+                    let args = pop_n(&mut stack, argc);
+                    stack.push(self.call_value(&proto.names[name as usize], &func, args)?);
+                }
+                Op::TailCall(name, argc) => {
+                    let name = &proto.names[name as usize];
+                    let args = pop_n(&mut stack, argc);
+                    return match lookup(&self.globals, name) {
+                        Some(func) => self.tail_call(proto, name, &func, args),
+                        None => {
+                            let globals = self.globals.clone();
+                            self.call_in(name, args, &globals).map(Exit::Return)
+                        }
+                    };
+                }
+                Op::TailCallValue(name, argc) => {
+                    let func = stack.pop().unwrap();
+                    let args = pop_n(&mut stack, argc);
+                    return self.tail_call(proto, &proto.names[name as usize], &func, args);
+                }
+                Op::Try(handler, finally) => {
+                    let finally = if finally { stack.pop() } else { None };
+                    let handler = if handler { stack.pop() } else { None };
+                    let body = stack.pop().unwrap();
+                    stack.push(self.try_call(&body, handler.as_ref(), finally.as_ref())?);
+                }
+                Op::Closure(i, defaults) => {
+                    let inner = proto.protos[i as usize].clone();
+                    let mut defaults = pop_n(&mut stack, defaults).into_iter();
+                    let args = inner
+                        .params
+                        .iter()
+                        .map(|param| Arg {
+                            name: param.name.clone(),
+                            typ: param.typ.clone(),
+                            default: if param.has_default {
+                                defaults.next()
+                            } else {
+                                None
+                            },
+                        })
+                        .collect();
+                    let captured = inner
+                        .captures
+                        .iter()
+                        .map(|capture| match *capture {
+                            Capture::Cell(i) => cells[i as usize].clone(),
+                            Capture::Outer(i) => captures[i as usize].clone(),
+                        })
+                        .collect();
+                    stack.push(Value::Closure(Rc::new(Closure {
+                        function: Function {
+                            args,
+                            rest: None,
+                            returns: inner.returns.clone(),
+                            runable: Runable::Compiled(inner.clone()),
+                        },
+                        env: self.globals.clone(),
+                        code: inner.source.clone(),
+                        captures: captured,
+                    })));
+                }
+            }
+        }
+        Ok(Exit::Return(stack.pop().unwrap_or_else(nothing)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::code::Code;
+    use crate::compile::compile;
+
+    // runs the code with the tree walker and with the vm in fresh runtimes
+    // giving back what each top level expression came to
+    fn both(code: &str) -> (Vec<String>, Vec<String>) {
+        let code = Code::from_str(code).unwrap();
+        let mut tree = Runtime::new();
+        let mut vm = Runtime::new();
+        // collecting all the time finds anything the collector frees while it is still used
+        tree.set_gc_stress(true);
+        vm.set_gc_stress(true);
+        let tree_results = code
+            .iter()
+            .map(|expr| format!("{:?}", tree.interpret(expr).map_err(|e| e.to_string())));
+        let tree_results = tree_results.collect();
+        let vm_results = code.iter().map(|expr| {
+            format!(
+                "{:?}",
+                compile(expr).and_then(|program| vm.exec(&program).map_err(|e| e.to_string()))
+            )
+        });
+        (tree_results, vm_results.collect())
+    }
+
+    // how many of the top level expressions gave back values rather than errors
+    fn same(code: &str) -> usize {
+        let (tree, vm) = both(code);
+        assert_eq!(tree, vm, "for {}", code);
+        tree.iter()
+            .filter(|result| result.starts_with("Ok"))
+            .count()
+    }
+
+    #[test]
+    fn test_same_as_tree_walker() {
+        let programs = [
+            "[+ 1 2]",
+            "[* [- 10 4] [/ 9 2.0]]",
+            "[1 [2 x] {k: [+ 1 1]} \"s\" 'c']",
+            "undefined_name",
+            "[var x 5] [set x [* x x]] x",
+            "[set nope 1]",
+            "[pgm [var a 1] [var b [+ a 1]] [* a b]]",
+            "[var x 1] [pgm [var x 2] [set x 3]] x",
+            "{if c: [< 1 2] do: \"yes\" else: \"no\"}",
+            "{if c: false do: 1}",
+            "{if c: 1 do: 2}",
+            "[var i 0] [var sum 0] {while c: [< i 10] do: [[set sum [+ sum i]] [set i [+ i 1]]]} sum",
+            "{while c: 3}",
+            "[var n 0] {try do: [[set n 1] [raise n]] catch: [e] handle: [+ e n] finally: [set n 5]} n",
+            "{try do: [/ 1 0] catch: [e] handle: [is_error e arithmetic_error]}",
+            "[var f {fn a: [x] c: {try do: [g x] catch: [e] handle: e}}] [f 1]",
+            "{try do: [raise {k: 1}]} {try do: 1 handle: 2}",
+            "[var factorial {fn r: u64 a: [[n u64]] c: [
+                [var acc 1]
+                {while c: [greater_than n 1] do: [
+                    [set acc [multiply acc n]]
+                    [set n [plus n -1]]
+                ]}
+                acc
+            ]}] [factorial 20]",
+            "[var f {fn a: [[n u64]] c: n}] [f -1]",
+            "[var make {fn a: [start] c: [
+                [var n start]
+                {fn c: [[set n [+ n 1]] n]}
+            ]}] [var next [make 10]] [next] [next] [var other [make 0]] [other] [+ [next] [other]]",
+            "[var fib {fn a: [[n i64] [k i64 0]] c:
+                {if c: [< n 2] do: [+ n k] else: [+ [fib [- n 1]] [fib [- n 2]]]}}] [fib 15]",
+            "[var f {fn a: [x] c: x}] [f]",
+            "[var f {fn a: [x [y u8 [+ 1 2]]] c: [+ x y]}] [+ [f 1] [f 1 1]]",
+            "[var f {fn r: char c: 1}] [f]",
+            "[var x 1] [x 2]",
+            "[var f {fn c: 1}] f",
+            "plus",
+            "[var p plus] [p 2 3]",
+            // closures three deep share the outer variable
+            "[var outer {fn a: [n] c: [
+                {fn c: {fn c: [set n [* n 2]]}}
+            ]}] [var mid [outer 3]] [var inner [mid]] [inner] [inner]",
+            // local functions that call themselves and each other
+            "[var run {fn a: [n] c: [
+                [var even {fn a: [n] c: {if c: [= n 0] do: true else: [odd [- n 1]]}}]
+                [var odd {fn a: [n] c: {if c: [= n 0] do: false else: [even [- n 1]]}}]
+                [even n]
+            ]}] [run 7]",
+            // each run of a pgm is a new scope for the closures made in it
+            "[var fs []] [var i 0]
+             {while c: [< i 3] do: [
+                [pgm [var j i] [set fs [fs {fn c: j}]]]
+                [set i [+ i 1]]
+             ]}
+             fs",
+            "[var adders [pgm
+                [var base 100]
+                [var add {fn a: [x] c: [+ x base]}]
+                [set base 200]
+                add]] [adders 1]",
+            "[var g 1] [var f {fn c: g}] [set g 2] [f]",
+            "[var f {fn c: [var inner 5]}] [f] inner",
+            "[concat \"a\" \"b\" 'c']",
+            "[json_stringify {a: [1 2 3]}]",
+        ];
+        for program in programs {
+            same(program);
+        }
+    }
+
+    // a small random program generator for checking the two agree on many programs
+    struct Gen(u64);
+
+    impl Gen {
+        fn next(&mut self, bound: u64) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0 % bound
+        }
+
+        fn expr(&mut self, depth: u32) -> String {
+            let names = ["a", "b", "c"];
+            if depth == 0 {
+                return match self.next(3) {
+                    0 => names[self.next(3) as usize].to_string(),
+                    1 => format!("{}", self.next(20) as i64 - 5),
+                    _ => "[f 2]".to_string(),
+                };
+            }
+            let name = names[self.next(3) as usize];
+            match self.next(8) {
+                0 => format!(
+                    "[{} {} {}]",
+                    ["+", "-", "*"][self.next(3) as usize],
+                    self.expr(depth - 1),
+                    self.expr(depth - 1)
+                ),
+                1 => format!(
+                    "{{if c: [< {} {}] do: {} else: {}}}",
+                    self.expr(depth - 1),
+                    self.expr(depth - 1),
+                    self.expr(depth - 1),
+                    self.expr(depth - 1)
+                ),
+                2 => format!("[set {} {}]", name, self.expr(depth - 1)),
+                3 => format!(
+                    "[pgm [var {} {}] {}]",
+                    name,
+                    self.expr(depth - 1),
+                    self.expr(depth - 1)
+                ),
+                4 => format!(
+                    "[pgm [var n 0] {{while c: [< n 3] do: [[set {} [+ {} n]] [set n [+ n 1]]]}} {}]",
+                    name, name, name
+                ),
+                5 => format!(
+                    "[{{fn a: [{}] c: {}}} {}]",
+                    name,
+                    self.expr(depth - 1),
+                    self.expr(depth - 1)
+                ),
+                6 => format!("[{} {}]", self.expr(depth - 1), self.expr(depth - 1)),
+                _ => self.expr(depth - 1),
+            }
+        }
+    }
+
+    #[test]
+    fn test_random_programs() {
+        let mut gen = Gen(0x9e3779b97f4a7c15);
+        let mut values = 0;
+        for _ in 0..500 {
+            let program = format!(
+                "[var a 1] [var b 2] [var c 3] [var f {{fn a: [x] c: [+ x c]}}] {} [0 a b c]",
+                gen.expr(4)
+            );
+            values += same(&program) - 5;
+        }
+        // most programs should run rather than fail the same way in both
+        assert!(values > 200, "only {} programs ran", values);
+    }
+}